@@ -21,8 +21,7 @@ fn main() {
 
 			println!("Connection from {}", ip);
 
-			let message = OwnedMessage::Text("Hello".to_string());
-			client.send_message(&message).unwrap();
+			client.send_message(&OwnedMessage::from("Hello")).unwrap();
 
 			let (mut receiver, mut sender) = client.split().unwrap();
 
@@ -16,7 +16,14 @@ async-ssl
 async sync
 async sync-ssl
 sync async-ssl
-sync-ssl async-ssl"
+sync-ssl async-ssl
+server
+sync server
+async server
+sync-ssl server
+async-ssl server
+async sync server
+sync-ssl async-ssl server"
 
 while read FEATS; do
     if [[ ${INTERACTIVE:-} ]]; then
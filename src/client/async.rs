@@ -44,15 +44,23 @@
 //! # }
 //! ```
 
+use bytes::BytesMut;
+use futures::future::{self, Either};
 pub use futures::Future;
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
 use hyper::header::Headers;
+use std::io;
+use std::panic::{self, RefUnwindSafe};
+use std::time::{Duration, Instant};
 pub use tokio_codec::Framed;
 pub use tokio_reactor::Handle;
 pub use tokio_tcp::TcpStream;
+use tokio_timer::Delay;
 
 use crate::codec::ws::MessageCodec;
-use crate::message::OwnedMessage;
+use crate::message::{CloseData, OwnedMessage};
 use crate::result::WebSocketError;
+use crate::stream::r#async::Stream as AsyncStream;
 
 #[cfg(feature = "async-ssl")]
 pub use tokio_tls::TlsStream;
@@ -73,3 +81,296 @@ pub type Client<S> = Framed<S, MessageCodec<OwnedMessage>>;
 /// This crate will not automatically close the connection if the server refused
 /// to use the user protocols given to it, you must check that the server accepted.
 pub type ClientNew<S> = Box<dyn Future<Item = (Client<S>, Headers), Error = WebSocketError> + Send>;
+
+/// Conveniences for driving an asynchronous `Client`.
+pub trait ClientExt {
+	/// The stream the client runs on.
+	type Stream;
+
+	/// Call `handler` with every message received until the peer goes away,
+	/// resolving once the connection has ended.
+	///
+	/// A panic in `handler` is caught instead of tearing down the task the
+	/// client runs on: the connection is closed with status 1011 (internal
+	/// error) so that the peer learns what happened, and the future resolves.
+	/// The panic message is still printed by the panic hook.
+	fn for_each_message<F>(
+		self,
+		handler: F,
+	) -> Box<dyn Future<Item = (), Error = WebSocketError> + Send>
+	where
+		F: Fn(OwnedMessage) + RefUnwindSafe + Send + 'static;
+
+	/// Stop speaking websocket and get back the stream, along with the bytes
+	/// already read from it but not decoded yet, like the synchronous
+	/// `Client::into_stream`.
+	///
+	/// This is for connections that switch to another protocol afterwards,
+	/// whose first bytes may already be in the buffer. Messages that were
+	/// sent but not flushed yet are dropped.
+	fn into_stream(self) -> (Self::Stream, BytesMut);
+
+	/// Keep an idle connection in check: if nothing arrives for `ping_after`,
+	/// a ping is sent, and if nothing arrives within `close_after` of that
+	/// either, the connection is closed with status 1001 (Going Away) and the
+	/// stream fails with an `ErrorKind::TimedOut` I/O error once that close
+	/// message has been flushed.
+	///
+	/// Any message proves the connection alive, not just the pong, which is
+	/// passed on like any other message. The timer only runs while the stream
+	/// is polled, and needs the timer of a tokio runtime.
+	fn idle_timeout(self, ping_after: Duration, close_after: Duration)
+		-> IdleTimeout<Self::Stream>;
+}
+
+impl<S> ClientExt for Client<S>
+where
+	S: AsyncStream + Send + 'static,
+{
+	type Stream = S;
+
+	fn for_each_message<F>(
+		self,
+		handler: F,
+	) -> Box<dyn Future<Item = (), Error = WebSocketError> + Send>
+	where
+		F: Fn(OwnedMessage) + RefUnwindSafe + Send + 'static,
+	{
+		let (sink, stream) = self.split();
+		let future = stream
+			.map(move |message| panic::catch_unwind(|| handler(message)).is_err())
+			.skip_while(|panicked| Ok(!*panicked))
+			.into_future()
+			.map_err(|(e, _)| e)
+			.and_then(|(panicked, _)| match panicked {
+				Some(_) => {
+					let close = CloseData::new(1011, "Internal Error".to_string());
+					Either::A(sink.send(OwnedMessage::Close(Some(close))).map(|_| ()))
+				}
+				None => Either::B(future::ok(())),
+			});
+		Box::new(future)
+	}
+
+	fn into_stream(self) -> (S, BytesMut) {
+		let parts = self.into_parts();
+		(parts.io, parts.read_buf)
+	}
+
+	fn idle_timeout(self, ping_after: Duration, close_after: Duration) -> IdleTimeout<S> {
+		IdleTimeout {
+			client: self,
+			ping_after,
+			close_after,
+			timer: Delay::new(Instant::now() + ping_after),
+			awaiting_pong: false,
+			pending: None,
+			flushing: false,
+			timed_out: false,
+		}
+	}
+}
+
+/// A `Client` that pings the remote endpoint when the connection goes idle
+/// and closes it if that goes unanswered, see `ClientExt::idle_timeout`.
+///
+/// This is a `Stream` and `Sink` of `OwnedMessage`s just like the client.
+pub struct IdleTimeout<S> {
+	client: Client<S>,
+	ping_after: Duration,
+	close_after: Duration,
+	timer: Delay,
+	awaiting_pong: bool,
+	/// A ping or close waiting for room in the client's buffer
+	pending: Option<OwnedMessage>,
+	flushing: bool,
+	/// Set once the close has been queued, the stream fails when it's flushed
+	timed_out: bool,
+}
+
+impl<S> IdleTimeout<S>
+where
+	S: AsyncStream,
+{
+	/// Get back the client, without the idle timeout.
+	pub fn into_inner(self) -> Client<S> {
+		self.client
+	}
+
+	/// Send the pending ping or close as far as possible without blocking.
+	fn flush_pending(&mut self) -> Result<(), WebSocketError> {
+		if let Some(message) = self.pending.take() {
+			match self.client.start_send(message)? {
+				AsyncSink::Ready => self.flushing = true,
+				AsyncSink::NotReady(message) => self.pending = Some(message),
+			}
+		}
+		if self.flushing && self.client.poll_complete()?.is_ready() {
+			self.flushing = false;
+		}
+		Ok(())
+	}
+}
+
+impl<S> Stream for IdleTimeout<S>
+where
+	S: AsyncStream,
+{
+	type Item = OwnedMessage;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<OwnedMessage>, WebSocketError> {
+		self.flush_pending()?;
+		if self.timed_out {
+			if self.pending.is_some() || self.flushing {
+				return Ok(Async::NotReady);
+			}
+			return Err(io::Error::new(io::ErrorKind::TimedOut, "No pong received in time").into());
+		}
+		if let Async::Ready(message) = self.client.poll()? {
+			self.awaiting_pong = false;
+			self.timer.reset(Instant::now() + self.ping_after);
+			return Ok(Async::Ready(message));
+		}
+
+		while self
+			.timer
+			.poll()
+			.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+			.is_ready()
+		{
+			if self.awaiting_pong {
+				let close = CloseData::new(1001, "Idle timeout".to_string());
+				self.pending = Some(OwnedMessage::Close(Some(close)));
+				self.timed_out = true;
+				return self.poll();
+			}
+			self.awaiting_pong = true;
+			self.pending = Some(OwnedMessage::Ping(Vec::new()));
+			self.flush_pending()?;
+			self.timer.reset(Instant::now() + self.close_after);
+		}
+		Ok(Async::NotReady)
+	}
+}
+
+impl<S> Sink for IdleTimeout<S>
+where
+	S: AsyncStream,
+{
+	type SinkItem = OwnedMessage;
+	type SinkError = WebSocketError;
+
+	fn start_send(&mut self, item: OwnedMessage) -> StartSend<OwnedMessage, WebSocketError> {
+		self.flush_pending()?;
+		if self.pending.is_some() {
+			return Ok(AsyncSink::NotReady(item));
+		}
+		self.client.start_send(item)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+		self.flush_pending()?;
+		if self.pending.is_some() {
+			return Ok(Async::NotReady);
+		}
+		self.client.poll_complete()
+	}
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+	use super::*;
+	use crate::codec::ws::Context;
+	use crate::message::Message;
+	use hyper::buffer::BufReader;
+	use std::sync::{Arc, Mutex};
+	use tokio::runtime::Runtime;
+
+	#[test]
+	fn for_each_message_closes_on_panic() {
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let peer = std::thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let mut peer =
+				crate::sync::Client::unchecked(BufReader::new(stream), Headers::new(), false, true);
+			peer.send_message(&Message::text("fine")).unwrap();
+			peer.send_message(&Message::text("boom")).unwrap();
+			peer.recv_message().unwrap()
+		});
+
+		let seen = Arc::new(Mutex::new(Vec::new()));
+		let record = seen.clone();
+		let future = TcpStream::connect(&addr)
+			.map_err(WebSocketError::from)
+			.and_then(move |stream| {
+				Framed::new(stream, MessageCodec::default(Context::Client)).for_each_message(
+					move |message| {
+						if message == OwnedMessage::Text("boom".to_string()) {
+							panic!("handler failed");
+						}
+						record.lock().unwrap().push(message);
+					},
+				)
+			});
+		Runtime::new().unwrap().block_on(future).unwrap();
+
+		assert_eq!(
+			*seen.lock().unwrap(),
+			vec![OwnedMessage::Text("fine".to_string())]
+		);
+		match peer.join().unwrap() {
+			OwnedMessage::Close(Some(close)) => assert_eq!(close.status_code, 1011),
+			other => panic!("expected a close, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn idle_timeout_pings_then_closes() {
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let peer = std::thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let mut peer =
+				crate::sync::Client::unchecked(BufReader::new(stream), Headers::new(), false, true);
+			(peer.recv_message().unwrap(), peer.recv_message().unwrap())
+		});
+
+		let future = TcpStream::connect(&addr)
+			.map_err(WebSocketError::from)
+			.and_then(|stream| {
+				Framed::new(stream, MessageCodec::default(Context::Client))
+					.idle_timeout(Duration::from_millis(50), Duration::from_millis(50))
+					.into_future()
+					.map_err(|(e, _)| e)
+			});
+		match Runtime::new().unwrap().block_on(future) {
+			Err(WebSocketError::IoError(e)) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+			Err(e) => panic!("expected a timeout, got {:?}", e),
+			Ok(_) => panic!("expected a timeout"),
+		}
+
+		let (ping, close) = peer.join().unwrap();
+		assert_eq!(ping, OwnedMessage::Ping(Vec::new()));
+		match close {
+			OwnedMessage::Close(Some(close)) => assert_eq!(close.status_code, 1001),
+			other => panic!("expected a close, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn into_stream_keeps_undecoded_bytes() {
+		use crate::stream::ReadWritePair;
+		use std::io::Cursor;
+
+		let incoming = vec![0x81, 0x02, b'h', b'i', b'r', b'a', b'w'];
+		let stream = ReadWritePair(Cursor::new(incoming), Cursor::new(Vec::new()));
+		let client = Framed::new(stream, MessageCodec::default(Context::Client));
+		let (message, client) = client.into_future().wait().map_err(|e| e.0).unwrap();
+		assert_eq!(message, Some(OwnedMessage::Text("hi".to_string())));
+
+		let (_stream, buffered) = client.into_stream();
+		assert_eq!(&buffered[..], b"raw");
+	}
+}
@@ -2,12 +2,19 @@
 
 use crate::header::extensions::Extension;
 use crate::header::{
-	Origin, WebSocketExtensions, WebSocketKey, WebSocketProtocol, WebSocketVersion,
+	Origin, WebSocketAccept, WebSocketExtensions, WebSocketKey, WebSocketProtocol, WebSocketVersion,
 };
-use hyper::header::{Authorization, Basic, Header, HeaderFormat, Headers};
+use crate::observer::Observer;
+use crate::result::{HandshakeResponseError, ProtocolErrorPolicy, WebSocketOtherError};
+use hyper::header::{Authorization, Basic, Bearer, Header, HeaderFormat, Headers};
 use hyper::version::HttpVersion;
 use std::borrow::Cow;
 use std::convert::Into;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 pub use url::{ParseError, Url};
 
 const DEFAULT_MAX_DATAFRAME_SIZE : usize = 1024*1024*100;
@@ -15,8 +22,7 @@ const DEFAULT_MAX_MESSAGE_SIZE : usize = 1024*1024*200;
 
 #[cfg(any(feature = "sync", feature = "async"))]
 mod common_imports {
-	pub use crate::header::WebSocketAccept;
-	pub use crate::result::{WSUrlErrorKind, WebSocketError, WebSocketOtherError, WebSocketResult};
+	pub use crate::result::{WSUrlErrorKind, WebSocketError, WebSocketResult};
 	pub use crate::stream::{self, Stream};
 	pub use hyper::buffer::BufReader;
 	pub use hyper::header::{Connection, ConnectionOption, Host, Protocol, ProtocolName, Upgrade};
@@ -36,6 +42,16 @@ use self::common_imports::*;
 
 #[cfg(feature = "sync")]
 use super::sync::Client;
+#[cfg(feature = "sync")]
+use crate::result::handshake_snippet;
+#[cfg(feature = "sync")]
+use crate::stream::sync::AsTcpStream;
+#[cfg(all(feature = "tcp-buffers", any(feature = "sync", feature = "async")))]
+use socket2::SockRef;
+#[cfg(all(feature = "bind-local", any(feature = "sync", feature = "async")))]
+use socket2::{Domain, Socket, Type};
+#[cfg(feature = "sync")]
+use std::time::Instant;
 
 #[cfg(feature = "sync-ssl")]
 use crate::stream::sync::NetworkStream;
@@ -48,6 +64,8 @@ use native_tls::TlsStream;
 #[cfg(feature = "async")]
 mod async_imports {
 	pub use super::super::r#async;
+	pub(crate) use crate::codec::http::CappedHttpClientCodec;
+	pub use crate::codec::http::HttpCodecError;
 	pub use crate::codec::ws::{Context, MessageCodec};
 	pub use crate::ws::util::update_framed_codec;
 	pub use futures::future;
@@ -57,12 +75,14 @@ mod async_imports {
 	pub use tokio_codec::{Decoder, Framed};
 	pub use tokio_reactor::Handle;
 	pub use tokio_tcp::TcpStream as TcpStreamNew;
+	pub use tokio_timer::Timeout;
 	#[cfg(feature = "async-ssl")]
 	pub use tokio_tls::TlsConnector as TlsConnectorExt;
 }
 #[cfg(feature = "async")]
 use self::async_imports::*;
 
+#[cfg(any(feature = "sync", feature = "async"))]
 use crate::result::towse;
 
 /// Build clients with a builder-style API
@@ -119,6 +139,141 @@ pub struct ClientBuilder<'u> {
 	key_set: bool,
 	max_dataframe_size: usize,
 	max_message_size: usize,
+	auto_fragment: Option<usize>,
+	socket_addr: Option<SocketAddr>,
+	alpn_protocols: Vec<String>,
+	key_source: Option<KeySource>,
+	omit_host_port: bool,
+	protocols_single_header: bool,
+	swallow_pongs: bool,
+	close_on_drop: bool,
+	pong_callback: Option<PongCallback>,
+	auto_pong_rate_limit: Option<u32>,
+	protocol_error_policy: ProtocolErrorPolicy,
+	resolver: Option<ResolverHandle>,
+	observer: Option<ObserverHandle>,
+	handshake_timeout: Option<Duration>,
+	max_response_header_bytes: Option<usize>,
+	#[cfg_attr(not(feature = "tcp-buffers"), allow(dead_code))]
+	tcp_buffer_sizes: (Option<usize>, Option<usize>),
+	#[cfg_attr(not(feature = "bind-local"), allow(dead_code))]
+	local_addr: Option<SocketAddr>,
+}
+
+/// Generates the bytes of the `Sec-WebSocket-Key` header, see `ClientBuilder::key_from`.
+#[derive(Clone)]
+#[cfg_attr(not(any(feature = "sync", feature = "async")), allow(dead_code))]
+struct KeySource(Arc<dyn Fn() -> [u8; 16] + Send + Sync>);
+
+impl fmt::Debug for KeySource {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("KeySource")
+	}
+}
+
+/// Checks that the `Sec-WebSocket-Accept` header of a handshake response
+/// matches the `Sec-WebSocket-Key` that was sent in the request.
+///
+/// This is the check clients built with `ClientBuilder` do, exposed for
+/// libraries that perform the handshake themselves.
+///
+/// ```rust
+/// # extern crate hyper;
+/// # extern crate websocket;
+/// # fn main() {
+/// use hyper::header::Headers;
+/// use websocket::client::builder::verify_accept;
+/// use websocket::header::{WebSocketAccept, WebSocketKey};
+///
+/// let key = WebSocketKey::new();
+/// let mut headers = Headers::new();
+/// assert!(verify_accept(&key, &headers).is_err());
+///
+/// headers.set(WebSocketAccept::new(&key));
+/// assert!(verify_accept(&key, &headers).is_ok());
+/// # }
+/// ```
+pub fn verify_accept(
+	key: &WebSocketKey,
+	response_headers: &Headers,
+) -> Result<(), WebSocketOtherError> {
+	if !response_headers.has::<WebSocketAccept>() {
+		return Err(WebSocketOtherError::HandshakeResponse(
+			HandshakeResponseError::MissingAccept,
+		));
+	}
+	if response_headers.get() != Some(&WebSocketAccept::new(key)) {
+		return Err(WebSocketOtherError::HandshakeResponse(
+			HandshakeResponseError::BadAccept,
+		));
+	}
+	Ok(())
+}
+
+/// Where a `ClientBuilder` will connect to, see `ClientBuilder::target`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WsTarget {
+	/// The host name or IP address, as it appears in the URL.
+	pub host: String,
+	/// The port, the scheme's default one if the URL has none.
+	pub port: u16,
+	/// Whether the connection is wrapped in TLS.
+	pub secure: bool,
+	/// The path and query that are requested in the handshake.
+	pub resource: String,
+}
+
+/// Turns host names into socket addresses when a client connects,
+/// see `ClientBuilder::resolver`.
+///
+/// Implement this to look hosts up through service discovery or a DNS
+/// resolver of your own instead of the system's.
+pub trait Resolver: Send + Sync {
+	/// All addresses `host` can be reached at on `port`, to be tried in order.
+	fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// The system's resolver, as used by `std::net::ToSocketAddrs`.
+/// This is what clients use unless told otherwise.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+	fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+		Ok(std::net::ToSocketAddrs::to_socket_addrs(&(host, port))?.collect())
+	}
+}
+
+#[derive(Clone)]
+#[cfg_attr(not(any(feature = "sync", feature = "async")), allow(dead_code))]
+struct ResolverHandle(Arc<dyn Resolver>);
+
+impl fmt::Debug for ResolverHandle {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("Resolver")
+	}
+}
+
+/// Observes swallowed pongs, see `ClientBuilder::on_pong`.
+#[derive(Clone)]
+#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+struct PongCallback(Arc<dyn Fn(&[u8]) + Send + Sync>);
+
+impl fmt::Debug for PongCallback {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("PongCallback")
+	}
+}
+
+/// Watches the synchronous client, see `ClientBuilder::observer`.
+#[derive(Clone)]
+#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+struct ObserverHandle(Arc<dyn Observer>);
+
+impl fmt::Debug for ObserverHandle {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("Observer")
+	}
 }
 
 impl<'u> ClientBuilder<'u> {
@@ -168,6 +323,23 @@ impl<'u> ClientBuilder<'u> {
 			headers: Headers::new(),
 			max_dataframe_size: DEFAULT_MAX_DATAFRAME_SIZE,
 			max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+			auto_fragment: None,
+			socket_addr: None,
+			alpn_protocols: Vec::new(),
+			key_source: None,
+			omit_host_port: false,
+			protocols_single_header: true,
+			swallow_pongs: false,
+			close_on_drop: false,
+			pong_callback: None,
+			auto_pong_rate_limit: None,
+			protocol_error_policy: ProtocolErrorPolicy::default(),
+			resolver: None,
+			observer: None,
+			handshake_timeout: None,
+			max_response_header_bytes: None,
+			tcp_buffer_sizes: (None, None),
+			local_addr: None,
 		}
 	}
 
@@ -227,6 +399,23 @@ impl<'u> ClientBuilder<'u> {
 		self
 	}
 
+	/// Whether the protocols are sent as a single comma separated
+	/// `Sec-WebSocket-Protocol` header (the default, as recommended by RFC6455)
+	/// or as one header line per protocol.
+	///
+	/// Only turn this off for servers that reject the folded form.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://legacy.example.com").unwrap()
+	///     .add_protocols(vec!["pubsub", "sub.events"])
+	///     .protocols_single_header(false);
+	/// ```
+	pub fn protocols_single_header(mut self, single: bool) -> Self {
+		self.protocols_single_header = single;
+		self
+	}
+
 	/// Adds an extension to the connection.
 	/// Unlike protocols, extensions can be below the application level
 	/// (like compression). Currently no extensions are supported
@@ -311,6 +500,227 @@ impl<'u> ClientBuilder<'u> {
 		self
 	}
 
+	/// Automatically split outgoing messages whose payload is larger than `max_frame`
+	/// bytes into several fragments. This is useful when some intermediaries choke
+	/// on very large frames. Control messages are never fragmented.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://big.files").unwrap()
+	///     .auto_fragment(64 * 1024);
+	/// ```
+	pub fn auto_fragment(mut self, max_frame: usize) -> Self {
+		self.auto_fragment = Some(max_frame);
+		self
+	}
+
+	/// Have the synchronous client's `recv_message` skip pong messages, which
+	/// peers may send unsolicited, so application loops only see data, ping
+	/// and close messages. Use `on_pong` to still observe them.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://localhost:3000").unwrap()
+	///     .swallow_pongs(true);
+	/// ```
+	pub fn swallow_pongs(mut self, swallow: bool) -> Self {
+		self.swallow_pongs = swallow;
+		self
+	}
+
+	/// Have the synchronous client send a close message with 1001 (Going
+	/// Away) when it is dropped without having closed the connection, so the
+	/// server doesn't see it as an abnormal closure.
+	///
+	/// This is off by default since it makes dropping the client write to
+	/// the stream, and it doesn't apply to `connect_on`, whose stream might
+	/// not be a socket. See `Client::set_close_on_drop` for the details.
+	pub fn close_on_drop(mut self, close: bool) -> Self {
+		self.close_on_drop = close;
+		self
+	}
+
+	/// Call `callback` with the payload of every pong the synchronous client
+	/// skips because of `swallow_pongs`, e.g. to keep track of a heartbeat.
+	pub fn on_pong<F>(mut self, callback: F) -> Self
+	where
+		F: Fn(&[u8]) + Send + Sync + 'static,
+	{
+		self.pong_callback = Some(PongCallback(Arc::new(callback)));
+		self
+	}
+
+	/// Have the synchronous client's `recv_message` answer pings itself,
+	/// but no more than `max_per_sec` of them per second.
+	///
+	/// Pings beyond that rate are dropped without an answer, so a peer
+	/// flooding the connection with pings can't make the client send just as
+	/// many pongs back. Answered or not, pings no longer reach the application.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://localhost:3000").unwrap()
+	///     .auto_pong_rate_limit(10);
+	/// ```
+	pub fn auto_pong_rate_limit(mut self, max_per_sec: u32) -> Self {
+		self.auto_pong_rate_limit = Some(max_per_sec);
+		self
+	}
+
+	/// Choose the close message the synchronous client sends when the server
+	/// violates the protocol, see `Client::set_protocol_error_policy`.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// use websocket::result::ProtocolErrorPolicy;
+	///
+	/// let builder = ClientBuilder::new("ws://localhost:3000").unwrap()
+	///     .protocol_error_policy(ProtocolErrorPolicy::Always(1011));
+	/// ```
+	pub fn protocol_error_policy(mut self, policy: ProtocolErrorPolicy) -> Self {
+		self.protocol_error_policy = policy;
+		self
+	}
+
+	/// Resolve the host of the URL with `resolver` instead of the system's resolver.
+	/// IP addresses in the URL are used as they are.
+	///
+	/// ```rust
+	/// # use std::io;
+	/// # use std::net::SocketAddr;
+	/// # use std::sync::Arc;
+	/// use websocket::client::builder::Resolver;
+	/// # use websocket::ClientBuilder;
+	///
+	/// struct Fixed(SocketAddr);
+	///
+	/// impl Resolver for Fixed {
+	///     fn resolve(&self, _host: &str, _port: u16) -> io::Result<Vec<SocketAddr>> {
+	///         Ok(vec![self.0])
+	///     }
+	/// }
+	///
+	/// let builder = ClientBuilder::new("ws://chat.service").unwrap()
+	///     .resolver(Arc::new(Fixed("10.0.0.7:8080".parse().unwrap())));
+	/// ```
+	pub fn resolver(mut self, resolver: Arc<dyn Resolver>) -> Self {
+		self.resolver = Some(ResolverHandle(resolver));
+		self
+	}
+
+	/// Attach `observer` to the synchronous client, starting with its
+	/// `handshake_complete`. See the `observer` module and
+	/// `Client::set_observer`.
+	pub fn observer(mut self, observer: Arc<dyn Observer>) -> Self {
+		self.observer = Some(ObserverHandle(observer));
+		self
+	}
+
+	/// Give up on the handshake if the server hasn't answered it with a valid
+	/// `101 Switching Protocols` within `timeout` of the connection being
+	/// established (TLS included), failing with
+	/// `WebSocketOtherError::HandshakeTimeout`.
+	///
+	/// The synchronous client enforces this with the socket's read and write
+	/// timeouts, so it applies to `connect`, `connect_insecure` and
+	/// `connect_secure` but not to `connect_on`. The asynchronous client
+	/// needs a `tokio` timer to be running.
+	///
+	/// ```rust
+	/// # use std::time::Duration;
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://slow.server").unwrap()
+	///     .handshake_timeout(Duration::from_secs(5));
+	/// ```
+	pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+		self.handshake_timeout = Some(timeout);
+		self
+	}
+
+	/// Fail the handshake if the server's response takes more than `max`
+	/// bytes before its headers end, instead of buffering whatever the server
+	/// sends until hyper's own limit of a few hundred kilobytes is reached.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://untrusted.example").unwrap()
+	///     .max_response_header_bytes(16 * 1024);
+	/// ```
+	pub fn max_response_header_bytes(mut self, max: usize) -> Self {
+		self.max_response_header_bytes = Some(max);
+		self
+	}
+
+	/// Set the size of the socket's send and receive buffers once the TCP
+	/// connection is established, leaving the system's defaults for `None`.
+	///
+	/// Larger buffers can improve throughput for big messages. The operating
+	/// system may round or cap the sizes. Only available with the
+	/// `tcp-buffers` feature.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://bulk.transfer").unwrap()
+	///     .tcp_buffer_sizes(Some(1 << 20), Some(1 << 20));
+	/// ```
+	#[cfg(feature = "tcp-buffers")]
+	pub fn tcp_buffer_sizes(mut self, send: Option<usize>, recv: Option<usize>) -> Self {
+		self.tcp_buffer_sizes = (send, recv);
+		self
+	}
+
+	/// Bind the client's socket to the local address `addr` before connecting,
+	/// e.g. to pick the network interface or source IP on a multi-homed host.
+	/// A port of 0 lets the system choose one.
+	///
+	/// Only addresses of the same family as `addr` can be connected to. Only
+	/// available with the `bind-local` feature.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://example.com").unwrap()
+	///     .bind_local("192.0.2.10:0".parse().unwrap());
+	/// ```
+	#[cfg(feature = "bind-local")]
+	pub fn bind_local(mut self, addr: SocketAddr) -> Self {
+		self.local_addr = Some(addr);
+		self
+	}
+
+	/// Open the TCP connection to `addr` instead of resolving the host of the URL.
+	///
+	/// The URL's host is still used for the `Host` header and for TLS,
+	/// which is useful to reach a specific backend behind a virtual host.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("wss://example.com").unwrap()
+	///     .connect_to_addr("127.0.0.1:8443".parse().unwrap());
+	/// ```
+	pub fn connect_to_addr(mut self, addr: SocketAddr) -> Self {
+		self.socket_addr = Some(addr);
+		self
+	}
+
+	/// Sets the protocols to offer with ALPN during the TLS handshake.
+	///
+	/// This only applies when no custom `TlsConnector` is passed when connecting,
+	/// configure the ALPN protocols on the connector yourself otherwise.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("wss://example.com").unwrap()
+	///     .request_alpns(vec!["http/1.1"]);
+	/// ```
+	pub fn request_alpns<I, S>(mut self, protocols: I) -> Self
+	where
+		I: IntoIterator<Item = S>,
+		S: Into<String>,
+	{
+		self.alpn_protocols = protocols.into_iter().map(Into::into).collect();
+		self
+	}
+
 	/// Add a custom `Sec-WebSocket-Key` header.
 	/// Use this only if you know what you're doing, and this almost
 	/// never has to be used.
@@ -320,6 +730,41 @@ impl<'u> ClientBuilder<'u> {
 		self
 	}
 
+	/// Generate the `Sec-WebSocket-Key` header with `f` each time a handshake
+	/// request is built, instead of using random bytes.
+	/// Useful for reproducible handshakes in tests, or to use another source
+	/// of randomness. This replaces any key set with `key`.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://localhost:3000").unwrap()
+	///     .key_from(|| *b"the sample nonce");
+	/// ```
+	pub fn key_from<F>(mut self, f: F) -> Self
+	where
+		F: Fn() -> [u8; 16] + Send + Sync + 'static,
+	{
+		self.headers.remove::<WebSocketKey>();
+		self.key_set = false;
+		self.key_source = Some(KeySource(Arc::new(f)));
+		self
+	}
+
+	/// Send only the hostname in the `Host` header, even if the URL has a
+	/// non-default port.
+	///
+	/// Some reverse proxies reject a `Host` header with an explicit port.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("ws://localhost:3000").unwrap()
+	///     .omit_host_port(true);
+	/// ```
+	pub fn omit_host_port(mut self, omit: bool) -> Self {
+		self.omit_host_port = omit;
+		self
+	}
+
 	/// Remove the currently set `Sec-WebSocket-Key` header if any.
 	pub fn clear_key(mut self) -> Self {
 		self.headers.remove::<WebSocketKey>();
@@ -359,6 +804,41 @@ impl<'u> ClientBuilder<'u> {
 		self
 	}
 
+	/// Authenticate the handshake with a bearer token, sent as
+	/// `Authorization: Bearer <token>`.
+	///
+	/// This replaces any Authorization header set before, but credentials in
+	/// the URL (like `ws://user:pass@host`) take precedence over it.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// # use websocket::header::{Authorization, Bearer};
+	/// let builder = ClientBuilder::new("ws://api.example").unwrap()
+	///     .bearer_token("s3cr3t");
+	///
+	/// # let auth = builder.get_header::<Authorization<Bearer>>().unwrap();
+	/// # assert_eq!(auth.0.token, "s3cr3t");
+	/// ```
+	pub fn bearer_token(mut self, token: &str) -> Self {
+		self.headers.set(Authorization(Bearer {
+			token: token.to_owned(),
+		}));
+		self
+	}
+
+	/// Authenticate the handshake with HTTP Basic authentication, like
+	/// credentials in the URL do, taking care of the base64 encoding.
+	///
+	/// This replaces any Authorization header set before, but credentials in
+	/// the URL take precedence over it.
+	pub fn basic_auth(mut self, user: &str, pass: Option<&str>) -> Self {
+		self.headers.set(Authorization(Basic {
+			username: user.to_owned(),
+			password: pass.map(str::to_owned),
+		}));
+		self
+	}
+
 	/// This is a catch all to add random headers to your handshake,
 	/// the process here is more manual.
 	///
@@ -397,6 +877,67 @@ impl<'u> ClientBuilder<'u> {
 		self.headers.get::<H>()
 	}
 
+	/// The host, port and resource the builder will connect to, and whether
+	/// it will use TLS, worked out just like `connect` does.
+	///
+	/// Useful as a key for pools of connections.
+	///
+	/// ```rust
+	/// # use websocket::ClientBuilder;
+	/// let builder = ClientBuilder::new("wss://example.com/chat?room=1").unwrap();
+	/// let target = builder.target().unwrap();
+	///
+	/// assert_eq!(target.host, "example.com");
+	/// assert_eq!(target.port, 443);
+	/// assert!(target.secure);
+	/// assert_eq!(target.resource, "/chat?room=1");
+	/// ```
+	#[cfg(any(feature = "sync", feature = "async"))]
+	pub fn target(&self) -> WebSocketResult<WsTarget> {
+		let host_port = self.extract_host_port(None)?;
+		Ok(WsTarget {
+			host: host_port.host.to_string(),
+			port: host_port.port,
+			secure: self.is_secure_url(),
+			resource: self.url[Position::BeforePath..Position::AfterQuery].to_owned(),
+		})
+	}
+
+	/// Build the handshake request once, to connect many clients with it.
+	///
+	/// Every connection of the resulting `FrozenClientBuilder` sends the same
+	/// request bytes, only the `Sec-WebSocket-Key` is generated anew (unless
+	/// one was set with `key`). This saves formatting the headers over and
+	/// over when e.g. a connection pool dials the same endpoint a lot.
+	///
+	/// ```rust,no_run
+	/// # use websocket::ClientBuilder;
+	/// let frozen = ClientBuilder::new("ws://localhost:3000").unwrap()
+	///     .add_protocol("rust-websocket")
+	///     .freeze();
+	///
+	/// let clients: Vec<_> = (0..10)
+	///     .map(|_| frozen.connect_insecure().unwrap())
+	///     .collect();
+	/// ```
+	#[cfg(any(feature = "sync", feature = "async"))]
+	pub fn freeze(mut self) -> FrozenClientBuilder<'u> {
+		let resource = self.build_request();
+		let key = if self.key_set {
+			self.headers.get::<WebSocketKey>().cloned()
+		} else {
+			None
+		};
+		self.headers.remove::<WebSocketKey>();
+		let request = format!("GET {} {}\r\n{}", resource, self.version, self.headers);
+		self.headers.clear();
+		FrozenClientBuilder {
+			builder: self,
+			request: request.into_bytes().into(),
+			key,
+		}
+	}
+
 	/// Connect to a server (finally)!
 	/// This will use a `Box<NetworkStream>` to represent either an SSL
 	/// connection or a normal TCP connection, what to use will be decided
@@ -422,14 +963,19 @@ impl<'u> ClientBuilder<'u> {
 		ssl_config: Option<TlsConnector>,
 	) -> WebSocketResult<Client<Box<dyn NetworkStream + Send>>> {
 		let tcp_stream = self.establish_tcp(None)?;
+		let started = self.arm_handshake_timeout(&tcp_stream)?;
 
 		let boxed_stream: Box<dyn NetworkStream + Send> = if self.is_secure_url() {
-			Box::new(self.wrap_ssl(tcp_stream, ssl_config)?)
+			Box::new(
+				self.wrap_ssl(tcp_stream, ssl_config)
+					.map_err(|e| self.handshake_error(e, started))?,
+			)
 		} else {
 			Box::new(tcp_stream)
 		};
 
-		self.connect_on(boxed_stream)
+		let (request, key) = self.request()?;
+		self.connect_within(boxed_stream, started, &request, &key)
 	}
 
 	/// Create an insecure (plain TCP) connection to the client.
@@ -449,8 +995,10 @@ impl<'u> ClientBuilder<'u> {
 	#[cfg(feature = "sync")]
 	pub fn connect_insecure(&mut self) -> WebSocketResult<Client<TcpStream>> {
 		let tcp_stream = self.establish_tcp(Some(false))?;
+		let started = self.arm_handshake_timeout(&tcp_stream)?;
 
-		self.connect_on(tcp_stream)
+		let (request, key) = self.request()?;
+		self.connect_within(tcp_stream, started, &request, &key)
 	}
 
 	/// Create an SSL connection to the sever.
@@ -463,10 +1011,14 @@ impl<'u> ClientBuilder<'u> {
 		ssl_config: Option<TlsConnector>,
 	) -> WebSocketResult<Client<TlsStream<TcpStream>>> {
 		let tcp_stream = self.establish_tcp(Some(true))?;
+		let started = self.arm_handshake_timeout(&tcp_stream)?;
 
-		let ssl_stream = self.wrap_ssl(tcp_stream, ssl_config)?;
+		let ssl_stream = self
+			.wrap_ssl(tcp_stream, ssl_config)
+			.map_err(|e| self.handshake_error(e, started))?;
 
-		self.connect_on(ssl_stream)
+		let (request, key) = self.request()?;
+		self.connect_within(ssl_stream, started, &request, &key)
 	}
 
 	/// Connects to a websocket server on any stream you would like.
@@ -474,6 +1026,7 @@ impl<'u> ClientBuilder<'u> {
 	///  - Unix Sockets
 	///  - Logging Middle-ware
 	///  - SSH
+	///  - TLS streams set up elsewhere
 	///
 	/// ```rust
 	/// # use websocket::ClientBuilder;
@@ -498,24 +1051,106 @@ impl<'u> ClientBuilder<'u> {
 	/// let text = String::from_utf8(text).unwrap();
 	/// assert!(text.contains("dGhlIHNhbXBsZSBub25jZQ=="), "{}", text);
 	/// ```
+	///
+	/// Frames the server sends right behind its response, even in the same
+	/// read, are kept for the client: the first `recv_message` returns them.
+	///
+	/// Only the websocket handshake is done here, so a TLS session that was
+	/// already established (e.g. with a custom certificate check) can be
+	/// passed on as it is. The client can't be split, but it still reaches
+	/// the `TcpStream` underneath for options and `shutdown`:
+	///
+	/// ```rust,no_run
+	/// # extern crate websocket;
+	/// # use websocket::ClientBuilder;
+	/// use std::net::TcpStream;
+	/// use websocket::native_tls::TlsConnector;
+	///
+	/// # fn main() {
+	/// let tcp = TcpStream::connect("example.com:443").unwrap();
+	/// let tls = TlsConnector::new().unwrap().connect("example.com", tcp).unwrap();
+	///
+	/// let mut client = ClientBuilder::new("wss://example.com/chat").unwrap()
+	///     .connect_on(tls)
+	///     .unwrap();
+	/// client.set_nodelay(true).unwrap();
+	/// client.shutdown().unwrap();
+	/// # }
+	/// ```
 	#[cfg(feature = "sync")]
-	pub fn connect_on<S>(&mut self, mut stream: S) -> WebSocketResult<Client<S>>
+	pub fn connect_on<S>(&mut self, stream: S) -> WebSocketResult<Client<S>>
 	where
 		S: Stream,
 	{
-		// send request
+		let (request, key) = self.request()?;
+		self.handshake(stream, &request, &key)
+	}
+
+	/// The bytes of the handshake request, along with the key sent in it.
+	#[cfg(feature = "sync")]
+	fn request(&mut self) -> WebSocketResult<(Vec<u8>, WebSocketKey)> {
 		let resource = self.build_request();
 		let data = format!("GET {} {}\r\n{}\r\n", resource, self.version, self.headers);
-		stream.write_all(data.as_bytes())?;
+		Ok((data.into_bytes(), self.request_key()?))
+	}
+
+	/// Sends the handshake `request` and reads the response to it, which has
+	/// to accept `key`, then sets the client up.
+	#[cfg(feature = "sync")]
+	fn handshake<S>(
+		&self,
+		mut stream: S,
+		request: &[u8],
+		key: &WebSocketKey,
+	) -> WebSocketResult<Client<S>>
+	where
+		S: Stream,
+	{
+		// send request
+		stream.write_all(request)?;
 
-		// wait for a response
-		let mut reader = BufReader::new(stream);
-		let response = parse_response(&mut reader).map_err(towse)?;
+		// wait for a response, reading no more of it than allowed
+		let mut reader = BufReader::new(Capped {
+			stream,
+			left: self.max_response_header_bytes.unwrap_or(usize::MAX),
+		});
+		let response = match parse_response(&mut reader) {
+			Ok(response) => response,
+			Err(hyper::Error::Io(_)) if reader.get_ref().left == 0 => {
+				return Err(towse(WebSocketOtherError::ResponseError(
+					"Handshake response headers are too large",
+				)));
+			}
+			Err(e @ hyper::Error::Io(_)) => return Err(towse(e)),
+			Err(e) => {
+				let received = handshake_snippet(reader.get_buf());
+				return Err(towse(WebSocketOtherError::MalformedHandshake(e, received)));
+			}
+		};
 
 		// validate
-		self.validate(&response)?;
-
-		Ok(Client::unchecked_with_limits(reader, response.headers, true, false, self.max_dataframe_size, self.max_dataframe_size))
+		Self::validate(&response, key)?;
+
+		let (capped, buf, pos, cap) = reader.into_parts();
+		let reader = BufReader::from_parts(capped.stream, buf, pos, cap);
+		let mut client = Client::unchecked_with_limits(
+			reader,
+			response.headers,
+			true,
+			false,
+			self.max_dataframe_size,
+			self.max_dataframe_size,
+		);
+		client.set_auto_fragment(self.auto_fragment);
+		client.set_swallow_pongs(self.swallow_pongs);
+		client.set_pong_callback(self.pong_callback.clone().map(|PongCallback(f)| f));
+		client.set_auto_pong_rate_limit(self.auto_pong_rate_limit);
+		client.set_protocol_error_policy(self.protocol_error_policy);
+		if let Some(ObserverHandle(ref observer)) = self.observer {
+			observer.handshake_complete();
+			client.set_observer(Some(observer.clone()));
+		}
+		Ok(client)
 	}
 
 	/// Connect to a websocket server asynchronously.
@@ -569,7 +1204,7 @@ impl<'u> ClientBuilder<'u> {
 		// connect to the tcp stream
 		let tcp_stream = self.async_tcpstream(None);
 
-		let builder = ClientBuilder {
+		let mut builder = ClientBuilder {
 			url: Cow::Owned(self.url.into_owned()),
 			version: self.version,
 			headers: self.headers,
@@ -577,6 +1212,23 @@ impl<'u> ClientBuilder<'u> {
 			key_set: self.key_set,
 			max_dataframe_size: self.max_dataframe_size,
 			max_message_size: self.max_message_size,
+			auto_fragment: self.auto_fragment,
+			socket_addr: self.socket_addr,
+			alpn_protocols: self.alpn_protocols,
+			key_source: self.key_source,
+			omit_host_port: self.omit_host_port,
+			protocols_single_header: self.protocols_single_header,
+			swallow_pongs: self.swallow_pongs,
+			close_on_drop: self.close_on_drop,
+			pong_callback: self.pong_callback,
+			auto_pong_rate_limit: self.auto_pong_rate_limit,
+			protocol_error_policy: self.protocol_error_policy,
+			resolver: self.resolver,
+			observer: self.observer,
+			handshake_timeout: self.handshake_timeout,
+			max_response_header_bytes: self.max_response_header_bytes,
+			tcp_buffer_sizes: self.tcp_buffer_sizes,
+			local_addr: self.local_addr,
 		};
 
 		// check if we should connect over ssl or not
@@ -589,12 +1241,18 @@ impl<'u> ClientBuilder<'u> {
 				}
 			};
 			// secure connection, wrap with ssl
-			let future = tcp_stream
-				.and_then(move |s| connector.connect(&host, s).map_err(towse))
-				.and_then(move |stream| {
-					let stream: Box<dyn stream::r#async::Stream + Send> = Box::new(stream);
-					builder.async_connect_on(stream)
-				});
+			let timeout = builder.handshake_timeout.take();
+			let future = tcp_stream.and_then(move |s| {
+				let handshake =
+					connector
+						.connect(&host, s)
+						.map_err(towse)
+						.and_then(move |stream| {
+							let stream: Box<dyn stream::r#async::Stream + Send> = Box::new(stream);
+							builder.async_connect_on(stream)
+						});
+				within_handshake_timeout(timeout, handshake)
+			});
 			Box::new(future)
 		} else {
 			// insecure connection, connect normally
@@ -655,7 +1313,7 @@ impl<'u> ClientBuilder<'u> {
 			}
 		};
 
-		let builder = ClientBuilder {
+		let mut builder = ClientBuilder {
 			url: Cow::Owned(self.url.into_owned()),
 			version: self.version,
 			headers: self.headers,
@@ -663,12 +1321,34 @@ impl<'u> ClientBuilder<'u> {
 			key_set: self.key_set,
 			max_dataframe_size: self.max_dataframe_size,
 			max_message_size: self.max_message_size,
+			auto_fragment: self.auto_fragment,
+			socket_addr: self.socket_addr,
+			alpn_protocols: self.alpn_protocols,
+			key_source: self.key_source,
+			omit_host_port: self.omit_host_port,
+			protocols_single_header: self.protocols_single_header,
+			swallow_pongs: self.swallow_pongs,
+			close_on_drop: self.close_on_drop,
+			pong_callback: self.pong_callback,
+			auto_pong_rate_limit: self.auto_pong_rate_limit,
+			protocol_error_policy: self.protocol_error_policy,
+			resolver: self.resolver,
+			observer: self.observer,
+			handshake_timeout: self.handshake_timeout,
+			max_response_header_bytes: self.max_response_header_bytes,
+			tcp_buffer_sizes: self.tcp_buffer_sizes,
+			local_addr: self.local_addr,
 		};
 
 		// put it all together
-		let future = tcp_stream
-			.and_then(move |s| connector.connect(&host, s).map_err(towse))
-			.and_then(move |stream| builder.async_connect_on(stream));
+		let timeout = builder.handshake_timeout.take();
+		let future = tcp_stream.and_then(move |s| {
+			let handshake = connector
+				.connect(&host, s)
+				.map_err(towse)
+				.and_then(move |stream| builder.async_connect_on(stream));
+			within_handshake_timeout(timeout, handshake)
+		});
 		Box::new(future)
 	}
 
@@ -715,6 +1395,23 @@ impl<'u> ClientBuilder<'u> {
 			key_set: self.key_set,
 			max_dataframe_size: self.max_dataframe_size,
 			max_message_size: self.max_message_size,
+			auto_fragment: self.auto_fragment,
+			socket_addr: self.socket_addr,
+			alpn_protocols: self.alpn_protocols,
+			key_source: self.key_source,
+			omit_host_port: self.omit_host_port,
+			protocols_single_header: self.protocols_single_header,
+			swallow_pongs: self.swallow_pongs,
+			close_on_drop: self.close_on_drop,
+			pong_callback: self.pong_callback,
+			auto_pong_rate_limit: self.auto_pong_rate_limit,
+			protocol_error_policy: self.protocol_error_policy,
+			resolver: self.resolver,
+			observer: self.observer,
+			handshake_timeout: self.handshake_timeout,
+			max_response_header_bytes: self.max_response_header_bytes,
+			tcp_buffer_sizes: self.tcp_buffer_sizes,
+			local_addr: self.local_addr,
 		};
 
 		let future = tcp_stream.and_then(move |stream| builder.async_connect_on(stream));
@@ -776,39 +1473,50 @@ impl<'u> ClientBuilder<'u> {
 			key_set: self.key_set,
 			max_dataframe_size: self.max_dataframe_size,
 			max_message_size: self.max_message_size,
+			auto_fragment: self.auto_fragment,
+			socket_addr: self.socket_addr,
+			alpn_protocols: self.alpn_protocols,
+			key_source: self.key_source,
+			omit_host_port: self.omit_host_port,
+			protocols_single_header: self.protocols_single_header,
+			swallow_pongs: self.swallow_pongs,
+			close_on_drop: self.close_on_drop,
+			pong_callback: self.pong_callback,
+			auto_pong_rate_limit: self.auto_pong_rate_limit,
+			protocol_error_policy: self.protocol_error_policy,
+			resolver: self.resolver,
+			observer: self.observer,
+			handshake_timeout: self.handshake_timeout,
+			max_response_header_bytes: self.max_response_header_bytes,
+			tcp_buffer_sizes: self.tcp_buffer_sizes,
+			local_addr: self.local_addr,
 		};
 		let resource = builder.build_request();
-		let framed = crate::codec::http::HttpClientCodec.framed(stream);
+		let key = match builder.request_key() {
+			Ok(key) => key,
+			Err(e) => return Box::new(future::err(e)),
+		};
+		let framed = CappedHttpClientCodec {
+			max: self.max_response_header_bytes.unwrap_or(usize::MAX),
+		}
+		.framed(stream);
 		let request = Incoming {
 			version: builder.version,
 			headers: builder.headers.clone(),
 			subject: (Method::Get, RequestUri::AbsolutePath(resource)),
 		};
 
-		let max_dataframe_size = self.max_dataframe_size;
-		let max_message_size = self.max_message_size;
-		let future = framed
-			// send request
-			.send(request)
-			.map_err(::std::convert::Into::into)
-			// wait for a response
-			.and_then(|stream| stream.into_future().map_err(|e| towse(e.0)))
-			// validate
-			.and_then(move |(message, stream)| {
-				message
-					.ok_or(WebSocketError::ProtocolError(
-						"Connection closed before handshake could complete.",
-					))
-					.and_then(|message| builder.validate(&message).map(|()| (message, stream)))
-			})
-			// output the final client and metadata
-			.map(move |(message, stream)| {
-				let codec = MessageCodec::new_with_limits(Context::Client, max_dataframe_size, max_message_size);
-				let client = update_framed_codec(stream, codec);
-				(client, message.headers)
-			});
-
-		Box::new(future)
+		// send request
+		let sent = framed.send(request).map_err(::std::convert::Into::into);
+		let future = async_handshake(
+			sent,
+			key,
+			self.max_dataframe_size,
+			self.max_message_size,
+			self.auto_fragment,
+		);
+
+		within_handshake_timeout(self.handshake_timeout, future)
 	}
 
 	#[cfg(feature = "async")]
@@ -817,36 +1525,57 @@ impl<'u> ClientBuilder<'u> {
 		secure: Option<bool>,
 	) -> Box<dyn future::Future<Item = TcpStreamNew, Error = WebSocketError> + Send> {
 		// get the address to connect to, return an error future if ther's a problem
-		let address = match self
-			.extract_host_port(secure)
-			.and_then(|p| Ok(p.to_socket_addrs()?))
-		{
-			Ok(mut s) => match s.next() {
-				Some(a) => a,
-				None => {
-					return Box::new(
-						Err(WebSocketOtherError::WebSocketUrlError(
-							WSUrlErrorKind::NoHostName,
-						))
-						.map_err(towse)
-						.into_future(),
-					);
-				}
+		let address = match self.socket_addr {
+			Some(address) => address,
+			None => match self.resolve(secure) {
+				Ok(s) => match s.into_iter().next() {
+					Some(a) => a,
+					None => {
+						return Box::new(
+							Err(WebSocketOtherError::WebSocketUrlError(
+								WSUrlErrorKind::NoHostName,
+							))
+							.map_err(towse)
+							.into_future(),
+						);
+					}
+				},
+				Err(e) => return Box::new(Err(e).into_future()),
 			},
-			Err(e) => return Box::new(Err(e).into_future()),
 		};
 
 		// connect a tcp stream
-		Box::new(TcpStreamNew::connect(&address).map_err(Into::into))
-	}
-
-	#[cfg(any(feature = "sync", feature = "async"))]
+		#[cfg(feature = "bind-local")]
+		let connect = match self.local_addr {
+			Some(local) => match bound_socket(local, &address) {
+				Ok(socket) => {
+					TcpStreamNew::connect_std(socket.into(), &address, &Handle::default())
+				}
+				Err(e) => return Box::new(Err(e.into()).into_future()),
+			},
+			None => TcpStreamNew::connect(&address),
+		};
+		#[cfg(not(feature = "bind-local"))]
+		let connect = TcpStreamNew::connect(&address);
+		#[cfg(feature = "tcp-buffers")]
+		let connect = {
+			let sizes = self.tcp_buffer_sizes;
+			connect.and_then(move |s| set_buffer_sizes(sizes, SockRef::from(&s)).map(|()| s))
+		};
+		Box::new(connect.map_err(Into::into))
+	}
+
+	#[cfg(any(feature = "sync", feature = "async"))]
 	fn build_request(&mut self) -> String {
 		// enter host if available (unix sockets don't have hosts)
 		if let Some(host) = self.url.host_str() {
 			self.headers.set(Host {
 				hostname: host.to_string(),
-				port: self.url.port(),
+				port: if self.omit_host_port {
+					None
+				} else {
+					self.url.port()
+				},
 			});
 		}
 
@@ -876,40 +1605,78 @@ impl<'u> ClientBuilder<'u> {
 		}
 
 		if !self.key_set {
-			self.headers.set(WebSocketKey::new());
+			let key = self.new_key();
+			self.headers.set(key);
+		}
+
+		if !self.protocols_single_header {
+			let lines = self.headers.get::<WebSocketProtocol>().map(|protos| {
+				protos
+					.0
+					.iter()
+					.map(|proto| proto.as_bytes().to_vec())
+					.collect::<Vec<_>>()
+			});
+			if let Some(lines) = lines {
+				self.headers
+					.set_raw(WebSocketProtocol::header_name(), lines);
+			}
 		}
 
 		// send request
 		self.url[Position::BeforePath..Position::AfterQuery].to_owned()
 	}
 
+	/// A key for a new handshake request, unless one was set with `key`.
+	#[cfg(any(feature = "sync", feature = "async"))]
+	fn new_key(&self) -> WebSocketKey {
+		match self.key_source {
+			Some(KeySource(ref f)) => WebSocketKey::from_array(f()),
+			None => WebSocketKey::new(),
+		}
+	}
+
+	/// The key of the handshake request built by `build_request`.
+	#[cfg(any(feature = "sync", feature = "async"))]
+	fn request_key(&self) -> WebSocketResult<WebSocketKey> {
+		self.headers.get::<WebSocketKey>().cloned().ok_or_else(|| {
+			towse(WebSocketOtherError::RequestError(
+				"Request Sec-WebSocket-Key was invalid",
+			))
+		})
+	}
+
 	#[cfg(any(feature = "sync", feature = "async"))]
-	fn validate(&self, response: &Incoming<RawStatus>) -> WebSocketResult<()> {
+	fn validate(response: &Incoming<RawStatus>, key: &WebSocketKey) -> WebSocketResult<()> {
 		let status = StatusCode::from_u16(response.subject.0);
 
 		if status != StatusCode::SwitchingProtocols {
+			if status == StatusCode::UpgradeRequired {
+				let versions = response
+					.headers
+					.get_raw(WebSocketVersion::header_name())
+					.and_then(|raw| WebSocketVersion::parse_list(raw).ok());
+				if let Some(versions) = versions {
+					return Err(towse(WebSocketOtherError::UnsupportedVersionError(
+						status, versions,
+					)));
+				}
+			}
 			if status.is_redirection() {
 				match response.headers.get::<hyper::header::Location>() {
 					Some(x) => return Err(WebSocketOtherError::RedirectError(status, x.to_string())).map_err(towse),
 					None => (),
 				}
 			}
-			return Err(WebSocketOtherError::StatusCodeError(status)).map_err(towse);
+			if status.is_success() {
+				return Err(towse(WebSocketOtherError::NotUpgraded(status)));
+			}
+			return Err(towse(WebSocketOtherError::HandshakeResponse(
+				HandshakeResponseError::NotSwitchingProtocols(status),
+			)));
 		}
 
-		let key = self
-			.headers
-			.get::<WebSocketKey>()
-			.ok_or(WebSocketOtherError::RequestError(
-				"Request Sec-WebSocket-Key was invalid",
-			))?;
-
-		if response.headers.get() != Some(&(WebSocketAccept::new(key))) {
-			return Err(WebSocketOtherError::ResponseError(
-				"Sec-WebSocket-Accept is invalid",
-			))
-			.map_err(towse);
-		}
+		verify_accept(key, &response.headers).map_err(towse)?;
 
 		if response.headers.get()
 			!= Some(
@@ -918,22 +1685,19 @@ impl<'u> ClientBuilder<'u> {
 					version: None,
 				}])),
 			) {
-			return Err(WebSocketOtherError::ResponseError(
-				"Upgrade field must be WebSocket",
-			))
-			.map_err(towse);
+			return Err(towse(WebSocketOtherError::HandshakeResponse(
+				HandshakeResponseError::WrongUpgrade,
+			)));
 		}
 
-		if self.headers.get()
-			!= Some(
-				&(Connection(vec![ConnectionOption::ConnectionHeader(UniCase(
-					"Upgrade".to_string(),
-				))])),
-			) {
-			return Err(WebSocketOtherError::ResponseError(
-				"Connection field must be 'Upgrade'",
-			))
-			.map_err(towse);
+		let upgrade = ConnectionOption::ConnectionHeader(UniCase("Upgrade".to_string()));
+		match response.headers.get::<Connection>() {
+			Some(Connection(options)) if options.contains(&upgrade) => (),
+			_ => {
+				return Err(towse(WebSocketOtherError::HandshakeResponse(
+					HandshakeResponseError::WrongConnection,
+				)));
+			}
 		}
 
 		Ok(())
@@ -942,7 +1706,7 @@ impl<'u> ClientBuilder<'u> {
 	/// Check whether the given URL uses a secure scheme, e.g. `wss` or `https`.
 	/// Note that `https` is not intended scheme for web sockets, but
 	/// it's still reasonable to wrap TLS if it is encountered.
-	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+	#[cfg(any(feature = "sync", feature = "async"))]
 	fn is_secure_url(&self) -> bool {
 		let scheme = self.url.scheme();
 		scheme == "wss" || scheme == "https"
@@ -971,9 +1735,110 @@ impl<'u> ClientBuilder<'u> {
 		})?)
 	}
 
+	/// Start the clock on the handshake timeout, bounding the reads and
+	/// writes of a TLS handshake on `tcp_stream` by it.
 	#[cfg(feature = "sync")]
-	fn establish_tcp(&mut self, secure: Option<bool>) -> WebSocketResult<TcpStream> {
-		Ok(TcpStream::connect(self.extract_host_port(secure)?)?)
+	fn arm_handshake_timeout(&self, tcp_stream: &TcpStream) -> WebSocketResult<Instant> {
+		if self.handshake_timeout.is_some() {
+			tcp_stream.set_read_timeout(self.handshake_timeout)?;
+			tcp_stream.set_write_timeout(self.handshake_timeout)?;
+		}
+		Ok(Instant::now())
+	}
+
+	/// Report `error` as a timeout if the handshake timeout has run out since
+	/// `started`, since that is most likely what caused it.
+	#[cfg(feature = "sync")]
+	fn handshake_error(&self, error: WebSocketError, started: Instant) -> WebSocketError {
+		match self.handshake_timeout {
+			Some(timeout) if started.elapsed() >= timeout => {
+				towse(WebSocketOtherError::HandshakeTimeout)
+			}
+			_ => error,
+		}
+	}
+
+	/// `handshake` with the socket timeouts set to what is left of the
+	/// handshake timeout, then cleared again for the client.
+	#[cfg(feature = "sync")]
+	fn connect_within<S>(
+		&self,
+		stream: S,
+		started: Instant,
+		request: &[u8],
+		key: &WebSocketKey,
+	) -> WebSocketResult<Client<S>>
+	where
+		S: Stream + AsTcpStream,
+	{
+		let timeout = match self.handshake_timeout {
+			Some(timeout) => timeout,
+			None => {
+				let mut client = self.handshake(stream, request, key)?;
+				client.set_close_on_drop(self.close_on_drop);
+				return Ok(client);
+			}
+		};
+		let left = match timeout.checked_sub(started.elapsed()) {
+			Some(left) if left > Duration::from_secs(0) => left,
+			_ => return Err(towse(WebSocketOtherError::HandshakeTimeout)),
+		};
+		stream.as_tcp().set_read_timeout(Some(left))?;
+		stream.as_tcp().set_write_timeout(Some(left))?;
+
+		let mut client = self
+			.handshake(stream, request, key)
+			.map_err(|e| self.handshake_error(e, started))?;
+		// every read may have taken up to `left`, so check the total as well
+		if started.elapsed() >= timeout {
+			return Err(towse(WebSocketOtherError::HandshakeTimeout));
+		}
+
+		let tcp_stream = client.stream_ref().as_tcp();
+		tcp_stream.set_read_timeout(None)?;
+		tcp_stream.set_write_timeout(None)?;
+		client.set_close_on_drop(self.close_on_drop);
+		Ok(client)
+	}
+
+	#[cfg(feature = "sync")]
+	fn establish_tcp(&self, secure: Option<bool>) -> WebSocketResult<TcpStream> {
+		#[cfg(feature = "bind-local")]
+		{
+			if let Some(local) = self.local_addr {
+				let addresses = match self.socket_addr {
+					Some(address) => vec![address],
+					None => self.resolve(secure)?,
+				};
+				let tcp_stream = connect_from(local, &addresses)?;
+				#[cfg(feature = "tcp-buffers")]
+				set_buffer_sizes(self.tcp_buffer_sizes, SockRef::from(&tcp_stream))?;
+				return Ok(tcp_stream);
+			}
+		}
+		let tcp_stream = if let Some(address) = self.socket_addr {
+			TcpStream::connect(address)?
+		} else if self.resolver.is_some() {
+			TcpStream::connect(&self.resolve(secure)?[..])?
+		} else {
+			TcpStream::connect(self.extract_host_port(secure)?)?
+		};
+		#[cfg(feature = "tcp-buffers")]
+		set_buffer_sizes(self.tcp_buffer_sizes, SockRef::from(&tcp_stream))?;
+		Ok(tcp_stream)
+	}
+
+	/// The addresses to try to connect to, found with the configured resolver.
+	#[cfg(any(feature = "sync", feature = "async"))]
+	fn resolve(&self, secure: Option<bool>) -> WebSocketResult<Vec<SocketAddr>> {
+		let host_port = self.extract_host_port(secure)?;
+		let addresses = match (&self.resolver, &host_port.host) {
+			(Some(ResolverHandle(resolver)), url::Host::Domain(domain)) => {
+				resolver.resolve(domain, host_port.port)?
+			}
+			_ => host_port.to_socket_addrs()?.collect(),
+		};
+		Ok(addresses)
 	}
 
 	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
@@ -992,7 +1857,13 @@ impl<'u> ClientBuilder<'u> {
 		};
 		let connector = match connector {
 			Some(c) => c,
-			None => TlsConnector::builder().build().map_err(towse)?,
+			None => {
+				let alpn: Vec<&str> = self.alpn_protocols.iter().map(String::as_str).collect();
+				TlsConnector::builder()
+					.request_alpns(&alpn)
+					.build()
+					.map_err(towse)?
+			}
 		};
 		Ok((host, connector))
 	}
@@ -1009,6 +1880,260 @@ impl<'u> ClientBuilder<'u> {
 	}
 }
 
+/// A `ClientBuilder` whose handshake request has been built once and for all,
+/// see `ClientBuilder::freeze`.
+///
+/// Clones share the request bytes.
+#[cfg(any(feature = "sync", feature = "async"))]
+#[derive(Clone, Debug)]
+pub struct FrozenClientBuilder<'u> {
+	/// The rest of the configuration, without the headers.
+	builder: ClientBuilder<'u>,
+	/// The request line and headers, except for the key and the empty line.
+	request: Arc<[u8]>,
+	/// The key set with `ClientBuilder::key`, if any.
+	key: Option<WebSocketKey>,
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+impl<'u> FrozenClientBuilder<'u> {
+	/// The handshake request for a new connection, along with its key.
+	fn request(&self) -> (Vec<u8>, WebSocketKey) {
+		let key = match self.key {
+			Some(key) => key,
+			None => self.builder.new_key(),
+		};
+		let mut request = Vec::with_capacity(self.request.len() + 48);
+		request.extend_from_slice(&self.request);
+		let key_line = format!(
+			"{}: {}\r\n\r\n",
+			WebSocketKey::header_name(),
+			key.serialize()
+		);
+		request.extend_from_slice(key_line.as_bytes());
+		(request, key)
+	}
+
+	/// Create an insecure (plain TCP) connection, see `ClientBuilder::connect_insecure`.
+	#[cfg(feature = "sync")]
+	pub fn connect_insecure(&self) -> WebSocketResult<Client<TcpStream>> {
+		let tcp_stream = self.builder.establish_tcp(Some(false))?;
+		let started = self.builder.arm_handshake_timeout(&tcp_stream)?;
+		let (request, key) = self.request();
+		self.builder
+			.connect_within(tcp_stream, started, &request, &key)
+	}
+
+	/// Create an SSL connection, see `ClientBuilder::connect_secure`.
+	#[cfg(feature = "sync-ssl")]
+	pub fn connect_secure(
+		&self,
+		ssl_config: Option<TlsConnector>,
+	) -> WebSocketResult<Client<TlsStream<TcpStream>>> {
+		let tcp_stream = self.builder.establish_tcp(Some(true))?;
+		let started = self.builder.arm_handshake_timeout(&tcp_stream)?;
+		let ssl_stream = self
+			.builder
+			.wrap_ssl(tcp_stream, ssl_config)
+			.map_err(|e| self.builder.handshake_error(e, started))?;
+		let (request, key) = self.request();
+		self.builder
+			.connect_within(ssl_stream, started, &request, &key)
+	}
+
+	/// Connect on any stream, see `ClientBuilder::connect_on`.
+	#[cfg(feature = "sync")]
+	pub fn connect_on<S>(&self, stream: S) -> WebSocketResult<Client<S>>
+	where
+		S: Stream,
+	{
+		let (request, key) = self.request();
+		self.builder.handshake(stream, &request, &key)
+	}
+
+	/// Asynchronously create an insecure (plain TCP) connection,
+	/// see `ClientBuilder::async_connect_insecure`.
+	#[cfg(feature = "async")]
+	pub fn async_connect_insecure(&self) -> r#async::ClientNew<r#async::TcpStream> {
+		let tcp_stream = self.builder.async_tcpstream(Some(false));
+		Box::new(tcp_stream.and_then(self.async_handshake()))
+	}
+
+	/// Asynchronously connect on any stream, see `ClientBuilder::async_connect_on`.
+	#[cfg(feature = "async")]
+	pub fn async_connect_on<S>(&self, stream: S) -> r#async::ClientNew<S>
+	where
+		S: stream::r#async::Stream + Send + 'static,
+	{
+		self.async_handshake()(stream)
+	}
+
+	/// The handshake to run on a stream, which doesn't borrow `self`.
+	#[cfg(feature = "async")]
+	fn async_handshake<S>(&self) -> impl FnOnce(S) -> r#async::ClientNew<S>
+	where
+		S: stream::r#async::Stream + Send + 'static,
+	{
+		let (request, key) = self.request();
+		let builder = &self.builder;
+		let max_dataframe_size = builder.max_dataframe_size;
+		let max_message_size = builder.max_message_size;
+		let auto_fragment = builder.auto_fragment;
+		let timeout = builder.handshake_timeout;
+		let codec = CappedHttpClientCodec {
+			max: builder.max_response_header_bytes.unwrap_or(usize::MAX),
+		};
+		move |stream| {
+			// send request
+			let sent = tokio_io::io::write_all(stream, request)
+				.map(move |(stream, _)| codec.framed(stream))
+				.map_err(::std::convert::Into::into);
+			let future = async_handshake(
+				sent,
+				key,
+				max_dataframe_size,
+				max_message_size,
+				auto_fragment,
+			);
+			within_handshake_timeout(timeout, future)
+		}
+	}
+}
+
+/// Apply the sizes set with `ClientBuilder::tcp_buffer_sizes` to `socket`.
+#[cfg(all(feature = "tcp-buffers", any(feature = "sync", feature = "async")))]
+fn set_buffer_sizes(sizes: (Option<usize>, Option<usize>), socket: SockRef) -> io::Result<()> {
+	if let Some(send) = sizes.0 {
+		socket.set_send_buffer_size(send)?;
+	}
+	if let Some(recv) = sizes.1 {
+		socket.set_recv_buffer_size(recv)?;
+	}
+	Ok(())
+}
+
+/// A TCP socket bound to `local` that can be connected to `address`.
+#[cfg(all(feature = "bind-local", any(feature = "sync", feature = "async")))]
+fn bound_socket(local: SocketAddr, address: &SocketAddr) -> io::Result<Socket> {
+	let socket = Socket::new(Domain::for_address(*address), Type::STREAM, None)?;
+	socket.bind(&local.into())?;
+	Ok(socket)
+}
+
+/// Connects from `local` to the first of `addresses` that accepts.
+#[cfg(all(feature = "bind-local", feature = "sync"))]
+fn connect_from(local: SocketAddr, addresses: &[SocketAddr]) -> io::Result<TcpStream> {
+	let mut last_error = None;
+	for address in addresses {
+		match bound_socket(local, address).and_then(|s| s.connect(&(*address).into()).map(|()| s)) {
+			Ok(socket) => return Ok(socket.into()),
+			Err(e) => last_error = Some(e),
+		}
+	}
+	Err(last_error.unwrap_or_else(|| {
+		io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"could not resolve to any addresses",
+		)
+	}))
+}
+
+/// Reads from `stream`, but no more than `left` more bytes.
+#[cfg(feature = "sync")]
+struct Capped<S> {
+	stream: S,
+	left: usize,
+}
+
+#[cfg(feature = "sync")]
+impl<S: io::Read> io::Read for Capped<S> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.left == 0 {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"read limit reached",
+			));
+		}
+		let len = buf.len().min(self.left);
+		let read = self.stream.read(&mut buf[..len])?;
+		self.left -= read;
+		Ok(read)
+	}
+}
+
+/// Waits for the response to the handshake request sent by `sent`, which
+/// has to accept `key`, then turns the stream into a client.
+#[cfg(feature = "async")]
+fn async_handshake<F, S>(
+	sent: F,
+	key: WebSocketKey,
+	max_dataframe_size: usize,
+	max_message_size: usize,
+	auto_fragment: Option<usize>,
+) -> impl Future<Item = (r#async::Client<S>, Headers), Error = WebSocketError>
+where
+	F: Future<Item = Framed<S, CappedHttpClientCodec>, Error = WebSocketError>,
+	S: stream::r#async::Stream,
+{
+	sent
+		// wait for a response, reading no more of it than allowed
+		.and_then(|stream| {
+			stream.into_future().map_err(|e| match e.0 {
+				HttpCodecError::Http(hyper::Error::TooLarge) => {
+					towse(WebSocketOtherError::ResponseError(
+						"Handshake response headers are too large",
+					))
+				}
+				e => towse(e),
+			})
+		})
+		// validate
+		.and_then(move |(message, stream)| {
+			message
+				.ok_or(WebSocketError::ProtocolError(
+					"Connection closed before handshake could complete.",
+				))
+				.and_then(|message| {
+					ClientBuilder::validate(&message, &key).map(|()| (message, stream))
+				})
+		})
+		// output the final client and metadata
+		.map(move |(message, stream)| {
+			let mut codec = MessageCodec::new_with_limits(
+				Context::Client,
+				max_dataframe_size,
+				max_message_size,
+			);
+			codec.set_auto_fragment(auto_fragment);
+			let client = update_framed_codec(stream, codec);
+			(client, message.headers)
+		})
+}
+
+/// Fail `handshake` with `WebSocketOtherError::HandshakeTimeout` unless it
+/// finishes within `timeout`.
+#[cfg(feature = "async")]
+fn within_handshake_timeout<F, S>(timeout: Option<Duration>, handshake: F) -> r#async::ClientNew<S>
+where
+	F: Future<Item = (r#async::Client<S>, Headers), Error = WebSocketError> + Send + 'static,
+	S: stream::r#async::Stream + Send + 'static,
+{
+	let timeout = match timeout {
+		Some(timeout) => timeout,
+		None => return Box::new(handshake),
+	};
+	let future = Timeout::new(handshake, timeout).map_err(|e| {
+		if e.is_elapsed() {
+			towse(WebSocketOtherError::HandshakeTimeout)
+		} else if e.is_inner() {
+			e.into_inner().unwrap()
+		} else {
+			WebSocketError::IoError(io::Error::new(io::ErrorKind::Other, e))
+		}
+	});
+	Box::new(future)
+}
+
 mod tests {
 	#[test]
 	fn build_client_with_protocols() {
@@ -1034,6 +2159,501 @@ mod tests {
 		assert!(!protos.contains(&"rust-websocket".to_string()));
 	}
 
+	#[test]
+	fn unsupported_version_response() {
+		use super::*;
+		use std::io::Cursor;
+		use websocket_base::stream::ReadWritePair;
+
+		let response = b"HTTP/1.1 426 Upgrade Required\r\nSec-WebSocket-Version: 13, 8\r\n\r\n";
+		let stream = ReadWritePair(Cursor::new(&response[..]), Cursor::new(Vec::new()));
+		let err = match ClientBuilder::new("ws://127.0.0.1:8080")
+			.unwrap()
+			.version(WebSocketVersion::Unknown("9".to_string()))
+			.connect_on(stream)
+		{
+			Ok(_) => panic!("handshake should fail"),
+			Err(WebSocketError::Other(e)) => e,
+			Err(e) => panic!("unexpected error {}", e),
+		};
+		match err.downcast_ref::<WebSocketOtherError>() {
+			Some(WebSocketOtherError::UnsupportedVersionError(status, versions)) => {
+				assert_eq!(*status, StatusCode::UpgradeRequired);
+				assert_eq!(
+					versions,
+					&vec![
+						WebSocketVersion::WebSocket13,
+						WebSocketVersion::Unknown("8".to_string()),
+					]
+				);
+			}
+			_ => panic!("unexpected error {}", err),
+		}
+	}
+
+	#[test]
+	fn redirect_with_version_header() {
+		use super::*;
+		use std::io::Cursor;
+		use websocket_base::stream::ReadWritePair;
+
+		let response = b"HTTP/1.1 302 Found\r\nLocation: ws://example.org/chat\r\nSec-WebSocket-Version: 13\r\n\r\n";
+		let stream = ReadWritePair(Cursor::new(&response[..]), Cursor::new(Vec::new()));
+		let err = match ClientBuilder::new("ws://127.0.0.1:8080")
+			.unwrap()
+			.connect_on(stream)
+		{
+			Ok(_) => panic!("handshake should fail"),
+			Err(WebSocketError::Other(e)) => e,
+			Err(e) => panic!("unexpected error {}", e),
+		};
+		match err.downcast_ref::<WebSocketOtherError>() {
+			Some(WebSocketOtherError::RedirectError(status, location)) => {
+				assert_eq!(*status, StatusCode::Found);
+				assert_eq!(location, "ws://example.org/chat");
+			}
+			_ => panic!("unexpected error {}", err),
+		}
+	}
+
+	#[test]
+	fn build_client_with_key_source() {
+		use super::*;
+		use std::sync::atomic::{AtomicUsize, Ordering};
+
+		let calls = Arc::new(AtomicUsize::new(0));
+		let counter = calls.clone();
+		let mut builder = ClientBuilder::new("ws://127.0.0.1:8080")
+			.unwrap()
+			.key_from(move || [counter.fetch_add(1, Ordering::SeqCst) as u8; 16]);
+
+		builder.build_request();
+		assert_eq!(
+			builder.headers.get(),
+			Some(&WebSocketKey::from_array([0; 16]))
+		);
+		builder.build_request();
+		assert_eq!(
+			builder.headers.get(),
+			Some(&WebSocketKey::from_array([1; 16]))
+		);
+		assert_eq!(calls.load(Ordering::SeqCst), 2);
+	}
+
+	#[test]
+	fn build_client_without_host_port() {
+		use super::*;
+
+		let mut builder = ClientBuilder::new("ws://127.0.0.1:8080").unwrap();
+		builder.build_request();
+		assert_eq!(
+			builder.headers.get(),
+			Some(&Host {
+				hostname: "127.0.0.1".to_string(),
+				port: Some(8080),
+			})
+		);
+
+		let mut builder = builder.omit_host_port(true);
+		builder.build_request();
+		assert_eq!(
+			builder.headers.get(),
+			Some(&Host {
+				hostname: "127.0.0.1".to_string(),
+				port: None,
+			})
+		);
+	}
+
+	#[test]
+	fn not_upgraded_response() {
+		use super::*;
+		use std::io::Cursor;
+		use websocket_base::stream::ReadWritePair;
+
+		let response = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+		let stream = ReadWritePair(Cursor::new(&response[..]), Cursor::new(Vec::new()));
+		let err = match ClientBuilder::new("ws://127.0.0.1:8080")
+			.unwrap()
+			.connect_on(stream)
+		{
+			Ok(_) => panic!("handshake should fail"),
+			Err(e) => e,
+		};
+		assert!(err
+			.to_string()
+			.ends_with("Server responded with HTTP 200 OK instead of upgrading to WebSocket"));
+	}
+
+	#[test]
+	#[cfg(feature = "sync")]
+	fn handshake_response_errors_are_typed() {
+		use super::*;
+		use std::io::Cursor;
+		use websocket_base::stream::ReadWritePair;
+
+		let fail = |response: &str| {
+			let stream = ReadWritePair(
+				Cursor::new(response.as_bytes().to_vec()),
+				Cursor::new(Vec::new()),
+			);
+			let err = match ClientBuilder::new("ws://127.0.0.1:8080")
+				.unwrap()
+				.key_from(|| *b"the sample nonce")
+				.connect_on(stream)
+			{
+				Ok(_) => panic!("handshake should fail"),
+				Err(WebSocketError::Other(e)) => e,
+				Err(e) => panic!("unexpected error {}", e),
+			};
+			match err.downcast_ref::<WebSocketOtherError>() {
+				Some(WebSocketOtherError::HandshakeResponse(e)) => e.clone(),
+				_ => panic!("unexpected error {}", err),
+			}
+		};
+
+		let upgrade = "Upgrade: websocket\r\n";
+		let connection = "Connection: keep-alive, Upgrade\r\n";
+		let accept = "Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n";
+		let response = |status: &str, headers: &[&str]| {
+			format!("HTTP/1.1 {}\r\n{}\r\n", status, headers.concat())
+		};
+
+		assert_eq!(
+			fail(&response("101 Switching Protocols", &[upgrade, connection])),
+			HandshakeResponseError::MissingAccept
+		);
+		assert_eq!(
+			fail(&response(
+				"101 Switching Protocols",
+				&[
+					upgrade,
+					connection,
+					"Sec-WebSocket-Accept: dGhlIHNhbXBsZSBub25jZQ==\r\n"
+				]
+			)),
+			HandshakeResponseError::BadAccept
+		);
+		assert_eq!(
+			fail(&response("101 Switching Protocols", &[connection, accept])),
+			HandshakeResponseError::WrongUpgrade
+		);
+		assert_eq!(
+			fail(&response("101 Switching Protocols", &[upgrade, accept])),
+			HandshakeResponseError::WrongConnection
+		);
+		assert_eq!(
+			fail(&response("500 Internal Server Error", &[])),
+			HandshakeResponseError::NotSwitchingProtocols(StatusCode::InternalServerError)
+		);
+	}
+
+	#[test]
+	#[cfg(feature = "sync")]
+	fn malformed_response_shows_received_bytes() {
+		use super::*;
+		use std::io::Cursor;
+		use websocket_base::stream::ReadWritePair;
+
+		let response = b"SSH-2.0-OpenSSH_7.4\r\nProtocol mismatch.\r\n\r\n";
+		let stream = ReadWritePair(Cursor::new(&response[..]), Cursor::new(Vec::new()));
+		let err = match ClientBuilder::new("ws://127.0.0.1:8080")
+			.unwrap()
+			.connect_on(stream)
+		{
+			Ok(_) => panic!("handshake should fail"),
+			Err(e) => e,
+		};
+		assert!(err
+			.to_string()
+			.contains("received \"SSH-2.0-OpenSSH_7.4\\r\\n"));
+	}
+
+	#[test]
+	#[cfg(feature = "sync")]
+	fn connect_to_addr_keeps_host() {
+		use super::*;
+		use std::io::{BufRead, Write};
+		use std::net::TcpListener;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = ::std::thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let mut reader = ::std::io::BufReader::new(stream);
+			let mut request = String::new();
+			while reader.read_line(&mut request).unwrap() > 2 {}
+			let mut stream = reader.into_inner();
+			stream.write_all(b"HTTP/1.1 404 Not Found\r\n\r\n").unwrap();
+			request
+		});
+
+		let result = ClientBuilder::new("ws://backend.invalid/chat")
+			.unwrap()
+			.connect_to_addr(addr)
+			.connect_insecure();
+		assert!(result.is_err());
+
+		let request = server.join().unwrap();
+		assert!(request.starts_with("GET /chat HTTP/1.1\r\n"), "{}", request);
+		assert!(request.contains("Host: backend.invalid\r\n"), "{}", request);
+	}
+
+	#[test]
+	#[cfg(feature = "sync")]
+	fn connect_through_resolver() {
+		use super::*;
+		use std::net::TcpListener;
+		use std::sync::Mutex;
+
+		struct Recording(SocketAddr, Mutex<Vec<(String, u16)>>);
+
+		impl Resolver for Recording {
+			fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+				self.1.lock().unwrap().push((host.to_string(), port));
+				Ok(vec![self.0])
+			}
+		}
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let resolver = Arc::new(Recording(
+			listener.local_addr().unwrap(),
+			Mutex::new(Vec::new()),
+		));
+		let server = ::std::thread::spawn(move || listener.accept().map(|_| ()));
+
+		// the listener closes the connection, so only the connection attempt matters
+		let _ = ClientBuilder::new("ws://chat.service.consul/room")
+			.unwrap()
+			.resolver(resolver.clone())
+			.connect_insecure();
+		server.join().unwrap().unwrap();
+		assert_eq!(
+			*resolver.1.lock().unwrap(),
+			vec![("chat.service.consul".to_string(), 80)]
+		);
+	}
+
+	#[test]
+	#[cfg(feature = "sync")]
+	fn stalled_handshake_times_out() {
+		use super::*;
+		use std::net::TcpListener;
+		use std::sync::mpsc;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let (done, wait) = mpsc::channel::<()>();
+		// accept the connection but never answer the handshake
+		let server = ::std::thread::spawn(move || {
+			let _stream = listener.accept().unwrap();
+			let _ = wait.recv();
+		});
+
+		let result = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.handshake_timeout(Duration::from_millis(100))
+			.connect_insecure();
+		done.send(()).unwrap();
+		server.join().unwrap();
+		match result {
+			Err(WebSocketError::Other(e)) => match e.downcast_ref::<WebSocketOtherError>() {
+				Some(WebSocketOtherError::HandshakeTimeout) => (),
+				_ => panic!("expected a handshake timeout, got {}", e),
+			},
+			Err(e) => panic!("expected a handshake timeout, got {}", e),
+			Ok(_) => panic!("the handshake should not complete"),
+		}
+	}
+
+	#[test]
+	#[cfg(all(feature = "sync", feature = "tcp-buffers"))]
+	fn tcp_buffer_sizes_are_applied() {
+		use super::*;
+		use std::net::TcpListener;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let stream = ClientBuilder::new(&format!("ws://{}", listener.local_addr().unwrap()))
+			.unwrap()
+			.tcp_buffer_sizes(Some(256 * 1024), None)
+			.establish_tcp(Some(false))
+			.unwrap();
+		// the system may round the size up, e.g. linux doubles it
+		assert!(SockRef::from(&stream).send_buffer_size().unwrap() >= 256 * 1024);
+	}
+
+	#[test]
+	#[cfg(all(feature = "sync", feature = "bind-local"))]
+	fn bind_local_sets_the_source_address() {
+		use super::*;
+		use std::net::TcpListener;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		// find a free port to bind the client to
+		let local = TcpListener::bind("127.0.0.1:0")
+			.unwrap()
+			.local_addr()
+			.unwrap();
+		let stream = ClientBuilder::new(&format!("ws://{}", listener.local_addr().unwrap()))
+			.unwrap()
+			.bind_local(local)
+			.establish_tcp(Some(false))
+			.unwrap();
+		assert_eq!(stream.local_addr().unwrap(), local);
+		assert_eq!(listener.accept().unwrap().1, local);
+	}
+
+	#[test]
+	#[cfg(feature = "sync")]
+	fn oversized_response_headers_are_rejected() {
+		use super::*;
+		use std::io::Cursor;
+		use websocket_base::stream::ReadWritePair;
+
+		let mut response = b"HTTP/1.1 101 Switching Protocols\r\n\
+			Upgrade: websocket\r\n\
+			Connection: Upgrade\r\n\
+			Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"
+			.to_vec();
+		response.extend_from_slice(format!("X-Padding: {}\r\n\r\n", "a".repeat(1000)).as_bytes());
+		let connect = |max| {
+			let stream = ReadWritePair(Cursor::new(response.clone()), Cursor::new(Vec::new()));
+			ClientBuilder::new("ws://127.0.0.1:8080")
+				.unwrap()
+				.key_from(|| *b"the sample nonce")
+				.max_response_header_bytes(max)
+				.connect_on(stream)
+		};
+
+		assert!(connect(response.len()).is_ok());
+		match connect(512) {
+			Err(WebSocketError::Other(e)) => match e.downcast_ref::<WebSocketOtherError>() {
+				Some(WebSocketOtherError::ResponseError(_)) => (),
+				_ => panic!("expected a response error, got {}", e),
+			},
+			Err(e) => panic!("expected a response error, got {}", e),
+			Ok(_) => panic!("the handshake should fail"),
+		}
+	}
+
+	#[test]
+	#[cfg(all(feature = "sync", feature = "async"))]
+	fn async_oversized_response_headers_are_rejected() {
+		use super::*;
+		use std::io::Cursor;
+		use websocket_base::stream::ReadWritePair;
+
+		let mut response = b"HTTP/1.1 101 Switching Protocols\r\n\
+			Upgrade: websocket\r\n\
+			Connection: Upgrade\r\n\
+			Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"
+			.to_vec();
+		response.extend_from_slice(format!("X-Padding: {}\r\n\r\n", "a".repeat(1000)).as_bytes());
+		let mut runtime = tokio::runtime::Runtime::new().unwrap();
+		let mut connect = |max| {
+			let stream = ReadWritePair(Cursor::new(response.clone()), Cursor::new(Vec::new()));
+			let future = ClientBuilder::new("ws://127.0.0.1:8080")
+				.unwrap()
+				.key_from(|| *b"the sample nonce")
+				.max_response_header_bytes(max)
+				.async_connect_on(stream);
+			runtime.block_on(future)
+		};
+
+		assert!(connect(response.len()).is_ok());
+		match connect(512) {
+			Err(WebSocketError::Other(e)) => match e.downcast_ref::<WebSocketOtherError>() {
+				Some(WebSocketOtherError::ResponseError(_)) => (),
+				_ => panic!("expected a response error, got {}", e),
+			},
+			Err(e) => panic!("expected a response error, got {}", e),
+			Ok(_) => panic!("the handshake should fail"),
+		}
+	}
+
+	#[test]
+	#[cfg(feature = "sync")]
+	fn frame_glued_to_response_is_received() {
+		use super::*;
+		use crate::message::OwnedMessage;
+		use std::io::Cursor;
+		use websocket_base::stream::ReadWritePair;
+
+		let response = b"HTTP/1.1 101 Switching Protocols\r\n\
+			Upgrade: websocket\r\n\
+			Connection: Upgrade\r\n\
+			Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+			\r\n";
+		let mut incoming = response.to_vec();
+		incoming.extend_from_slice(&[0x81, 0x05, b'h', b'e', b'l', b'l', b'o']);
+
+		// read along with the response, or left on the stream by the header limit
+		for &max in &[None, Some(response.len())] {
+			let stream = ReadWritePair(Cursor::new(incoming.clone()), Cursor::new(Vec::new()));
+			let mut builder = ClientBuilder::new("ws://127.0.0.1:8080")
+				.unwrap()
+				.key_from(|| *b"the sample nonce");
+			if let Some(max) = max {
+				builder = builder.max_response_header_bytes(max);
+			}
+			let mut client = builder.connect_on(stream).unwrap();
+			assert_eq!(
+				client.recv_message().unwrap(),
+				OwnedMessage::Text("hello".to_string())
+			);
+		}
+
+		#[cfg(feature = "async")]
+		{
+			let stream = ReadWritePair(Cursor::new(incoming), Cursor::new(Vec::new()));
+			let future = ClientBuilder::new("ws://127.0.0.1:8080")
+				.unwrap()
+				.key_from(|| *b"the sample nonce")
+				.async_connect_on(stream)
+				.and_then(|(client, _)| client.into_future().map_err(|e| e.0));
+			let (message, _) = tokio::runtime::Runtime::new()
+				.unwrap()
+				.block_on(future)
+				.unwrap();
+			assert_eq!(message, Some(OwnedMessage::Text("hello".to_string())));
+		}
+	}
+
+	#[test]
+	#[cfg(feature = "sync")]
+	fn client_keeps_response_headers() {
+		use super::*;
+		use std::io::Cursor;
+		use websocket_base::stream::ReadWritePair;
+
+		let response = b"HTTP/1.1 101 Switching Protocols\r\n\
+			Upgrade: websocket\r\n\
+			Connection: Upgrade\r\n\
+			Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+			Sec-WebSocket-Protocol: chat\r\n\
+			Set-Cookie: session=abc\r\n\
+			Set-Cookie: theme=dark\r\n\
+			X-Served-By: edge-1\r\n\
+			\r\n";
+		let stream = ReadWritePair(Cursor::new(&response[..]), Cursor::new(Vec::new()));
+		let client = ClientBuilder::new("ws://127.0.0.1:8080")
+			.unwrap()
+			.key_from(|| *b"the sample nonce")
+			.add_protocol("chat")
+			.connect_on(stream)
+			.unwrap();
+
+		assert_eq!(client.protocols(), &["chat".to_string()]);
+		assert_eq!(
+			client.server_cookies(),
+			vec!["session=abc".to_string(), "theme=dark".to_string()]
+		);
+		assert_eq!(
+			client.headers().get_raw("X-Served-By"),
+			Some(&[b"edge-1".to_vec()][..])
+		);
+	}
+
 	#[test]
 	fn build_client_with_username_password() {
 		use super::*;
@@ -1043,4 +2663,115 @@ mod tests {
 		assert!(auth.username == "john");
 		assert_eq!(auth.password, Some("pswd".to_owned()));
 	}
+
+	#[test]
+	fn build_client_with_auth_headers() {
+		use super::*;
+		let mut builder = ClientBuilder::new("ws://127.0.0.1:8080/hello")
+			.unwrap()
+			.bearer_token("abc");
+		let _request = builder.build_request();
+		assert_eq!(
+			builder.headers.get_raw("Authorization").unwrap()[0],
+			b"Bearer abc".to_vec()
+		);
+
+		let mut builder = builder.basic_auth("john", Some("pswd"));
+		let _request = builder.build_request();
+		assert_eq!(
+			builder.headers.get_raw("Authorization").unwrap()[0],
+			b"Basic am9objpwc3dk".to_vec()
+		);
+	}
+
+	#[test]
+	fn build_request_protocols_header_form() {
+		use super::*;
+		let mut builder = ClientBuilder::new("ws://127.0.0.1:8080/hello")
+			.unwrap()
+			.add_protocols(vec!["pubsub", "sub.events"]);
+		let _request = builder.build_request();
+		assert!(builder
+			.headers
+			.to_string()
+			.contains("Sec-WebSocket-Protocol: pubsub, sub.events\r\n"));
+
+		let mut builder = builder.protocols_single_header(false);
+		let _request = builder.build_request();
+		let headers = builder.headers.to_string();
+		assert!(headers.contains("Sec-WebSocket-Protocol: pubsub\r\n"));
+		assert!(headers.contains("Sec-WebSocket-Protocol: sub.events\r\n"));
+		assert_eq!(
+			builder.get_header::<WebSocketProtocol>().unwrap().0,
+			vec!["pubsub".to_string(), "sub.events".to_string()]
+		);
+	}
+
+	#[test]
+	fn target_matches_connection() {
+		use super::*;
+		let builder = ClientBuilder::new("ws://[::1]:8080").unwrap();
+		assert_eq!(
+			builder.target().unwrap(),
+			WsTarget {
+				host: "[::1]".to_string(),
+				port: 8080,
+				secure: false,
+				resource: "/".to_string(),
+			}
+		);
+
+		let builder = ClientBuilder::new("unix:/run/ws.sock").unwrap();
+		assert!(builder.target().is_err());
+	}
+
+	#[test]
+	fn frozen_builder_sends_the_same_request() {
+		use super::*;
+		use crate::stream::ReadWritePair;
+		use std::io::Cursor;
+
+		let accept = b"HTTP/1.1 101 Switching Protocols\r\n\
+		               Upgrade: websocket\r\n\
+		               Connection: Upgrade\r\n\
+		               Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+		               \r\n";
+		let connect = |builder: &mut ClientBuilder| {
+			let stream = ReadWritePair(Cursor::new(&accept[..]), Cursor::new(Vec::new()));
+			(builder.connect_on(stream).unwrap().into_stream().0)
+				.1
+				.into_inner()
+		};
+
+		let builder = ClientBuilder::new("ws://127.0.0.1:8080/chat")
+			.unwrap()
+			.add_protocol("rust-websocket")
+			.key(*b"the sample nonce");
+		let expected = connect(&mut builder.clone());
+
+		let frozen = builder.freeze();
+		for _ in 0..2 {
+			let stream = ReadWritePair(Cursor::new(&accept[..]), Cursor::new(Vec::new()));
+			let client = frozen.connect_on(stream).unwrap();
+			let sent = (client.into_stream().0).1.into_inner();
+			let mut sent_lines: Vec<_> = sent.split(|&b| b == b'\n').collect();
+			let mut expected_lines: Vec<_> = expected.split(|&b| b == b'\n').collect();
+			sent_lines.sort();
+			expected_lines.sort();
+			assert_eq!(sent_lines, expected_lines);
+		}
+
+		// without a fixed key every request gets a new one
+		let frozen = ClientBuilder::new("ws://127.0.0.1:8080/chat")
+			.unwrap()
+			.freeze();
+		let (first, first_key) = frozen.request();
+		let (second, second_key) = frozen.request();
+		assert_ne!(first_key, second_key);
+		assert_eq!(first.len(), second.len());
+		assert!(String::from_utf8(second).unwrap().ends_with(&format!(
+			"Sec-WebSocket-Key: {}\r\n\r\n",
+			second_key.serialize()
+		)));
+	}
 }
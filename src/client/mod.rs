@@ -14,7 +14,7 @@
 //! `websocket::{sync, async}::client` module which will have all sync or all async things.
 
 pub mod builder;
-pub use self::builder::{ClientBuilder, ParseError, Url};
+pub use self::builder::{ClientBuilder, ParseError, Url, WsTarget};
 
 #[cfg(feature = "async")]
 pub mod r#async;
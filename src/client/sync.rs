@@ -1,17 +1,39 @@
 //! Contains the WebSocket client.
 use hyper::buffer::BufReader;
-use hyper::header::Headers;
+use hyper::header::{Headers, SetCookie};
+#[cfg(feature = "server")]
+use std::any::Any;
+use std::io::ErrorKind;
 use std::io::Result as IoResult;
-use std::io::{Read, Write};
+use std::io::{BufRead, Read, Write};
 use std::net::SocketAddr;
 use std::net::TcpStream;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::dataframe::DataFrame;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, RawSocket};
+
+#[cfg(feature = "sync-ssl")]
+use native_tls::TlsStream;
+
+#[cfg(feature = "keepalive")]
+use socket2::SockRef;
+/// Keepalive settings for `Client::set_keepalive`, re-exported from `socket2`.
+#[cfg(feature = "keepalive")]
+pub use socket2::TcpKeepalive;
+
+use crate::dataframe::{DataFrame, Opcode};
 use crate::header::extensions::Extension;
 use crate::header::{WebSocketExtensions, WebSocketProtocol};
-use crate::message::OwnedMessage;
-use crate::result::WebSocketResult;
-use crate::stream::sync::{AsTcpStream, Shutdown, Splittable, Stream};
+use crate::message::{CloseData, Fragment, FragmentState, Message, OwnedMessage, Type};
+use crate::observer::Observer;
+use crate::result::{ProtocolErrorPolicy, ProtocolViolation, WebSocketError, WebSocketResult};
+use crate::stream::sync::{AsTcpStream, Shutdown, Splittable, Stream, TrySplit};
 use crate::ws;
 use crate::ws::receiver::Receiver as ReceiverTrait;
 use crate::ws::receiver::{DataFrameIterator, MessageIterator};
@@ -20,9 +42,50 @@ use crate::ws::sender::Sender as SenderTrait;
 pub use crate::receiver::Reader;
 use crate::receiver::Receiver;
 use crate::sender::Sender;
-pub use crate::sender::Writer;
+pub use crate::sender::{MessageWriter, Writer};
+#[cfg(feature = "server")]
+use crate::server::ConnectionGuard;
 use crate::ws::dataframe::DataFrame as DataFrameable;
 
+/// What happened on a connection, as returned by `Client::next_event`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum Event {
+	/// A message other than close was received.
+	Message(OwnedMessage),
+	/// The remote endpoint closed the connection, with an optional reason.
+	Closed(Option<CloseData>),
+}
+
+/// A client that has sent its close message and is waiting for the remote
+/// endpoint's answer, as returned by `Client::initiate_close`.
+///
+/// The remote endpoint may still send messages it had sent before seeing the
+/// close message, which `recv_message` returns until the answer arrives.
+pub struct ClosingClient<'a, S>
+where
+	S: Stream,
+{
+	client: &'a mut Client<S>,
+}
+
+impl<'a, S> ClosingClient<'a, S>
+where
+	S: Stream,
+{
+	/// Reads the next message the remote endpoint sent before closing, or
+	/// `Event::Closed` once it has answered the close message. Pings are
+	/// answered along the way.
+	pub fn recv_message(&mut self) -> WebSocketResult<Event> {
+		loop {
+			match self.client.recv_message()? {
+				OwnedMessage::Close(data) => return Ok(Event::Closed(data)),
+				OwnedMessage::Ping(data) => self.client.send_message(&OwnedMessage::Pong(data))?,
+				message => return Ok(Event::Message(message)),
+			}
+		}
+	}
+}
+
 /// Represents a WebSocket client, which can send and receive messages/data frames.
 ///
 /// The client just wraps around a `Stream` (which is something that can be read from
@@ -56,10 +119,23 @@ pub struct Client<S>
 where
 	S: Stream,
 {
-	stream: BufReader<S>,
+	stream: ClientStream<S>,
 	headers: Headers,
 	sender: Sender,
 	receiver: Receiver,
+	#[cfg(feature = "server")]
+	connection_guard: Option<ConnectionGuard>,
+	unread: Vec<OwnedMessage>,
+	swallow_pongs: bool,
+	pong_callback: Option<Arc<dyn Fn(&[u8]) + Send + Sync>>,
+	auto_pong: Option<PongBudget>,
+	error_policy: ProtocolErrorPolicy,
+	strict_close: bool,
+	fragments: FragmentState,
+	close_codes: (Option<u16>, Option<u16>),
+	observer: Option<Arc<dyn Observer>>,
+	nonblocking: AtomicBool,
+	next_ping: u64,
 }
 
 impl Client<TcpStream> {
@@ -76,6 +152,16 @@ impl Client<TcpStream> {
 	}
 }
 
+#[cfg(feature = "sync-ssl")]
+impl Client<TlsStream<TcpStream>> {
+	/// The protocol negotiated with ALPN during the TLS handshake, if any.
+	///
+	/// See `ClientBuilder::request_alpns` to offer protocols.
+	pub fn alpn_protocol(&self) -> Option<Vec<u8>> {
+		self.stream.get_ref().negotiated_alpn().ok().and_then(|p| p)
+	}
+}
+
 impl<S> Client<S>
 where
 	S: AsTcpStream + Stream,
@@ -104,9 +190,329 @@ where
 		self.stream.get_ref().as_tcp().set_nodelay(nodelay)
 	}
 
+	/// Turns on the operating system's TCP keepalive probes with the idle
+	/// time, interval and retry count of `params`, or turns them off with `None`.
+	///
+	/// Unlike pings these are handled by the OS without waking up the
+	/// application, and they keep some middleboxes from dropping idle
+	/// connections. Which settings are honored depends on the platform.
+	///
+	/// Only available with the `keepalive` feature.
+	#[cfg(feature = "keepalive")]
+	pub fn set_keepalive(&self, params: Option<TcpKeepalive>) -> IoResult<()> {
+		let socket = SockRef::from(self.stream.get_ref().as_tcp());
+		match params {
+			Some(params) => socket.set_tcp_keepalive(&params),
+			None => socket.set_keepalive(false),
+		}
+	}
+
 	/// Changes whether the stream is in nonblocking mode.
 	pub fn set_nonblocking(&self, nonblocking: bool) -> IoResult<()> {
-		self.stream.get_ref().as_tcp().set_nonblocking(nonblocking)
+		self.stream
+			.get_ref()
+			.as_tcp()
+			.set_nonblocking(nonblocking)?;
+		self.nonblocking.store(nonblocking, Ordering::Relaxed);
+		Ok(())
+	}
+
+	/// Whether the stream is in nonblocking mode, e.g. to restore the mode
+	/// after switching it temporarily.
+	///
+	/// The socket can't be asked, so this is the mode last set with
+	/// `set_nonblocking`, or blocking for a new client. Changes made to the
+	/// socket some other way, e.g. through `stream_ref`, aren't seen.
+	pub fn is_nonblocking(&self) -> bool {
+		self.nonblocking.load(Ordering::Relaxed)
+	}
+
+	/// Send a close message with 1001 (Going Away) when the client is
+	/// dropped without having sent one, instead of just cutting the
+	/// connection. See also `ClientBuilder::close_on_drop`.
+	///
+	/// This is a best effort: the socket is put into nonblocking mode so
+	/// dropping never blocks, and the close message is written without
+	/// waiting for the answer, ignoring errors. Splitting the client or
+	/// taking its stream out turns this off.
+	pub fn set_close_on_drop(&mut self, close: bool) {
+		self.stream.close_on_drop = if close {
+			Some((self.sender.is_masked(), |stream| {
+				stream.as_tcp().set_nonblocking(true)
+			}))
+		} else {
+			None
+		};
+	}
+
+	/// Cheaply checks whether the peer is still connected, without sending anything.
+	///
+	/// Data that was already read into the client's buffer means the connection
+	/// is alive. Otherwise this peeks at the underlying socket: reading EOF or
+	/// getting an error such as a connection reset means the peer is gone, while
+	/// pending data or a read that would block means the connection is still
+	/// alive. Nothing is consumed from the stream.
+	///
+	/// A blocking socket is put into nonblocking mode for the peek and back
+	/// afterwards, so this shouldn't race with reads on a clone of the socket
+	/// from another thread. The socket's read timeout is left alone.
+	///
+	/// Note that a peer which vanished without closing its socket (e.g. a pulled cable)
+	/// can only be detected by a failing write or a ping that is never answered.
+	pub fn is_connected(&self) -> bool {
+		if !self.unread.is_empty() || !self.stream.get_buf().is_empty() {
+			return true;
+		}
+		let tcp = self.stream.get_ref().as_tcp();
+		let nonblocking = self.is_nonblocking();
+		if !nonblocking && tcp.set_nonblocking(true).is_err() {
+			return false;
+		}
+
+		let mut buf = [0u8; 1];
+		let connected = match tcp.peek(&mut buf) {
+			Ok(0) => false,
+			Ok(_) => true,
+			Err(ref e) => matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted),
+		};
+
+		if !nonblocking {
+			let _ = tcp.set_nonblocking(false);
+		}
+		connected
+	}
+
+	/// Reads and discards incoming messages until none are pending, returning
+	/// how many were discarded. Pings are answered along the way.
+	///
+	/// With a `timeout` this keeps waiting for more messages until it expires,
+	/// otherwise only messages that have already (started to) arrive are drained.
+	/// Once a message starts arriving it is always read completely, so the
+	/// connection stays usable. Draining stops at a close message, which is
+	/// answered with a close message as well (see `OwnedMessage::close_echo`).
+	///
+	/// Messages put back with `unread_message` are discarded as well.
+	///
+	/// Note that for TLS streams data already decrypted by the TLS layer
+	/// but not yet read cannot be seen, so draining might stop early.
+	pub fn drain_incoming(&mut self, timeout: Option<Duration>) -> WebSocketResult<usize> {
+		let deadline = timeout.map(|t| Instant::now() + t);
+		let mut discarded = self.unread.len();
+		self.unread.clear();
+		loop {
+			if self.stream.get_buf().is_empty() {
+				let wait = match deadline {
+					Some(d) => d.saturating_duration_since(Instant::now()),
+					None => Duration::from_micros(1),
+				};
+				// a zero read timeout is not allowed
+				if !self.wait_for_data(wait.max(Duration::from_micros(1)))? {
+					return Ok(discarded);
+				}
+			}
+
+			match self.recv_message()? {
+				OwnedMessage::Ping(data) => self.send_message(&OwnedMessage::Pong(data))?,
+				message @ OwnedMessage::Close(_) => {
+					if let Some(reply) = message.close_echo() {
+						self.send_message(&reply)?;
+					}
+					return Ok(discarded);
+				}
+				_ => discarded += 1,
+			}
+		}
+	}
+
+	/// Closes the connection gracefully like `close`, but waits at most
+	/// `timeout` for the remote endpoint to answer with its own close message.
+	///
+	/// Pings received meanwhile are answered and other messages are discarded.
+	/// If no answer arrives in time the sending half of the TCP connection is
+	/// shut down and `Ok(())` is returned all the same: the close message was
+	/// sent, so the close was completed as far as this side is concerned.
+	///
+	/// Unlike `close_nowait`, which returns as soon as the close message is
+	/// flushed, this still gives the remote endpoint the chance to answer,
+	/// without hanging forever on one that stays silent. Five seconds is a
+	/// reasonable default.
+	pub fn close_with_timeout(
+		&mut self,
+		data: Option<CloseData>,
+		timeout: Duration,
+	) -> WebSocketResult<()> {
+		self.close_nowait(data)?;
+		let deadline = Instant::now() + timeout;
+		let tcp_timeout = self.stream.get_ref().as_tcp().read_timeout()?;
+		let answered = loop {
+			let wait = deadline.saturating_duration_since(Instant::now());
+			// a zero read timeout is not allowed
+			let wait = wait.max(Duration::from_micros(1));
+			self.stream
+				.get_ref()
+				.as_tcp()
+				.set_read_timeout(Some(wait))?;
+			match self.recv_message() {
+				Ok(OwnedMessage::Close(_)) => break Ok(true),
+				Ok(OwnedMessage::Ping(data)) => self.send_message(&OwnedMessage::Pong(data))?,
+				Ok(_) => (),
+				Err(WebSocketError::IoError(ref e))
+					if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+				{
+					break Ok(false)
+				}
+				Err(e) => break Err(e),
+			}
+		};
+
+		let tcp = self.stream.get_ref().as_tcp();
+		let _ = tcp.set_read_timeout(tcp_timeout);
+		if !answered? {
+			tcp.shutdown(Shutdown::Write)?;
+		}
+		Ok(())
+	}
+
+	/// Sends a ping and waits at most `timeout` for the matching pong,
+	/// returning how long the round trip took, e.g. for health checks.
+	///
+	/// A sequence number is appended to `payload` to tell the pong apart from
+	/// unsolicited ones, which are ignored, so `payload` can be at most 117
+	/// bytes long. Pings received meanwhile are answered, and data messages
+	/// are put back in the order they arrived, like with `unread_message`, so
+	/// `recv_message` and `incoming_messages` still return them, on the
+	/// client or on the `Reader` split off it.
+	///
+	/// Fails with an `ErrorKind::TimedOut` I/O error if the pong doesn't
+	/// arrive in time, or with `WebSocketError::ProtocolError` if the remote
+	/// endpoint closes the connection first, whose close message is put back
+	/// as well. Once a message starts arriving it is read with the read
+	/// timeout of the stream, like with `recv_message`, so `timeout` is only
+	/// checked between messages.
+	pub fn ping_roundtrip(
+		&mut self,
+		payload: &[u8],
+		timeout: Duration,
+	) -> WebSocketResult<Duration> {
+		if payload.len() > 125 - 8 {
+			return Err(ProtocolViolation::ControlFrameTooBig.into());
+		}
+		let mut ping = payload.to_vec();
+		ping.extend_from_slice(&self.next_ping.to_be_bytes());
+		self.next_ping = self.next_ping.wrapping_add(1);
+
+		let started = Instant::now();
+		self.send_message(&OwnedMessage::Ping(ping.clone()))?;
+
+		let swallow_pongs = std::mem::replace(&mut self.swallow_pongs, false);
+		let mut received = Vec::new();
+		let result = self.await_pong(&ping, started + timeout, &mut received);
+		self.swallow_pongs = swallow_pongs;
+		// they arrived after anything that was put back before
+		self.unread.splice(0..0, received.into_iter().rev());
+
+		result.map(|()| started.elapsed())
+	}
+
+	/// Receives the next message like `recv_message`, keeping an idle
+	/// connection in check: if nothing arrives for `ping_after`, a ping is
+	/// sent, and if nothing arrives within `close_after` of that either, the
+	/// connection is closed with status 1001 (Going Away).
+	///
+	/// Any message proves the connection alive, not just the pong. Call this
+	/// in a loop in place of `recv_message`; the pong is returned like any
+	/// other message unless pongs are swallowed.
+	///
+	/// Fails with an `ErrorKind::TimedOut` I/O error once the connection was
+	/// closed for being idle. Once a message starts arriving it is read with
+	/// the read timeout of the stream, like with `recv_message`.
+	pub fn recv_message_with_idle_timeout(
+		&mut self,
+		ping_after: Duration,
+		close_after: Duration,
+	) -> WebSocketResult<OwnedMessage> {
+		if let Some(message) = self.unread.pop() {
+			return Ok(message);
+		}
+		// a zero read timeout is not allowed
+		let min = Duration::from_micros(1);
+		loop {
+			// swallowed pongs and answered pings don't end the call, so the
+			// connection is kept in check until a message is returned
+			if self.stream.get_buf().is_empty() && !self.wait_for_data(ping_after.max(min))? {
+				self.send_message(&OwnedMessage::Ping(Vec::new()))?;
+				if !self.wait_for_data(close_after.max(min))? {
+					let close = CloseData::new(1001, "Idle timeout".to_string());
+					self.close_nowait(Some(close))?;
+					return Err(std::io::Error::new(
+						ErrorKind::TimedOut,
+						"No pong received in time",
+					)
+					.into());
+				}
+			}
+			if let Some(message) = self.read_one_message()? {
+				return Ok(message);
+			}
+		}
+	}
+
+	/// Reads until the pong with `ping` as payload arrives, keeping the data
+	/// messages read meanwhile in `received`.
+	fn await_pong(
+		&mut self,
+		ping: &[u8],
+		deadline: Instant,
+		received: &mut Vec<OwnedMessage>,
+	) -> WebSocketResult<()> {
+		loop {
+			// only wait between messages, a timeout in the middle of one
+			// would lose the part read so far
+			if self.stream.get_buf().is_empty() {
+				let wait = deadline.saturating_duration_since(Instant::now());
+				// a zero read timeout is not allowed
+				if !self.wait_for_data(wait.max(Duration::from_micros(1)))? {
+					return Err(std::io::Error::new(
+						ErrorKind::TimedOut,
+						"No pong received in time",
+					)
+					.into());
+				}
+			}
+			match self.read_message()? {
+				OwnedMessage::Pong(ref data) if data[..] == *ping => return Ok(()),
+				OwnedMessage::Pong(_) => (),
+				OwnedMessage::Ping(data) => self.send_message(&OwnedMessage::Pong(data))?,
+				message @ OwnedMessage::Close(_) => {
+					received.push(message);
+					return Err(WebSocketError::ProtocolError(
+						"Connection closed before the pong arrived",
+					));
+				}
+				message => received.push(message),
+			}
+		}
+	}
+
+	/// Waits at most `wait` for data to become available on the socket,
+	/// without consuming any of it.
+	fn wait_for_data(&self, wait: Duration) -> IoResult<bool> {
+		let tcp = self.stream.get_ref().as_tcp();
+		let timeout = tcp.read_timeout()?;
+		tcp.set_read_timeout(Some(wait))?;
+
+		let mut buf = [0u8; 1];
+		let available = match tcp.peek(&mut buf) {
+			// let the following read report the end of the stream
+			Ok(_) => Ok(true),
+			Err(ref e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+				Ok(false)
+			}
+			Err(e) => Err(e),
+		};
+
+		tcp.set_read_timeout(timeout)?;
+		available
 	}
 }
 
@@ -127,9 +533,22 @@ where
 	) -> Self {
 		Client {
 			headers,
-			stream,
+			stream: ClientStream::new(stream),
 			sender: Sender::new(out_mask),    // true
 			receiver: Receiver::new(in_mask), // false
+			#[cfg(feature = "server")]
+			connection_guard: None,
+			unread: Vec::new(),
+			swallow_pongs: false,
+			pong_callback: None,
+			auto_pong: None,
+			error_policy: ProtocolErrorPolicy::default(),
+			strict_close: false,
+			fragments: FragmentState::new(),
+			close_codes: (None, None),
+			observer: None,
+			nonblocking: AtomicBool::new(false),
+			next_ping: 0,
 		}
 	}
 
@@ -144,18 +563,131 @@ where
 	) -> Self {
 		Client {
 			headers,
-			stream,
+			stream: ClientStream::new(stream),
 			sender: Sender::new(out_mask),    // true
 			receiver: Receiver::new_with_limits(in_mask, max_dataframe_size, max_message_size), // false
+			#[cfg(feature = "server")]
+			connection_guard: None,
+			unread: Vec::new(),
+			swallow_pongs: false,
+			pong_callback: None,
+			auto_pong: None,
+			error_policy: ProtocolErrorPolicy::default(),
+			strict_close: false,
+			fragments: FragmentState::new(),
+			close_codes: (None, None),
+			observer: None,
+			nonblocking: AtomicBool::new(false),
+			next_ping: 0,
 		}
 	}
 
+	/// Hold a slot of the accepting server's connection limit for as long as
+	/// this client lives.
+	#[cfg(feature = "server")]
+	pub(crate) fn set_connection_guard(&mut self, guard: Option<ConnectionGuard>) {
+		self.connection_guard = guard;
+	}
+
+	/// Take the slot this client holds in the accepting server's connection
+	/// limit, e.g. to keep it taken after `into_stream`, which gives it back.
+	/// See `Server::with_connection_limit`.
+	#[cfg(feature = "server")]
+	pub fn take_connection_guard(&mut self) -> Option<ConnectionGuard> {
+		self.connection_guard.take()
+	}
+
+	/// Automatically split outgoing messages whose payload is larger than
+	/// `max_frame_size` into several fragments, or stop doing so with `None`.
+	/// Control messages are never fragmented.
+	///
+	/// This is useful when intermediaries choke on very large frames.
+	/// See also `ClientBuilder::auto_fragment`.
+	pub fn set_auto_fragment(&mut self, max_frame_size: Option<usize>) {
+		self.sender.set_auto_fragment(max_frame_size);
+	}
+
+	/// Check that the data frames sent with `send_dataframe` make up valid
+	/// messages, catching framing bugs before they reach the wire.
+	/// See `Sender::set_validate_outgoing`.
+	pub fn set_validate_outgoing(&mut self, validate: bool) {
+		self.sender.set_validate_outgoing(validate);
+	}
+
 	/// Sends a single data frame to the remote endpoint.
 	pub fn send_dataframe<D>(&mut self, dataframe: &D) -> WebSocketResult<()>
 	where
 		D: DataFrameable,
 	{
-		self.sender.send_dataframe(self.stream.get_mut(), dataframe)
+		let result = self.sender.send_dataframe(self.stream.get_mut(), dataframe);
+		self.observe_error(result)?;
+		self.sent_dataframe(dataframe)
+	}
+
+	/// Sends a single data frame masked with `mask` instead of a random key,
+	/// even if this client doesn't mask its frames otherwise. See
+	/// `DataFrame::write_to_with_mask` for when that's appropriate.
+	pub fn send_dataframe_with_mask<D>(
+		&mut self,
+		dataframe: &D,
+		mask: [u8; 4],
+	) -> WebSocketResult<()>
+	where
+		D: DataFrameable,
+	{
+		let result = self
+			.sender
+			.send_dataframe_with_mask(self.stream.get_mut(), dataframe, mask);
+		self.observe_error(result)?;
+		self.sent_dataframe(dataframe)
+	}
+
+	/// Sends a message as a single data frame masked with `mask`, see
+	/// `send_dataframe_with_mask`. The message is never split up into
+	/// fragments, whatever `set_auto_fragment` says.
+	pub fn send_message_with_mask<M>(&mut self, message: &M, mask: [u8; 4]) -> WebSocketResult<()>
+	where
+		M: ws::Message + DataFrameable,
+	{
+		self.send_dataframe_with_mask(message, mask)
+	}
+
+	/// Keeps track of the close code of a data frame that was sent.
+	fn sent_dataframe<D>(&mut self, dataframe: &D) -> WebSocketResult<()>
+	where
+		D: DataFrameable,
+	{
+		if dataframe.opcode() == Opcode::Close as u8 {
+			let mut payload = Vec::new();
+			dataframe.write_payload(&mut payload)?;
+			self.sent_close(frame_close_code(&payload));
+		}
+		Ok(())
+	}
+
+	/// Keeps track of the code of a close message that was sent.
+	fn sent_close(&mut self, code: u16) {
+		self.close_codes.0 = Some(code);
+		self.stream.close_on_drop = None;
+		if let Some(ref observer) = self.observer {
+			observer.close_sent(code);
+		}
+	}
+
+	/// Keeps track of the code of a close message that was received.
+	fn received_close(&mut self, code: u16) {
+		self.close_codes.1 = Some(code);
+		if let Some(ref observer) = self.observer {
+			observer.close_received(code);
+		}
+	}
+
+	/// Tells the observer, if there is one, about `result` failing.
+	fn observe_error<T>(&self, result: WebSocketResult<T>) -> WebSocketResult<T> {
+		if let (Err(ref e), Some(ref observer)) = (&result, &self.observer) {
+			observer.error(e);
+		}
+		result
 	}
 
 	/// Sends a single message to the remote endpoint.
@@ -163,12 +695,82 @@ where
 	where
 		M: ws::Message,
 	{
-		self.sender.send_message(self.stream.get_mut(), message)
+		let result = self.sender.send_message(self.stream.get_mut(), message);
+		self.observe_error(result)?;
+		if let Some(ref observer) = self.observer {
+			observer.message_sent(message.message_type(), message.message_size(false));
+		}
+		if let Some(code) = message.close_code() {
+			self.sent_close(code);
+		}
+		Ok(())
+	}
+
+	/// Sends several messages back to back with a single write to the stream,
+	/// see `Sender::send_messages`.
+	pub fn send_messages<I, M>(&mut self, messages: I) -> WebSocketResult<()>
+	where
+		I: IntoIterator<Item = M>,
+		M: ws::Message,
+	{
+		let mut close_code = None;
+		let mut sent = Vec::new();
+		let observing = self.observer.is_some();
+		let messages = messages.into_iter().inspect(|m| {
+			if let Some(code) = m.close_code() {
+				close_code = Some(code);
+			}
+			if observing {
+				sent.push((m.message_type(), m.message_size(false)));
+			}
+		});
+		let result = self.sender.send_messages(self.stream.get_mut(), messages);
+		self.observe_error(result)?;
+		if let Some(ref observer) = self.observer {
+			for (kind, size) in sent {
+				observer.message_sent(kind, size);
+			}
+		}
+		if let Some(code) = close_code {
+			self.sent_close(code);
+		}
+		Ok(())
+	}
+
+	/// Starts streaming a message of type `opcode` to the remote endpoint,
+	/// without having to buffer its payload first.
+	///
+	/// Each `write` of N bytes becomes one data frame with its own mask,
+	/// see `MessageWriter` for details.
+	///
+	/// ```rust,no_run
+	/// use std::fs::File;
+	/// use std::io;
+	/// use websocket::ClientBuilder;
+	/// use websocket::message::Type;
+	///
+	/// let mut client = ClientBuilder::new("ws://localhost:3000")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// let mut file = File::open("upload.bin").unwrap();
+	/// let mut message = io::BufWriter::new(client.message_writer(Type::Binary));
+	/// io::copy(&mut file, &mut message).unwrap();
+	/// message.into_inner().ok().unwrap().finish().unwrap();
+	/// ```
+	pub fn message_writer(&mut self, opcode: Type) -> MessageWriter<'_> {
+		MessageWriter::new(self.stream.get_mut(), &mut self.sender, opcode)
 	}
 
 	/// Reads a single data frame from the remote endpoint.
 	pub fn recv_dataframe(&mut self) -> WebSocketResult<DataFrame> {
-		self.receiver.recv_dataframe(&mut self.stream)
+		let result = self.receiver.recv_dataframe(&mut *self.stream);
+		let frame = self.close_on_violation(result)?;
+		if frame.opcode == Opcode::Close {
+			self.received_close(frame_close_code(&frame.data));
+		}
+		Ok(frame)
 	}
 
 	/// Returns an iterator over incoming data frames.
@@ -190,15 +792,415 @@ where
 	/// let response = client.recv_message().unwrap();
 	/// ```
 	pub fn recv_message(&mut self) -> WebSocketResult<OwnedMessage> {
-		self.receiver.recv_message(&mut self.stream)
+		match self.unread.pop() {
+			Some(message) => Ok(message),
+			None => self.read_message(),
+		}
+	}
+
+	/// Reads a single message into `buf` instead of a new `OwnedMessage` and
+	/// returns its type, see `Receiver::recv_message_into`.
+	///
+	/// Reusing `buf` saves an allocation per message when receiving lots of
+	/// them. Pings and pongs are handled like by `recv_message`, and
+	/// messages put back with `unread_message` come first.
+	///
+	/// ```rust,no_run
+	/// use websocket::ClientBuilder;
+	/// use websocket::message::Type;
+	///
+	/// let mut client = ClientBuilder::new("ws://localhost:3000")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// let mut buf = Vec::new();
+	/// while client.recv_message_into(&mut buf).unwrap() != Type::Close {
+	///     println!("{} bytes", buf.len());
+	/// }
+	/// ```
+	pub fn recv_message_into(&mut self, buf: &mut Vec<u8>) -> WebSocketResult<Type> {
+		if let Some(message) = self.unread.pop() {
+			buf.clear();
+			message.write_payload(buf)?;
+			return Ok(ws::Message::message_type(&message).unwrap_or(Type::Binary));
+		}
+		loop {
+			let result = self.receiver.recv_message_into(&mut *self.stream, buf);
+			let kind = self.close_on_violation(result)?;
+			if let Some(ref observer) = self.observer {
+				observer.message_received(
+					kind,
+					ws::Message::message_size(&Message::binary(&buf[..]), false),
+				);
+			}
+			if kind == Type::Close {
+				self.received_close(frame_close_code(buf));
+			}
+			match kind {
+				Type::Pong if self.swallow_pongs => {
+					if let Some(ref callback) = self.pong_callback {
+						callback(buf);
+					}
+				}
+				Type::Ping if self.auto_pong.is_some() => {
+					if self.auto_pong.as_mut().map(PongBudget::take) == Some(true) {
+						self.send_message(&Message::pong(&buf[..]))?;
+					}
+				}
+				Type::Close if self.strict_close && !self.stream.get_buf().is_empty() => {
+					return self.close_on_violation(Err(ProtocolViolation::DataAfterClose.into()));
+				}
+				kind => return Ok(kind),
+			}
+		}
+	}
+
+	/// Reads the next piece of a text or binary message as soon as its frame
+	/// has arrived, instead of waiting for the whole message like `recv_message`.
+	///
+	/// Pings are answered along the way, pongs and close messages are returned
+	/// as `Fragment::Control`. Don't mix this with `recv_message` while a
+	/// fragmented message is being received.
+	pub fn recv_fragment(&mut self) -> WebSocketResult<Fragment> {
+		loop {
+			let frame = self.recv_dataframe()?;
+			match self.fragments.push(frame)? {
+				Fragment::Control(OwnedMessage::Ping(data)) => {
+					self.send_message(&OwnedMessage::Pong(data))?
+				}
+				fragment => return Ok(fragment),
+			}
+		}
+	}
+
+	/// Reads the next message off the stream, skipping pongs if asked to.
+	fn read_message(&mut self) -> WebSocketResult<OwnedMessage> {
+		loop {
+			if let Some(message) = self.read_one_message()? {
+				return Ok(message);
+			}
+		}
+	}
+
+	/// Reads one message off the stream, `None` if it was a pong that got
+	/// swallowed or a ping that got answered.
+	fn read_one_message(&mut self) -> WebSocketResult<Option<OwnedMessage>> {
+		let result = self.receiver.recv_message(&mut *self.stream);
+		let message = self.close_on_violation(result)?;
+		if let (Some(kind), Some(ref observer)) =
+			(ws::Message::message_type(&message), &self.observer)
+		{
+			observer.message_received(kind, ws::Message::message_size(&message, false));
+		}
+		if let Some(code) = ws::Message::close_code(&message) {
+			self.received_close(code);
+		}
+		match message {
+			OwnedMessage::Pong(ref data) if self.swallow_pongs => {
+				if let Some(ref callback) = self.pong_callback {
+					callback(data);
+				}
+				Ok(None)
+			}
+			OwnedMessage::Ping(data) if self.auto_pong.is_some() => {
+				if self.auto_pong.as_mut().map(PongBudget::take) == Some(true) {
+					self.send_message(&OwnedMessage::Pong(data))?;
+				}
+				Ok(None)
+			}
+			OwnedMessage::Close(_) if self.strict_close && !self.stream.get_buf().is_empty() => {
+				self.close_on_violation(Err(ProtocolViolation::DataAfterClose.into()))
+			}
+			message => Ok(Some(message)),
+		}
+	}
+
+	/// Have `recv_message` skip pong messages instead of returning them,
+	/// so that only data, ping and close messages reach the application.
+	/// See also `ClientBuilder::swallow_pongs`.
+	pub fn set_swallow_pongs(&mut self, swallow: bool) {
+		self.swallow_pongs = swallow;
+	}
+
+	/// Call `callback` with the payload of every pong skipped because of
+	/// `set_swallow_pongs`, e.g. to keep track of a heartbeat.
+	pub fn on_pong<F>(&mut self, callback: F)
+	where
+		F: Fn(&[u8]) + Send + Sync + 'static,
+	{
+		self.pong_callback = Some(Arc::new(callback));
+	}
+
+	/// Fail with `ProtocolViolation::DataAfterClose` when a close message
+	/// is followed by more bytes in the same read, instead of ignoring them,
+	/// like `MessageCodec::set_strict_close` does for asynchronous clients.
+	pub fn set_strict_close(&mut self, strict: bool) {
+		self.strict_close = strict;
+	}
+
+	/// Close the connection with 1008 (Policy Violation) once the remote
+	/// endpoint has sent more than `max` frames over the connection's
+	/// lifetime, or lift the limit with `None`.
+	///
+	/// This guards servers against peers flooding them with tiny frames,
+	/// which the size limits don't catch. The read that exceeds the limit
+	/// fails with `ProtocolViolation::TooManyFrames` after the close message
+	/// has been sent, unless `set_protocol_error_policy` says otherwise.
+	/// Iterators from `incoming_messages` and `incoming_dataframes` report
+	/// the error without closing.
+	pub fn set_max_total_frames(&mut self, max: Option<u64>) {
+		self.receiver.set_max_total_frames(max);
+	}
+
+	/// Fail with `ProtocolViolation::TooManyEmptyFrames` once a message has
+	/// more than `max` empty, non-final frames in a row, or lift the limit
+	/// with `None`. The default is 1024.
+	///
+	/// Such frames make no progress, so a peer can keep a message open with
+	/// them at little cost. The connection is closed as set with
+	/// `set_protocol_error_policy`, with 1009 (Message Too Big) by default.
+	pub fn set_max_empty_frames(&mut self, max: Option<usize>) {
+		self.receiver.set_max_empty_frames(max);
+	}
+
+	/// Choose the close message sent when the remote endpoint violates the
+	/// protocol, e.g. to always close with 1011 or not to send one at all.
+	/// By default the code asked for by the RFC is sent.
+	/// See also `ClientBuilder::protocol_error_policy`.
+	///
+	/// This applies to violations detected by `recv_message`, `recv_dataframe`
+	/// and the methods built on them. Iterators from `incoming_messages` and
+	/// `incoming_dataframes` report the error without closing.
+	pub fn set_protocol_error_policy(&mut self, policy: ProtocolErrorPolicy) {
+		self.error_policy = policy;
+	}
+
+	/// Sends a close message as the protocol error policy says if `result`
+	/// failed because of a protocol violation, and tells the observer about
+	/// any error.
+	fn close_on_violation<T>(&mut self, result: WebSocketResult<T>) -> WebSocketResult<T> {
+		let result = self.observe_error(result);
+		if let Err(WebSocketError::ProtocolViolation(violation)) = result {
+			if let Some(code) = self.error_policy.close_code(violation) {
+				let close = CloseData::new(code, violation.to_string());
+				// the connection is failed anyway, the violation is the error to report
+				let _ = self.close_nowait(Some(close));
+			}
+		}
+		result
+	}
+
+	/// Tell `observer` about what goes on over this connection from now on,
+	/// or stop with `None`. See the `observer` module.
+	///
+	/// Clients accepted by a server with `Server::with_observer` or connected
+	/// with `ClientBuilder::observer` already have one. The observer is
+	/// dropped when the client is split.
+	pub fn set_observer(&mut self, observer: Option<Arc<dyn Observer>>) {
+		self.observer = observer;
+	}
+
+	/// Have `recv_message` answer pings itself instead of returning them, but
+	/// no more than `max_per_sec` of them per second, or stop with `None`.
+	/// See also `ClientBuilder::auto_pong_rate_limit`.
+	///
+	/// Pings beyond that rate are dropped without an answer, so a peer
+	/// flooding the connection with pings can't make the client send just as
+	/// many pongs back.
+	pub fn set_auto_pong_rate_limit(&mut self, max_per_sec: Option<u32>) {
+		self.auto_pong = max_per_sec.map(PongBudget::new);
+	}
+
+	pub(crate) fn set_pong_callback(&mut self, callback: Option<Arc<dyn Fn(&[u8]) + Send + Sync>>) {
+		self.pong_callback = callback;
+	}
+
+	/// Puts `message` back so that the next call to `recv_message` returns it,
+	/// e.g. after looking ahead at a message that should be handled elsewhere.
+	///
+	/// Messages put back several times come out in reverse order, like a stack.
+	/// `recv_message`, the methods built on it and `incoming_messages` all
+	/// return them first. When the client is split they are handed to the
+	/// `Reader`, whose `recv_message` and `incoming_messages` do the same.
+	pub fn unread_message(&mut self, message: OwnedMessage) {
+		self.unread.push(message);
+	}
+
+	/// Reads the next message without consuming it, so the next call to
+	/// `recv_message` returns it again.
+	pub fn peek_message(&mut self) -> WebSocketResult<&OwnedMessage> {
+		if self.unread.is_empty() {
+			let message = self.read_message()?;
+			self.unread.push(message);
+		}
+		Ok(&self.unread[self.unread.len() - 1])
+	}
+
+	/// The type of the next message, told by the first byte of its frame
+	/// without reading the rest, e.g. to choose between `recv_fragment` and
+	/// `recv_message` for it.
+	///
+	/// Nothing is consumed, so the message is still returned by whichever
+	/// method reads next, and messages put back with `unread_message` come
+	/// first. `None` if the connection has been closed or if the next frame
+	/// doesn't start a message, which reading it will report as an error.
+	/// Note that a pong peeked this way is still skipped by `recv_message`
+	/// if pongs are swallowed.
+	pub fn peek_opcode(&mut self) -> WebSocketResult<Option<Type>> {
+		if let Some(message) = self.unread.last() {
+			return Ok(ws::Message::message_type(message));
+		}
+		let first = match self.stream.fill_buf()?.first() {
+			Some(&byte) => byte,
+			None => return Ok(None),
+		};
+		let opcode = match (Opcode::new(first & 0x0F), self.receiver.buffered_opcode()) {
+			// a control frame can come between the fragments of a message
+			(Some(opcode), Some(_)) if opcode as u8 >= 8 => Some(opcode),
+			(_, Some(buffered)) => Some(buffered),
+			(opcode, None) => opcode,
+		};
+		Ok(match opcode {
+			Some(Opcode::Text) => Some(Type::Text),
+			Some(Opcode::Binary) => Some(Type::Binary),
+			Some(Opcode::Close) => Some(Type::Close),
+			Some(Opcode::Ping) => Some(Type::Ping),
+			Some(Opcode::Pong) => Some(Type::Pong),
+			_ => None,
+		})
+	}
+
+	/// Closes the connection gracefully: sends a close message with `data`,
+	/// then reads until the remote endpoint answers with its own close message,
+	/// whose data is returned. Pings received meanwhile are answered and other
+	/// messages are discarded.
+	///
+	/// This blocks until the answer arrives, use `close_nowait` to send the
+	/// close message without waiting for it, or `close_with_timeout` on TCP
+	/// based clients to bound the wait.
+	pub fn close(&mut self, data: Option<CloseData>) -> WebSocketResult<Option<CloseData>> {
+		self.close_nowait(data)?;
+		loop {
+			match self.recv_message()? {
+				OwnedMessage::Close(data) => return Ok(data),
+				OwnedMessage::Ping(data) => self.send_message(&OwnedMessage::Pong(data))?,
+				_ => (),
+			}
+		}
+	}
+
+	/// Starts closing the connection by sending a close message with `data`,
+	/// while still receiving the messages the remote endpoint sent before it
+	/// answers, which `close` discards.
+	///
+	/// ```rust,no_run
+	/// use websocket::ClientBuilder;
+	/// use websocket::sync::client::Event;
+	///
+	/// let mut client = ClientBuilder::new("ws://localhost:3000")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// let mut closing = client.initiate_close(None).unwrap();
+	/// while let Event::Message(message) = closing.recv_message().unwrap() {
+	///     println!("still received {:?}", message);
+	/// }
+	/// ```
+	pub fn initiate_close(
+		&mut self,
+		data: Option<CloseData>,
+	) -> WebSocketResult<ClosingClient<'_, S>> {
+		self.close_nowait(data)?;
+		Ok(ClosingClient { client: self })
+	}
+
+	/// Sends a close message with `data` and flushes it, without waiting
+	/// for the remote endpoint to answer. Useful when tearing down a connection
+	/// after an error, where the other side might never answer.
+	///
+	/// The stream is left open, it is closed when the client is dropped.
+	pub fn close_nowait(&mut self, data: Option<CloseData>) -> WebSocketResult<()> {
+		self.send_message(&OwnedMessage::Close(data))?;
+		self.stream.get_mut().flush()?;
+		Ok(())
+	}
+
+	/// Reads the next message, reporting a close from the remote endpoint
+	/// as `Event::Closed` rather than as a message.
+	///
+	/// This makes telling a clean close apart from a broken connection
+	/// easy, since the latter is returned as an error.
+	///
+	/// ```rust,no_run
+	/// use websocket::ClientBuilder;
+	/// use websocket::sync::client::Event;
+	///
+	/// let mut client = ClientBuilder::new("ws://localhost:3000")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// loop {
+	///     match client.next_event().unwrap() {
+	///         Event::Message(message) => println!("{:?}", message),
+	///         Event::Closed(reason) => {
+	///             println!("closed: {:?}", reason);
+	///             break;
+	///         }
+	///     }
+	/// }
+	/// ```
+	pub fn next_event(&mut self) -> WebSocketResult<Event> {
+		Ok(match self.recv_message()? {
+			OwnedMessage::Close(data) => Event::Closed(data),
+			message => Event::Message(message),
+		})
+	}
+
+	/// The status codes of the last close message sent to and received from
+	/// the remote endpoint, in that order, e.g. to record why connections
+	/// were closed no matter which side started it.
+	///
+	/// A close message without a status code counts as 1005 (No Status
+	/// Received). Messages read through `incoming_messages` or
+	/// `incoming_dataframes` are not seen here.
+	pub fn close_codes(&self) -> (Option<u16>, Option<u16>) {
+		self.close_codes
+	}
+
+	/// The number of bytes sent to the remote endpoint so far, counting
+	/// the whole data frames including their headers.
+	pub fn bytes_sent(&self) -> u64 {
+		self.sender.bytes_sent()
+	}
+
+	/// The number of bytes received from the remote endpoint so far, counting
+	/// the whole data frames including their headers.
+	pub fn bytes_received(&self) -> u64 {
+		self.receiver.bytes_received()
 	}
 
 	/// Access the headers that were sent in the server's handshake response.
 	/// This is a catch all for headers other than protocols and extensions.
+	///
+	/// These are the headers exactly as parsed from the server's response,
+	/// the same ones the async `ClientBuilder` functions hand back next to
+	/// the client.
 	pub fn headers(&self) -> &Headers {
 		&self.headers
 	}
 
+	/// The cookies the server set in its handshake response, one entry
+	/// per `Set-Cookie` header, in their raw form.
+	pub fn server_cookies(&self) -> Vec<String> {
+		self.headers
+			.get::<SetCookie>()
+			.map(|c| c.0.clone())
+			.unwrap_or_default()
+	}
+
 	/// **If you supplied a protocol, you must check that it was accepted by
 	/// the server** using this function.
 	/// This is not done automatically because the terms of accepting a protocol
@@ -298,7 +1300,7 @@ where
 	/// let message = receiver.recv_message(&mut buf_reader).unwrap();
 	/// ```
 	pub fn reader_mut(&mut self) -> &mut dyn Read {
-		&mut self.stream
+		&mut *self.stream
 	}
 
 	/// Deconstruct the client into its underlying stream and
@@ -308,8 +1310,11 @@ where
 	/// these buffered bytes are returned in the form
 	///
 	/// `(byte_buffer: Vec<u8>, buffer_capacity: usize, buffer_position: usize)`
+	///
+	/// The slot the client holds in the accepting server's connection limit
+	/// is given back, unless it was taken out with `take_connection_guard`.
 	pub fn into_stream(self) -> (S, Option<(Vec<u8>, usize, usize)>) {
-		let (stream, buf, pos, cap) = self.stream.into_parts();
+		let (stream, buf, pos, cap) = self.stream.into_inner().into_parts();
 		(stream, Some((buf, pos, cap)))
 	}
 
@@ -350,7 +1355,9 @@ where
 	///# }
 	///```
 	pub fn incoming_messages<'a>(&'a mut self) -> MessageIterator<'a, Receiver, BufReader<S>> {
-		self.receiver.incoming_messages(&mut self.stream)
+		self.receiver
+			.incoming_messages(&mut *self.stream)
+			.with_unread(&mut self.unread)
 	}
 }
 
@@ -361,6 +1368,8 @@ where
 	/// Split this client into its constituent Sender and Receiver pair.
 	///
 	/// This allows the Sender and Receiver to be sent to different threads.
+	/// The observer set with `set_observer` is dropped, see the `observer`
+	/// module.
 	///
 	///```no_run
 	///# extern crate websocket;
@@ -389,17 +1398,1136 @@ where
 		Reader<<S as Splittable>::Reader>,
 		Writer<<S as Splittable>::Writer>,
 	)> {
-		let (stream, buf, pos, cap) = self.stream.into_parts();
+		#[cfg(feature = "server")]
+		let connection_guard = shared_guard(self.connection_guard);
+		#[cfg(not(feature = "server"))]
+		let connection_guard = None;
+		let (stream, buf, pos, cap) = self.stream.into_inner().into_parts();
 		let (read, write) = stream.split()?;
 		Ok((
 			Reader {
 				stream: BufReader::from_parts(read, buf, pos, cap),
 				receiver: self.receiver,
+				connection_guard: connection_guard.clone(),
+				unread: self.unread,
 			},
 			Writer {
 				stream: write,
 				sender: self.sender,
+				connection_guard,
 			},
 		))
 	}
 }
+
+/// Exposes the socket so the client can be registered with an event loop.
+///
+/// Note that data already buffered by the client does not show up as
+/// readiness on the socket, so keep reading until the client would block.
+#[cfg(unix)]
+impl<S> AsRawFd for Client<S>
+where
+	S: AsTcpStream + Stream,
+{
+	fn as_raw_fd(&self) -> RawFd {
+		self.stream.get_ref().as_tcp().as_raw_fd()
+	}
+}
+
+/// Exposes the socket so the client can be registered with an event loop.
+///
+/// Note that data already buffered by the client does not show up as
+/// readiness on the socket, so keep reading until the client would block.
+#[cfg(windows)]
+impl<S> AsRawSocket for Client<S>
+where
+	S: AsTcpStream + Stream,
+{
+	fn as_raw_socket(&self) -> RawSocket {
+		self.stream.get_ref().as_tcp().as_raw_socket()
+	}
+}
+
+impl<S> Client<S>
+where
+	S: TrySplit + Stream,
+{
+	/// Split the client into a reader and a writer if its stream allows it,
+	/// otherwise give the client back unchanged.
+	///
+	/// Unlike `split` this is available for SSL streams as well (which can't
+	/// be split), so generic code can attempt a split and fall back to using
+	/// the whole client. The observer is dropped by a successful split, like
+	/// with `split`.
+	///
+	/// ```rust,no_run
+	/// use websocket::ClientBuilder;
+	///
+	/// let client = ClientBuilder::new("wss://example.com")
+	///     .unwrap()
+	///     .connect_secure(None)
+	///     .unwrap();
+	///
+	/// match client.try_split() {
+	///     Ok((receiver, sender)) => { /* use separate threads */ }
+	///     Err(client) => { /* use the client from a single thread */ }
+	/// }
+	/// ```
+	#[allow(clippy::result_large_err)]
+	pub fn try_split(
+		self,
+	) -> Result<
+		(
+			Reader<<S as TrySplit>::Reader>,
+			Writer<<S as TrySplit>::Writer>,
+		),
+		Self,
+	> {
+		let Client {
+			stream,
+			headers,
+			sender,
+			receiver,
+			#[cfg(feature = "server")]
+			connection_guard,
+			unread,
+			swallow_pongs,
+			pong_callback,
+			auto_pong,
+			error_policy,
+			strict_close,
+			fragments,
+			close_codes,
+			observer,
+			nonblocking,
+			next_ping,
+		} = self;
+		let close_on_drop = stream.close_on_drop;
+		let (stream, buf, pos, cap) = stream.into_inner().into_parts();
+		match stream.try_split() {
+			Ok((read, write)) => {
+				#[cfg(feature = "server")]
+				let connection_guard = shared_guard(connection_guard);
+				#[cfg(not(feature = "server"))]
+				let connection_guard = None;
+				Ok((
+					Reader {
+						stream: BufReader::from_parts(read, buf, pos, cap),
+						receiver,
+						connection_guard: connection_guard.clone(),
+						unread,
+					},
+					Writer {
+						stream: write,
+						sender,
+						connection_guard,
+					},
+				))
+			}
+			Err(stream) => {
+				let mut stream = ClientStream::new(BufReader::from_parts(stream, buf, pos, cap));
+				stream.close_on_drop = close_on_drop;
+				Err(Client {
+					stream,
+					headers,
+					sender,
+					receiver,
+					#[cfg(feature = "server")]
+					connection_guard,
+					unread,
+					swallow_pongs,
+					pong_callback,
+					auto_pong,
+					error_policy,
+					strict_close,
+					fragments,
+					close_codes,
+					observer,
+					nonblocking,
+					next_ping,
+				})
+			}
+		}
+	}
+}
+
+/// The connection slot of a client being split, shared by its `Reader` and
+/// `Writer`.
+#[cfg(feature = "server")]
+fn shared_guard(guard: Option<ConnectionGuard>) -> Option<Arc<dyn Any + Send + Sync>> {
+	guard.map(|guard| Arc::new(guard) as Arc<dyn Any + Send + Sync>)
+}
+
+/// The buffered stream of a client, which sends a close message with 1001
+/// (Going Away) when dropped while armed by `Client::set_close_on_drop`.
+struct ClientStream<S>
+where
+	S: Stream,
+{
+	/// Only `None` once taken out by `into_inner`.
+	stream: Option<BufReader<S>>,
+	/// Whether the close message is masked, and how to keep writing it from
+	/// blocking.
+	close_on_drop: Option<(bool, fn(&S) -> IoResult<()>)>,
+}
+
+impl<S> ClientStream<S>
+where
+	S: Stream,
+{
+	fn new(stream: BufReader<S>) -> Self {
+		ClientStream {
+			stream: Some(stream),
+			close_on_drop: None,
+		}
+	}
+
+	/// Takes the stream out without sending anything.
+	fn into_inner(mut self) -> BufReader<S> {
+		self.stream.take().expect("stream taken out twice")
+	}
+}
+
+impl<S> Deref for ClientStream<S>
+where
+	S: Stream,
+{
+	type Target = BufReader<S>;
+
+	fn deref(&self) -> &BufReader<S> {
+		self.stream.as_ref().expect("stream taken out")
+	}
+}
+
+impl<S> DerefMut for ClientStream<S>
+where
+	S: Stream,
+{
+	fn deref_mut(&mut self) -> &mut BufReader<S> {
+		self.stream.as_mut().expect("stream taken out")
+	}
+}
+
+impl<S> Drop for ClientStream<S>
+where
+	S: Stream,
+{
+	fn drop(&mut self) {
+		if let (Some(stream), Some((mask, unblock))) = (self.stream.as_mut(), self.close_on_drop) {
+			// there is nobody left to report a failure to
+			if unblock(stream.get_ref()).is_ok() {
+				let close = OwnedMessage::Close(Some(CloseData::new(1001, String::new())));
+				let _ = Sender::new(mask).send_message(stream.get_mut(), &close);
+				let _ = stream.get_mut().flush();
+			}
+		}
+	}
+}
+
+/// A token bucket limiting how many pings are answered automatically,
+/// see `Client::set_auto_pong_rate_limit`.
+struct PongBudget {
+	per_sec: u32,
+	tokens: f64,
+	refilled: Instant,
+}
+
+impl PongBudget {
+	fn new(per_sec: u32) -> Self {
+		PongBudget {
+			per_sec,
+			tokens: per_sec as f64,
+			refilled: Instant::now(),
+		}
+	}
+
+	/// Take a token if one is left, after adding the ones earned since the
+	/// last call. At most a second worth of tokens is kept.
+	fn take(&mut self) -> bool {
+		let now = Instant::now();
+		let earned = now.duration_since(self.refilled).as_secs_f64() * self.per_sec as f64;
+		self.tokens = (self.tokens + earned).min(self.per_sec as f64);
+		self.refilled = now;
+		if self.tokens < 1.0 {
+			return false;
+		}
+		self.tokens -= 1.0;
+		true
+	}
+}
+
+/// The status code in the payload of a close frame, see `ws::Message::close_code`.
+fn frame_close_code(payload: &[u8]) -> u16 {
+	match payload {
+		[high, low, ..] => u16::from_be_bytes([*high, *low]),
+		_ => 1005,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::TcpListener;
+
+	/// A client connected over TCP, along with the socket of its peer.
+	fn connected_pair(out_mask: bool) -> (Client<TcpStream>, TcpStream) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let (peer, _) = listener.accept().unwrap();
+		let client = Client::unchecked(BufReader::new(stream), Headers::new(), out_mask, false);
+		(client, peer)
+	}
+
+	#[test]
+	fn is_connected_detects_closed_peer() {
+		let (client, peer) = connected_pair(true);
+		assert!(client.is_connected());
+
+		// pending data doesn't get consumed by the check
+		(&peer).write_all(&[0x81, 0x00]).unwrap();
+		assert!(client.is_connected());
+		assert!(client.is_connected());
+
+		drop(peer);
+		let mut client = client;
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text(String::new())
+		);
+		assert!(!client.is_connected());
+	}
+
+	#[test]
+	fn is_connected_sees_buffered_data_and_keeps_read_timeout() {
+		let (mut client, peer) = connected_pair(true);
+		let timeout = Some(Duration::from_secs(7));
+		client.stream_ref().as_tcp().set_read_timeout(timeout).unwrap();
+
+		// both messages end up in the client's buffer on the first read
+		(&peer).write_all(&[0x81, 0x00, 0x81, 0x00]).unwrap();
+		drop(peer);
+		client.recv_message().unwrap();
+		assert!(client.is_connected());
+		assert_eq!(client.stream_ref().as_tcp().read_timeout().unwrap(), timeout);
+		assert!(!client.is_nonblocking());
+	}
+
+	#[test]
+	fn is_nonblocking_follows_set_nonblocking() {
+		let (mut client, _peer) = connected_pair(true);
+		assert!(!client.is_nonblocking());
+
+		client.set_nonblocking(true).unwrap();
+		assert!(client.is_nonblocking());
+		match client.recv_message() {
+			Err(WebSocketError::IoError(ref e)) if e.kind() == ErrorKind::WouldBlock => (),
+			other => panic!("expected the read to would-block, got {:?}", other),
+		}
+		client.set_nonblocking(false).unwrap();
+		assert!(!client.is_nonblocking());
+	}
+
+	#[test]
+	#[cfg(unix)]
+	fn exposes_raw_fd() {
+		let (client, _peer) = connected_pair(true);
+		assert_eq!(client.as_raw_fd(), client.stream_ref().as_raw_fd());
+	}
+
+	#[test]
+	#[cfg(feature = "keepalive")]
+	fn set_keepalive_toggles_probes() {
+		let (client, _peer) = connected_pair(true);
+		let socket = SockRef::from(client.stream_ref());
+
+		let params = TcpKeepalive::new().with_time(Duration::from_secs(30));
+		client.set_keepalive(Some(params)).unwrap();
+		assert!(socket.keepalive().unwrap());
+		client.set_keepalive(None).unwrap();
+		assert!(!socket.keepalive().unwrap());
+	}
+
+	#[test]
+	fn unread_message_comes_back_first() {
+		use crate::message::Message;
+		use crate::stream::ReadWritePair;
+		use crate::ws::Message as MessageTrait;
+		use std::io::Cursor;
+
+		let mut incoming = Vec::new();
+		Message::text("wire")
+			.serialize(&mut incoming, false)
+			.unwrap();
+		let stream = ReadWritePair(Cursor::new(incoming), Cursor::new(Vec::new()));
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+
+		assert_eq!(
+			client.peek_message().unwrap(),
+			&OwnedMessage::Text("wire".to_string())
+		);
+		let wire = client.recv_message().unwrap();
+		client.unread_message(wire);
+		client.unread_message(OwnedMessage::Binary(vec![1]));
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Binary(vec![1])
+		);
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("wire".to_string())
+		);
+		assert!(client.recv_message().is_err());
+	}
+
+	#[test]
+	fn split_keeps_peeked_messages() {
+		let (mut client, peer) = connected_pair(true);
+		let mut peer = Client::unchecked(BufReader::new(peer), Headers::new(), false, true);
+		peer.send_message(&OwnedMessage::Text("wire".to_string()))
+			.unwrap();
+		peer.send_message(&OwnedMessage::Binary(vec![1])).unwrap();
+
+		assert_eq!(
+			client.peek_message().unwrap(),
+			&OwnedMessage::Text("wire".to_string())
+		);
+		client.unread_message(OwnedMessage::Ping(vec![]));
+		let (mut reader, _writer) = client.split().unwrap();
+		assert_eq!(reader.recv_message().unwrap(), OwnedMessage::Ping(vec![]));
+		let mut buf = Vec::new();
+		assert_eq!(reader.recv_message_into(&mut buf).unwrap(), Type::Text);
+		assert_eq!(buf, b"wire");
+		assert_eq!(reader.recv_message().unwrap(), OwnedMessage::Binary(vec![1]));
+	}
+
+	#[test]
+	fn peek_opcode_leaves_the_message() {
+		use crate::message::Message;
+		use crate::stream::ReadWritePair;
+		use crate::ws::Message as MessageTrait;
+		use std::io::Cursor;
+
+		let mut incoming = Vec::new();
+		Message::binary(vec![1, 2])
+			.serialize(&mut incoming, false)
+			.unwrap();
+		incoming.push(0x80);
+		let stream = ReadWritePair(Cursor::new(incoming), Cursor::new(Vec::new()));
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+
+		assert_eq!(client.peek_opcode().unwrap(), Some(Type::Binary));
+		assert_eq!(client.peek_opcode().unwrap(), Some(Type::Binary));
+		client.unread_message(OwnedMessage::Ping(vec![]));
+		assert_eq!(client.peek_opcode().unwrap(), Some(Type::Ping));
+		client.recv_message().unwrap();
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Binary(vec![1, 2])
+		);
+		// a lone continuation frame doesn't start a message
+		assert_eq!(client.peek_opcode().unwrap(), None);
+		client.recv_dataframe().unwrap_err();
+		assert_eq!(client.peek_opcode().unwrap(), None);
+
+		// a ping between the fragments of a message
+		let mut incoming = Vec::new();
+		incoming.extend_from_slice(b"\x01\x02he");
+		incoming.extend_from_slice(b"\x89\x01!");
+		incoming.extend_from_slice(b"\x89\x01?");
+		incoming.extend_from_slice(b"\x80\x03llo");
+		let stream = ReadWritePair(Cursor::new(incoming), Cursor::new(Vec::new()));
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+		assert_eq!(client.peek_opcode().unwrap(), Some(Type::Text));
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Ping(b"!".to_vec())
+		);
+		assert_eq!(client.peek_opcode().unwrap(), Some(Type::Ping));
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Ping(b"?".to_vec())
+		);
+		assert_eq!(client.peek_opcode().unwrap(), Some(Type::Text));
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("hello".to_string())
+		);
+	}
+
+	#[test]
+	fn recv_message_into_reuses_the_buffer() {
+		use crate::stream::ReadWritePair;
+		use std::io::Cursor;
+
+		let mut incoming = Vec::new();
+		incoming.extend_from_slice(b"\x01\x02he");
+		incoming.extend_from_slice(b"\x89\x01!");
+		incoming.extend_from_slice(b"\x80\x03llo");
+		incoming.extend_from_slice(b"\x82\x02\x01\x02");
+		incoming.extend_from_slice(b"\x81\x01\xff");
+		let stream = ReadWritePair(Cursor::new(incoming), Cursor::new(Vec::new()));
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+
+		let mut buf = Vec::with_capacity(64);
+		assert_eq!(client.recv_message_into(&mut buf).unwrap(), Type::Ping);
+		assert_eq!(buf, b"!");
+		assert_eq!(client.recv_message_into(&mut buf).unwrap(), Type::Text);
+		assert_eq!(buf, b"hello");
+		assert_eq!(client.recv_message_into(&mut buf).unwrap(), Type::Binary);
+		assert_eq!(buf, [1, 2]);
+		assert_eq!(buf.capacity(), 64);
+
+		client.unread_message(OwnedMessage::Close(Some(CloseData::new(
+			1000,
+			"bye".to_string(),
+		))));
+		assert_eq!(client.recv_message_into(&mut buf).unwrap(), Type::Close);
+		assert_eq!(buf, b"\x03\xe8bye");
+		// text is still checked to be UTF-8
+		assert!(client.recv_message_into(&mut buf).is_err());
+	}
+
+	#[test]
+	fn initiate_close_keeps_messages_in_flight() {
+		use crate::message::Message;
+		use crate::stream::ReadWritePair;
+		use crate::ws::Message as MessageTrait;
+		use std::io::Cursor;
+
+		let mut incoming = Vec::new();
+		Message::text("late")
+			.serialize(&mut incoming, false)
+			.unwrap();
+		Message::ping(vec![1])
+			.serialize(&mut incoming, false)
+			.unwrap();
+		Message::close_because(1000, "")
+			.serialize(&mut incoming, false)
+			.unwrap();
+		let mut wire = Vec::new();
+		{
+			let stream = ReadWritePair(Cursor::new(incoming), &mut wire);
+			let mut client =
+				Client::unchecked(BufReader::new(stream), Headers::new(), false, false);
+			let mut closing = client.initiate_close(None).unwrap();
+			assert_eq!(
+				closing.recv_message().unwrap(),
+				Event::Message(OwnedMessage::Text("late".to_string()))
+			);
+			assert_eq!(
+				closing.recv_message().unwrap(),
+				Event::Closed(Some(CloseData::new(1000, String::new())))
+			);
+			assert_eq!(client.close_codes(), (Some(1005), Some(1000)));
+		}
+		// the close message went out first, then the pong
+		assert_eq!(wire, b"\x88\x00\x8a\x01\x01");
+	}
+
+	#[test]
+	fn close_on_drop_sends_going_away() {
+		let connect = || {
+			let (mut client, peer) = connected_pair(false);
+			client.set_close_on_drop(true);
+			(client, peer)
+		};
+		let read_all = |mut peer: TcpStream| {
+			let mut wire = Vec::new();
+			peer.read_to_end(&mut wire).unwrap();
+			wire
+		};
+
+		let (client, peer) = connect();
+		drop(client);
+		assert_eq!(read_all(peer), b"\x88\x02\x03\xe9");
+
+		// not again after closing, nor after taking the stream out
+		let (mut client, peer) = connect();
+		client.close_nowait(None).unwrap();
+		drop(client);
+		assert_eq!(read_all(peer), b"\x88\x00");
+		let (client, peer) = connect();
+		drop(client.into_stream());
+		assert!(read_all(peer).is_empty());
+
+		// dropping doesn't block on a peer that stopped reading
+		let (client, _peer) = connect();
+		client.set_nonblocking(true).unwrap();
+		while client.stream_ref().write(&[0; 65536]).is_ok() {}
+		client.set_nonblocking(false).unwrap();
+		drop(client);
+	}
+
+	#[test]
+	fn swallowed_pongs_reach_callback() {
+		use crate::message::Message;
+		use crate::stream::ReadWritePair;
+		use crate::ws::Message as MessageTrait;
+		use std::io::Cursor;
+		use std::sync::Mutex;
+
+		let mut incoming = Vec::new();
+		Message::pong(vec![1])
+			.serialize(&mut incoming, false)
+			.unwrap();
+		Message::text("data")
+			.serialize(&mut incoming, false)
+			.unwrap();
+		Message::pong(vec![2])
+			.serialize(&mut incoming, false)
+			.unwrap();
+		let stream = ReadWritePair(Cursor::new(incoming.clone()), Cursor::new(Vec::new()));
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+		assert_eq!(client.recv_message().unwrap(), OwnedMessage::Pong(vec![1]));
+
+		let stream = ReadWritePair(Cursor::new(incoming), Cursor::new(Vec::new()));
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+		let seen = Arc::new(Mutex::new(Vec::new()));
+		let record = seen.clone();
+		client.set_swallow_pongs(true);
+		client.on_pong(move |data| record.lock().unwrap().extend_from_slice(data));
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("data".to_string())
+		);
+		assert!(client.recv_message().is_err());
+		assert_eq!(*seen.lock().unwrap(), vec![1, 2]);
+	}
+
+	#[test]
+	fn strict_close_rejects_trailing_bytes() {
+		use crate::message::Message;
+		use crate::result::WebSocketError;
+		use crate::stream::ReadWritePair;
+		use crate::ws::Message as MessageTrait;
+		use std::io::Cursor;
+
+		let mut incoming = Vec::new();
+		Message::close().serialize(&mut incoming, false).unwrap();
+		incoming.extend_from_slice(b"garbage");
+		let stream = ReadWritePair(Cursor::new(incoming.clone()), Cursor::new(Vec::new()));
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+		assert_eq!(client.recv_message().unwrap(), OwnedMessage::Close(None));
+
+		let stream = ReadWritePair(Cursor::new(incoming), Cursor::new(Vec::new()));
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+		client.set_strict_close(true);
+		match client.recv_message() {
+			Err(WebSocketError::ProtocolViolation(ProtocolViolation::DataAfterClose)) => (),
+			other => panic!("expected a violation, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn recv_fragment_answers_pings() {
+		use crate::message::{Message, MessageFragment};
+		use crate::stream::ReadWritePair;
+		use crate::ws::Message as MessageTrait;
+		use std::io::Cursor;
+
+		let mut incoming = Vec::new();
+		Message::text("streamed")
+			.serialize_fragmented(&mut incoming, false, 5)
+			.unwrap();
+		Message::ping(vec![3])
+			.serialize(&mut incoming, false)
+			.unwrap();
+		Message::close().serialize(&mut incoming, false).unwrap();
+		let stream = ReadWritePair(Cursor::new(incoming), Cursor::new(Vec::new()));
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), false, false);
+
+		let text = |data: &str, is_final| {
+			Fragment::Data(MessageFragment {
+				opcode: Type::Text,
+				data: data.as_bytes().to_vec(),
+				is_final,
+			})
+		};
+		assert_eq!(client.recv_fragment().unwrap(), text("strea", false));
+		assert_eq!(client.recv_fragment().unwrap(), text("med", true));
+		assert_eq!(
+			client.recv_fragment().unwrap(),
+			Fragment::Control(OwnedMessage::Close(None))
+		);
+
+		let mut pong = Vec::new();
+		Message::pong(vec![3]).serialize(&mut pong, false).unwrap();
+		assert_eq!((client.into_stream().0).1.into_inner(), pong);
+	}
+
+	#[test]
+	fn max_total_frames_closes_with_policy_violation() {
+		use crate::message::Message;
+		use crate::result::WebSocketError;
+		use crate::stream::ReadWritePair;
+		use crate::ws::Message as MessageTrait;
+		use std::io::Cursor;
+
+		let mut incoming = Vec::new();
+		Message::text("split")
+			.serialize_fragmented(&mut incoming, false, 2)
+			.unwrap();
+		let stream = ReadWritePair(Cursor::new(incoming), Cursor::new(Vec::new()));
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), false, false);
+		client.set_max_total_frames(Some(2));
+		match client.recv_message() {
+			Err(WebSocketError::ProtocolViolation(ProtocolViolation::TooManyFrames)) => (),
+			other => panic!("expected a violation, got {:?}", other),
+		}
+
+		let mut close = Vec::new();
+		Message::close_because(1008, "Exceeded maximum number of data frames")
+			.serialize(&mut close, false)
+			.unwrap();
+		assert_eq!((client.into_stream().0).1.into_inner(), close);
+	}
+
+	#[test]
+	fn protocol_error_policy_picks_close_code() {
+		use crate::message::Message;
+		use crate::stream::ReadWritePair;
+		use crate::ws::Message as MessageTrait;
+		use std::io::Cursor;
+
+		fn only_utf8(violation: ProtocolViolation) -> Option<u16> {
+			match violation {
+				ProtocolViolation::InvalidUtf8 => Some(1007),
+				_ => None,
+			}
+		}
+
+		let policies = [
+			(ProtocolErrorPolicy::Rfc, Some(1002)),
+			(ProtocolErrorPolicy::Always(1011), Some(1011)),
+			(ProtocolErrorPolicy::Silent, None),
+			(ProtocolErrorPolicy::Custom(only_utf8), None),
+		];
+		for &(policy, code) in &policies {
+			// a frame with a reserved opcode
+			let stream = ReadWritePair(Cursor::new(vec![0x83, 0x00]), Cursor::new(Vec::new()));
+			let mut client =
+				Client::unchecked(BufReader::new(stream), Headers::new(), false, false);
+			client.set_protocol_error_policy(policy);
+			match client.recv_message() {
+				Err(WebSocketError::ProtocolViolation(ProtocolViolation::InvalidOpcode)) => (),
+				other => panic!("expected a violation, got {:?}", other),
+			}
+
+			let mut close = Vec::new();
+			if let Some(code) = code {
+				Message::close_because(code, "Invalid data frame opcode")
+					.serialize(&mut close, false)
+					.unwrap();
+			}
+			assert_eq!((client.into_stream().0).1.into_inner(), close);
+		}
+	}
+
+	#[test]
+	fn close_codes_track_both_directions() {
+		use crate::message::Message;
+		use crate::stream::ReadWritePair;
+		use crate::ws::Message as MessageTrait;
+		use std::io::Cursor;
+
+		let mut incoming = Vec::new();
+		Message::text("bye")
+			.serialize(&mut incoming, false)
+			.unwrap();
+		Message::close().serialize(&mut incoming, false).unwrap();
+		Message::close_because(1001, "")
+			.serialize(&mut incoming, false)
+			.unwrap();
+		let stream = ReadWritePair(Cursor::new(incoming), Cursor::new(Vec::new()));
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), false, false);
+		assert_eq!(client.close_codes(), (None, None));
+
+		client.recv_message().unwrap();
+		client
+			.send_message(&Message::close_because(1000, ""))
+			.unwrap();
+		assert_eq!(client.close_codes(), (Some(1000), None));
+		client.recv_message().unwrap();
+		assert_eq!(client.close_codes(), (Some(1000), Some(1005)));
+		client.recv_dataframe().unwrap();
+		client.send_message(&OwnedMessage::Close(None)).unwrap();
+		assert_eq!(client.close_codes(), (Some(1005), Some(1001)));
+	}
+
+	#[test]
+	fn send_with_fixed_mask() {
+		use crate::message::Message;
+		use crate::stream::ReadWritePair;
+		use std::io::Cursor;
+
+		let stream = ReadWritePair(Cursor::new(Vec::new()), Vec::new());
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), false, false);
+		let mask = [0x37, 0xfa, 0x21, 0x3d];
+		client
+			.send_message_with_mask(&Message::text("Hello"), mask)
+			.unwrap();
+		client
+			.send_dataframe_with_mask(&DataFrame::new(true, Opcode::Pong, vec![]), mask)
+			.unwrap();
+
+		// the masked "Hello" from RFC 6455 section 5.7
+		let expected = [
+			0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58, //
+			0x8a, 0x80, 0x37, 0xfa, 0x21, 0x3d,
+		];
+		assert_eq!(client.stream_ref().1, expected);
+		assert_eq!(client.bytes_sent(), expected.len() as u64);
+	}
+
+	#[test]
+	fn auto_pong_is_rate_limited() {
+		use crate::message::Message;
+		use crate::stream::ReadWritePair;
+		use crate::ws::Message as MessageTrait;
+		use std::io::Cursor;
+
+		let mut incoming = Vec::new();
+		for i in 0..5 {
+			Message::ping(vec![i])
+				.serialize(&mut incoming, false)
+				.unwrap();
+		}
+		Message::text("data")
+			.serialize(&mut incoming, false)
+			.unwrap();
+		let stream = ReadWritePair(Cursor::new(incoming), Cursor::new(Vec::new()));
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), false, false);
+		client.set_auto_pong_rate_limit(Some(2));
+
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("data".to_string())
+		);
+		// the flood is answered with no more pongs than the limit allows
+		let mut pongs = Vec::new();
+		Message::pong(vec![0]).serialize(&mut pongs, false).unwrap();
+		Message::pong(vec![1]).serialize(&mut pongs, false).unwrap();
+		assert_eq!((client.into_stream().0).1.into_inner(), pongs);
+	}
+
+	#[test]
+	fn close_with_timeout_gives_up_on_silent_peer() {
+		use crate::message::Message;
+		use crate::ws::Message as MessageTrait;
+
+		let (mut client, mut peer) = connected_pair(false);
+
+		let started = Instant::now();
+		client
+			.close_with_timeout(None, Duration::from_millis(100))
+			.unwrap();
+		assert!(started.elapsed() >= Duration::from_millis(100));
+
+		// the close message, then the end of the stream
+		let mut received = Vec::new();
+		peer.read_to_end(&mut received).unwrap();
+		let mut close = Vec::new();
+		Message::close().serialize(&mut close, false).unwrap();
+		assert_eq!(received, close);
+	}
+
+	#[test]
+	fn ping_roundtrip_keeps_messages_read_meanwhile() {
+		let (mut client, peer) = connected_pair(true);
+		let peer = std::thread::spawn(move || {
+			let mut peer = Client::unchecked(BufReader::new(peer), Headers::new(), false, true);
+			peer.send_message(&OwnedMessage::Text("a".to_string()))
+				.unwrap();
+			peer.send_message(&OwnedMessage::Pong(b"health".to_vec()))
+				.unwrap();
+			let ping = match peer.recv_message().unwrap() {
+				OwnedMessage::Ping(data) => data,
+				other => panic!("expected a ping, got {:?}", other),
+			};
+			peer.send_message(&OwnedMessage::Text("b".to_string()))
+				.unwrap();
+			peer.send_message(&OwnedMessage::Pong(ping.clone()))
+				.unwrap();
+			(ping, peer)
+		});
+
+		client.unread_message(OwnedMessage::Text("first".to_string()));
+		client
+			.ping_roundtrip(b"health", Duration::from_secs(5))
+			.unwrap();
+		// keep the peer around, silent
+		let (ping, _peer) = peer.join().unwrap();
+		assert_eq!(ping, [&b"health"[..], &0u64.to_be_bytes()].concat());
+		for text in &["first", "a", "b"] {
+			assert_eq!(
+				client.recv_message().unwrap(),
+				OwnedMessage::Text(text.to_string())
+			);
+		}
+
+		let error = client
+			.ping_roundtrip(b"health", Duration::from_millis(50))
+			.unwrap_err();
+		match error {
+			WebSocketError::IoError(e) => assert_eq!(e.kind(), ErrorKind::TimedOut),
+			other => panic!("expected a timeout, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn ping_roundtrip_messages_survive_split() {
+		let (mut client, peer) = connected_pair(true);
+		let peer = std::thread::spawn(move || {
+			let mut peer = Client::unchecked(BufReader::new(peer), Headers::new(), false, true);
+			let ping = match peer.recv_message().unwrap() {
+				OwnedMessage::Ping(data) => data,
+				other => panic!("expected a ping, got {:?}", other),
+			};
+			peer.send_message(&OwnedMessage::Text("during".to_string()))
+				.unwrap();
+			peer.send_message(&OwnedMessage::Pong(ping)).unwrap();
+			peer.send_message(&OwnedMessage::Text("after".to_string()))
+				.unwrap();
+			peer
+		});
+
+		client
+			.ping_roundtrip(b"health", Duration::from_secs(5))
+			.unwrap();
+		let _peer = peer.join().unwrap();
+		let (mut reader, _writer) = client.split().unwrap();
+		let mut messages = reader.incoming_messages();
+		for text in &["during", "after"] {
+			assert_eq!(
+				messages.next().unwrap().unwrap(),
+				OwnedMessage::Text(text.to_string())
+			);
+		}
+	}
+
+	#[test]
+	fn idle_timeout_pings_then_closes() {
+		let (mut client, peer) = connected_pair(true);
+		let peer = std::thread::spawn(move || {
+			let mut peer = Client::unchecked(BufReader::new(peer), Headers::new(), false, true);
+			peer.send_message(&OwnedMessage::Text("a".to_string()))
+				.unwrap();
+			(peer.recv_message().unwrap(), peer.recv_message().unwrap())
+		});
+
+		let (ping_after, close_after) = (Duration::from_millis(50), Duration::from_millis(50));
+		assert_eq!(
+			client
+				.recv_message_with_idle_timeout(ping_after, close_after)
+				.unwrap(),
+			OwnedMessage::Text("a".to_string())
+		);
+		match client.recv_message_with_idle_timeout(ping_after, close_after) {
+			Err(WebSocketError::IoError(e)) => assert_eq!(e.kind(), ErrorKind::TimedOut),
+			other => panic!("expected a timeout, got {:?}", other),
+		}
+
+		let (ping, close) = peer.join().unwrap();
+		assert_eq!(ping, OwnedMessage::Ping(Vec::new()));
+		match close {
+			OwnedMessage::Close(Some(close)) => assert_eq!(close.status_code, 1001),
+			other => panic!("expected a close, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn idle_timeout_keeps_checking_after_swallowed_pong() {
+		let (mut client, peer) = connected_pair(true);
+		client.set_swallow_pongs(true);
+		let peer = std::thread::spawn(move || {
+			let mut peer = Client::unchecked(BufReader::new(peer), Headers::new(), false, true);
+			// answer the first ping only, then go silent
+			let ping = peer.recv_message().unwrap();
+			assert_eq!(ping, OwnedMessage::Ping(Vec::new()));
+			peer.send_message(&OwnedMessage::Pong(Vec::new())).unwrap();
+			(peer.recv_message().unwrap(), peer.recv_message().unwrap())
+		});
+
+		let (ping_after, close_after) = (Duration::from_millis(50), Duration::from_millis(50));
+		match client.recv_message_with_idle_timeout(ping_after, close_after) {
+			Err(WebSocketError::IoError(e)) => assert_eq!(e.kind(), ErrorKind::TimedOut),
+			other => panic!("expected a timeout, got {:?}", other),
+		}
+
+		let (ping, close) = peer.join().unwrap();
+		assert_eq!(ping, OwnedMessage::Ping(Vec::new()));
+		match close {
+			OwnedMessage::Close(Some(close)) => assert_eq!(close.status_code, 1001),
+			other => panic!("expected a close, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn drain_incoming_discards_pending_messages() {
+		use crate::message::Message;
+		use crate::ws::Message as MessageTrait;
+
+		let (mut client, mut peer) = connected_pair(true);
+
+		let mut pending = Vec::new();
+		Message::text("late")
+			.serialize(&mut pending, false)
+			.unwrap();
+		Message::ping(vec![7])
+			.serialize(&mut pending, false)
+			.unwrap();
+		Message::binary(vec![1, 2, 3])
+			.serialize(&mut pending, false)
+			.unwrap();
+		peer.write_all(&pending).unwrap();
+
+		assert_eq!(
+			client
+				.drain_incoming(Some(Duration::from_millis(200)))
+				.unwrap(),
+			2
+		);
+		assert_eq!(client.drain_incoming(None).unwrap(), 0);
+
+		// the ping was answered
+		let mut pong = Vec::new();
+		Message::pong(vec![7]).serialize(&mut pong, false).unwrap();
+		let mut answer = vec![0; pong.len() + 4];
+		peer.read_exact(&mut answer).unwrap();
+		assert_eq!(answer[0], pong[0]);
+
+		Message::text("next").serialize(&mut peer, false).unwrap();
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("next".to_string())
+		);
+	}
+
+	#[test]
+	fn try_split_falls_back() {
+		use crate::message::Message;
+		use crate::stream::ReadWritePair;
+		use crate::ws::Message as MessageTrait;
+		use std::io::Cursor;
+
+		/// A stream that cannot be split
+		struct Whole(ReadWritePair<Cursor<Vec<u8>>, Vec<u8>>);
+		impl Read for Whole {
+			fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+				self.0.read(buf)
+			}
+		}
+		impl Write for Whole {
+			fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+				self.0.write(buf)
+			}
+			fn flush(&mut self) -> IoResult<()> {
+				self.0.flush()
+			}
+		}
+		impl TrySplit for Whole {
+			type Reader = ::std::io::Empty;
+			type Writer = ::std::io::Sink;
+			fn try_split(self) -> Result<(Self::Reader, Self::Writer), Self> {
+				Err(self)
+			}
+		}
+
+		let mut input = Vec::new();
+		Message::text("one").serialize(&mut input, false).unwrap();
+		Message::text("two").serialize(&mut input, false).unwrap();
+
+		let stream = Whole(ReadWritePair(Cursor::new(input.clone()), Vec::new()));
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("one".to_string())
+		);
+		let mut client = match client.try_split() {
+			Ok(_) => panic!("stream should not be splittable"),
+			Err(client) => client,
+		};
+		// buffered data survives the failed attempt
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("two".to_string())
+		);
+
+		let stream = ReadWritePair(Cursor::new(input), Vec::new());
+		let client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+		let (mut reader, _writer) = match client.try_split() {
+			Ok(parts) => parts,
+			Err(_) => panic!("stream should be splittable"),
+		};
+		assert_eq!(
+			reader.recv_message().unwrap(),
+			OwnedMessage::Text("one".to_string())
+		);
+	}
+
+	#[test]
+	fn close_modes() {
+		use crate::message::Message;
+		use crate::stream::ReadWritePair;
+		use crate::ws::Message as MessageTrait;
+		use std::io::Cursor;
+
+		let mut expected = Vec::new();
+		Message::close_because(1001, "going away")
+			.serialize(&mut expected, false)
+			.unwrap();
+
+		// the other side never answers
+		let stream = ReadWritePair(Cursor::new(Vec::new()), Vec::new());
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), false, false);
+		client
+			.close_nowait(Some(CloseData::new(1001, "going away".to_string())))
+			.unwrap();
+		assert_eq!(client.stream_ref().1, expected);
+
+		let mut input = Vec::new();
+		Message::text("late").serialize(&mut input, false).unwrap();
+		Message::ping(vec![1]).serialize(&mut input, false).unwrap();
+		Message::close_because(1001, "")
+			.serialize(&mut input, false)
+			.unwrap();
+		let stream = ReadWritePair(Cursor::new(input), Vec::new());
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), false, false);
+		assert_eq!(
+			client
+				.close(Some(CloseData::new(1001, "going away".to_string())))
+				.unwrap(),
+			Some(CloseData::new(1001, String::new()))
+		);
+		Message::pong(vec![1])
+			.serialize(&mut expected, false)
+			.unwrap();
+		assert_eq!(client.stream_ref().1, expected);
+	}
+
+	#[test]
+	fn next_event_reports_close() {
+		use crate::message::Message;
+		use crate::stream::ReadWritePair;
+		use crate::ws::Message as MessageTrait;
+		use std::io::Cursor;
+
+		let mut input = Vec::new();
+		Message::text("hi").serialize(&mut input, false).unwrap();
+		Message::close_because(1000, "bye")
+			.serialize(&mut input, false)
+			.unwrap();
+		let stream = ReadWritePair(Cursor::new(input), Vec::new());
+		let mut client = Client::unchecked(BufReader::new(stream), Headers::new(), true, false);
+
+		assert_eq!(
+			client.next_event().unwrap(),
+			Event::Message(OwnedMessage::Text("hi".to_string()))
+		);
+		assert_eq!(
+			client.next_event().unwrap(),
+			Event::Closed(Some(CloseData::new(1000, "bye".to_string())))
+		);
+		assert!(client.next_event().is_err());
+	}
+}
@@ -0,0 +1,174 @@
+//! Use the codecs with blocking `std::io` streams.
+//!
+//! The codecs in this module's siblings implement `tokio`'s `Decoder` and
+//! `Encoder` traits, which don't depend on a runtime themselves. `BlockingFramed`
+//! drives them over any `Read + Write` stream, so the same decoding logic can
+//! be used whether or not the rest of an application is asynchronous.
+//!
+//! ```rust
+//! # extern crate websocket;
+//! # fn main() {
+//! use std::io::Cursor;
+//! use websocket::codec::blocking::BlockingFramed;
+//! use websocket::codec::ws::{Context, MessageCodec};
+//! use websocket::stream::ReadWritePair;
+//! use websocket::OwnedMessage;
+//!
+//! let incoming = Cursor::new(vec![0x81, 0x02, b'h', b'i']);
+//! let stream = ReadWritePair(incoming, Cursor::new(Vec::new()));
+//! let mut framed = BlockingFramed::new(stream, MessageCodec::default(Context::Client));
+//!
+//! assert_eq!(framed.recv().unwrap(), Some(OwnedMessage::Text("hi".to_string())));
+//! assert_eq!(framed.recv().unwrap(), None);
+//! # }
+//! ```
+
+use bytes::BytesMut;
+use std::io::{self, Read, Write};
+use tokio_codec::{Decoder, Encoder};
+
+/// How many bytes are read from the stream at a time.
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// A blocking counterpart to `tokio_codec::Framed`: decodes items read from
+/// `stream` and encodes items written to it with the codec `C`.
+pub struct BlockingFramed<S, C> {
+	stream: S,
+	codec: C,
+	read_buf: BytesMut,
+	write_buf: BytesMut,
+}
+
+impl<S, C> BlockingFramed<S, C> {
+	/// Frame `stream` with `codec`.
+	pub fn new(stream: S, codec: C) -> Self {
+		BlockingFramed {
+			stream,
+			codec,
+			read_buf: BytesMut::new(),
+			write_buf: BytesMut::new(),
+		}
+	}
+
+	/// Get a reference to the underlying stream.
+	pub fn get_ref(&self) -> &S {
+		&self.stream
+	}
+
+	/// Get a mutable reference to the underlying stream.
+	///
+	/// Reading from it directly skips whatever has already been buffered.
+	pub fn get_mut(&mut self) -> &mut S {
+		&mut self.stream
+	}
+
+	/// Get a reference to the codec.
+	pub fn codec(&self) -> &C {
+		&self.codec
+	}
+
+	/// Get a mutable reference to the codec.
+	pub fn codec_mut(&mut self) -> &mut C {
+		&mut self.codec
+	}
+
+	/// The bytes read from the stream but not decoded yet.
+	pub fn read_buffer(&self) -> &[u8] {
+		&self.read_buf
+	}
+
+	/// Get back the stream and the codec, along with the bytes read from the
+	/// stream but not decoded yet.
+	pub fn into_parts(self) -> (S, C, BytesMut) {
+		(self.stream, self.codec, self.read_buf)
+	}
+}
+
+impl<S, C> BlockingFramed<S, C>
+where
+	S: Read,
+	C: Decoder,
+{
+	/// Block until the next item has been decoded.
+	///
+	/// Returns `None` once the stream ends between items. If it ends
+	/// in the middle of one, the codec decides through `decode_eof`.
+	pub fn recv(&mut self) -> Result<Option<C::Item>, C::Error> {
+		loop {
+			if let Some(item) = self.codec.decode(&mut self.read_buf)? {
+				return Ok(Some(item));
+			}
+
+			let mut chunk = [0; READ_CHUNK_SIZE];
+			let read = loop {
+				match self.stream.read(&mut chunk) {
+					Ok(read) => break read,
+					Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+					Err(e) => return Err(e.into()),
+				}
+			};
+			if read == 0 {
+				if self.read_buf.is_empty() {
+					return Ok(None);
+				}
+				return self.codec.decode_eof(&mut self.read_buf);
+			}
+			self.read_buf.extend_from_slice(&chunk[..read]);
+		}
+	}
+}
+
+impl<S, C> BlockingFramed<S, C>
+where
+	S: Write,
+	C: Encoder,
+{
+	/// Encode `item` and write it to the stream, then flush the stream.
+	pub fn send(&mut self, item: C::Item) -> Result<(), C::Error> {
+		self.codec.encode(item, &mut self.write_buf)?;
+		let written = self.stream.write_all(&self.write_buf);
+		self.write_buf.clear();
+		written?;
+		self.stream.flush()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::codec::ws::{Context, DataFrameCodec, MessageCodec};
+	use crate::message::OwnedMessage;
+	use crate::stream::ReadWritePair;
+	use std::io::Cursor;
+
+	#[test]
+	fn blocking_framed_round_trip() {
+		let mut wire = Vec::new();
+		{
+			let stream = ReadWritePair(Cursor::new(Vec::new()), &mut wire);
+			let mut framed = BlockingFramed::new(stream, MessageCodec::default(Context::Server));
+			framed
+				.send(OwnedMessage::Text("hello".to_string()))
+				.unwrap();
+			framed.send(OwnedMessage::Binary(vec![0; 20_000])).unwrap();
+		}
+
+		let stream = ReadWritePair(Cursor::new(wire), Cursor::new(Vec::new()));
+		let mut framed = BlockingFramed::new(stream, MessageCodec::default(Context::Client));
+		assert_eq!(
+			framed.recv().unwrap(),
+			Some(OwnedMessage::Text("hello".to_string()))
+		);
+		assert_eq!(
+			framed.recv().unwrap(),
+			Some(OwnedMessage::Binary(vec![0; 20_000]))
+		);
+		assert_eq!(framed.recv().unwrap(), None);
+
+		// a frame cut short by the end of the stream is reported
+		let stream = ReadWritePair(Cursor::new(vec![0x82, 0x05, 1, 2]), Cursor::new(Vec::new()));
+		let mut framed = BlockingFramed::new(stream, DataFrameCodec::default(Context::Client));
+		assert!(framed.recv().is_err());
+	}
+}
@@ -2,6 +2,7 @@
 //!
 //! This module has both an `HttpClientCodec` for an async HTTP client and an
 //! `HttpServerCodec` for an async HTTP server.
+use crate::result::handshake_snippet;
 use bytes::BufMut;
 use bytes::BytesMut;
 use hyper;
@@ -69,6 +70,38 @@ fn split_off_http(src: &mut BytesMut) -> Option<BytesMut> {
 	}
 }
 
+impl HttpClientCodec {
+	/// Parse a response from `buf` without a stream, returning it along with
+	/// the bytes that follow it, e.g. the first websocket frames.
+	///
+	/// If `buf` doesn't hold a complete response yet, `None` is returned and
+	/// the leftover bytes are all of `buf`. This makes it possible to test
+	/// handshake handling without a `tokio` runtime.
+	///
+	///```rust
+	///# extern crate websocket;
+	///use websocket::r#async::HttpClientCodec;
+	///
+	///# fn main() {
+	///let (response, leftover) =
+	///    HttpClientCodec::decode_once(b"HTTP/1.1 101 Switching Protocols\r\n\r\n\x81\x00").unwrap();
+	///assert_eq!(response.unwrap().subject.0, 101);
+	///assert_eq!(&leftover[..], b"\x81\x00");
+	///
+	///let (response, leftover) = HttpClientCodec::decode_once(b"HTTP/1.1 101").unwrap();
+	///assert!(response.is_none());
+	///assert_eq!(&leftover[..], b"HTTP/1.1 101");
+	///# }
+	///```
+	pub fn decode_once(
+		buf: &[u8],
+	) -> Result<(Option<Incoming<RawStatus>>, BytesMut), HttpCodecError> {
+		let mut src = BytesMut::from(buf);
+		let item = HttpClientCodec.decode(&mut src)?;
+		Ok((item, src))
+	}
+}
+
 impl Encoder for HttpClientCodec {
 	type Item = Incoming<(Method, RequestUri)>;
 	type Error = io::Error;
@@ -102,7 +135,7 @@ impl Decoder for HttpClientCodec {
 						return Ok(None);
 					}
 					Err(hyper::Error::TooLarge) => return Ok(None),
-					Err(e) => return Err(e.into()),
+					Err(e) => return Err(HttpCodecError::Malformed(e, handshake_snippet(&buf))),
 					Ok(r) => r,
 				};
 				Ok(Some(res))
@@ -112,6 +145,40 @@ impl Decoder for HttpClientCodec {
 	}
 }
 
+/// An `HttpClientCodec` that fails with `hyper::Error::TooLarge` once the
+/// response takes more than `max` bytes before its headers end, see
+/// `ClientBuilder::max_response_header_bytes`.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct CappedHttpClientCodec {
+	pub(crate) max: usize,
+}
+
+impl Encoder for CappedHttpClientCodec {
+	type Item = Incoming<(Method, RequestUri)>;
+	type Error = io::Error;
+
+	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		HttpClientCodec.encode(item, dst)
+	}
+}
+
+impl Decoder for CappedHttpClientCodec {
+	type Item = Incoming<RawStatus>;
+	type Error = HttpCodecError;
+
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		let buffered = src.len();
+		match HttpClientCodec.decode(src)? {
+			// the headers end beyond what has been buffered
+			None if buffered >= self.max => Err(HttpCodecError::Http(hyper::Error::TooLarge)),
+			Some(_) if buffered - src.len() > self.max => {
+				Err(HttpCodecError::Http(hyper::Error::TooLarge))
+			}
+			item => Ok(item),
+		}
+	}
+}
+
 ///A codec that can be used with streams implementing `AsyncRead + AsyncWrite`
 ///that can serialize HTTP responses and deserialize HTTP requests. Using this
 ///with an async `TcpStream` will give you a very bare async HTTP server.
@@ -166,6 +233,36 @@ impl Decoder for HttpClientCodec {
 #[derive(Copy, Clone, Debug)]
 pub struct HttpServerCodec;
 
+impl HttpServerCodec {
+	/// Parse a request from `buf` without a stream, returning it along with
+	/// the bytes that follow it, e.g. a request body or early websocket frames.
+	///
+	/// If `buf` doesn't hold a complete request yet, `None` is returned and
+	/// the leftover bytes are all of `buf`. This makes it possible to test
+	/// handshake handling without a `tokio` runtime.
+	///
+	///```rust
+	///# extern crate websocket;
+	///# extern crate hyper;
+	///use websocket::r#async::HttpServerCodec;
+	///# use hyper::method::Method;
+	///
+	///# fn main() {
+	///let (request, leftover) =
+	///    HttpServerCodec::decode_once(b"GET /chat HTTP/1.1\r\nHost: example.com\r\n\r\nextra").unwrap();
+	///assert_eq!(request.unwrap().subject.0, Method::Get);
+	///assert_eq!(&leftover[..], b"extra");
+	///# }
+	///```
+	pub fn decode_once(
+		buf: &[u8],
+	) -> Result<(Option<Incoming<(Method, RequestUri)>>, BytesMut), HttpCodecError> {
+		let mut src = BytesMut::from(buf);
+		let item = HttpServerCodec.decode(&mut src)?;
+		Ok((item, src))
+	}
+}
+
 impl Encoder for HttpServerCodec {
 	type Item = Incoming<StatusCode>;
 	type Error = io::Error;
@@ -216,6 +313,9 @@ pub enum HttpCodecError {
 	Io(io::Error),
 	/// An error that occurs during the parsing of an HTTP request or response.
 	Http(hyper::Error),
+	/// A response that could not be parsed, along with the start of the data
+	/// received instead (lossily decoded).
+	Malformed(hyper::Error, String),
 }
 
 impl Display for HttpCodecError {
@@ -223,6 +323,7 @@ impl Display for HttpCodecError {
 		match self {
 			HttpCodecError::Io(e) => fmt.write_str(e.to_string().as_str()),
 			HttpCodecError::Http(e) => fmt.write_str(e.to_string().as_str()),
+			HttpCodecError::Malformed(e, received) => write!(fmt, "{}, received {:?}", e, received),
 		}
 	}
 }
@@ -232,6 +333,7 @@ impl Error for HttpCodecError {
 		match *self {
 			HttpCodecError::Io(ref error) => Some(error),
 			HttpCodecError::Http(ref error) => Some(error),
+			HttpCodecError::Malformed(ref error, _) => Some(error),
 		}
 	}
 }
@@ -311,4 +413,26 @@ mod tests {
 			});
 		runtime.block_on(f).unwrap();
 	}
+
+	#[test]
+	fn decode_once_without_a_stream() {
+		let request = b"GET /chat HTTP/1.1\r\nUpgrade: websocket\r\n\r\n\x81\x80";
+		let (parsed, leftover) = HttpServerCodec::decode_once(request).unwrap();
+		let parsed = parsed.unwrap();
+		assert_eq!(
+			parsed.subject.1,
+			RequestUri::AbsolutePath("/chat".to_string())
+		);
+		assert_eq!(
+			parsed.headers.get_raw("Upgrade").unwrap()[0],
+			b"websocket".to_vec()
+		);
+		assert_eq!(&leftover[..], b"\x81\x80");
+
+		let (parsed, leftover) = HttpServerCodec::decode_once(&request[..10]).unwrap();
+		assert!(parsed.is_none());
+		assert_eq!(&leftover[..], &request[..10]);
+
+		assert!(HttpClientCodec::decode_once(b"garbage\r\n\r\n").is_err());
+	}
 }
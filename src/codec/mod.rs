@@ -9,6 +9,9 @@
 //! encoding and decoding websocket messages (and dataframes if you want to go
 //! more low level) in the `ws` module.
 //! See it's module level documentation for more info.
+//!
+//! The `blocking` module lets these codecs be used with blocking streams as well.
 
+pub mod blocking;
 pub mod http;
 pub use websocket_base::codec::ws;
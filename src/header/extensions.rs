@@ -55,11 +55,12 @@ impl FromStr for Extension {
 			},
 			params: ext
 				.map(|x| {
-					let mut pair = x.splitn(1, '=').map(|x| x.trim().to_string());
+					// a parameter may come without a value, e.g. `client_max_window_bits`
+					let mut pair = x.splitn(2, '=').map(str::trim);
 
 					Parameter {
-						name: pair.next().unwrap(),
-						value: pair.next(),
+						name: pair.next().unwrap().to_string(),
+						value: pair.next().map(|v| v.trim_matches('"').to_string()),
 					}
 				})
 				.collect(),
@@ -147,6 +148,34 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn test_header_extensions_bare_parameter() {
+		let offer = Extension {
+			name: "permessage-deflate".to_string(),
+			params: vec![Parameter::new("client_max_window_bits".to_string(), None)],
+		};
+		assert_eq!(
+			offer.to_string(),
+			"permessage-deflate; client_max_window_bits"
+		);
+		assert_eq!(offer.to_string().parse::<Extension>().unwrap(), offer);
+
+		let value = vec![b"permessage-deflate; client_max_window_bits=10".to_vec()];
+		let reply: WebSocketExtensions = Header::parse_header(&value[..]).unwrap();
+		assert_eq!(
+			reply.0[0].params,
+			vec![Parameter::new(
+				"client_max_window_bits".to_string(),
+				Some("10".to_string())
+			)]
+		);
+
+		let quoted: Extension = "permessage-deflate; client_max_window_bits=\"10\""
+			.parse()
+			.unwrap();
+		assert_eq!(quoted.params, reply.0[0].params);
+	}
+
 	#[bench]
 	fn bench_header_extensions_parse(b: &mut test::Bencher) {
 		let value = vec![b"foo, bar; baz; qux=quux".to_vec()];
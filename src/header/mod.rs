@@ -17,3 +17,54 @@ mod key;
 mod origin;
 mod protocol;
 mod version;
+
+use self::extensions::Extension;
+
+/// The protocols requested in `headers`, as `WsUpgrade::protocols` sees them.
+///
+/// This is for inspecting a handshake without going through `WsUpgrade`,
+/// e.g. in a hyper server of one's own.
+pub fn protocols_from(headers: &Headers) -> Vec<String> {
+	headers
+		.get::<WebSocketProtocol>()
+		.map(|p| p.0.clone())
+		.unwrap_or_default()
+}
+
+/// The extensions requested in `headers`, as `WsUpgrade::extensions` sees them.
+pub fn extensions_from(headers: &Headers) -> Vec<Extension> {
+	headers
+		.get::<WebSocketExtensions>()
+		.map(|e| e.0.clone())
+		.unwrap_or_default()
+}
+
+/// The origin of the request with `headers`, as `WsUpgrade::origin` sees it.
+pub fn origin_from(headers: &Headers) -> Option<&str> {
+	headers.get::<Origin>().map(|o| &o.0 as &str)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn negotiated_view_of_headers() {
+		let mut headers = Headers::new();
+		assert!(protocols_from(&headers).is_empty());
+		assert!(extensions_from(&headers).is_empty());
+		assert_eq!(origin_from(&headers), None);
+
+		headers.set_raw("Sec-WebSocket-Protocol", vec![b"chat, superchat".to_vec()]);
+		headers.set_raw(
+			"Sec-WebSocket-Extensions",
+			vec![b"permessage-deflate; client_max_window_bits".to_vec()],
+		);
+		headers.set_raw("Origin", vec![b"http://example.com".to_vec()]);
+		assert_eq!(protocols_from(&headers), vec!["chat", "superchat"]);
+		let extensions = extensions_from(&headers);
+		assert_eq!(extensions.len(), 1);
+		assert_eq!(extensions[0].name, "permessage-deflate");
+		assert_eq!(origin_from(&headers), Some("http://example.com"));
+	}
+}
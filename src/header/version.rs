@@ -1,5 +1,5 @@
 use hyper;
-use hyper::header::parsing::from_one_raw_str;
+use hyper::header::parsing::{from_comma_delimited, from_one_raw_str};
 use hyper::header::{Header, HeaderFormat};
 use std::fmt::{self, Debug};
 
@@ -21,16 +21,30 @@ impl fmt::Debug for WebSocketVersion {
 	}
 }
 
+impl WebSocketVersion {
+	/// Parses the list of versions a server sends back in its `Sec-WebSocket-Version`
+	/// header(s) when it does not support the version requested by the client
+	/// (see [RFC6455 section 4.4](https://tools.ietf.org/html/rfc6455#section-4.4)).
+	pub fn parse_list(raw: &[Vec<u8>]) -> hyper::Result<Vec<WebSocketVersion>> {
+		from_comma_delimited(raw)
+			.map(|v: Vec<String>| v.into_iter().map(Self::from_string).collect())
+	}
+
+	fn from_string(s: String) -> WebSocketVersion {
+		match &s[..] {
+			"13" => WebSocketVersion::WebSocket13,
+			_ => WebSocketVersion::Unknown(s),
+		}
+	}
+}
+
 impl Header for WebSocketVersion {
 	fn header_name() -> &'static str {
 		"Sec-WebSocket-Version"
 	}
 
 	fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<WebSocketVersion> {
-		from_one_raw_str(raw).map(|s: String| match &s[..] {
-			"13" => WebSocketVersion::WebSocket13,
-			_ => WebSocketVersion::Unknown(s),
-		})
+		from_one_raw_str(raw).map(WebSocketVersion::from_string)
 	}
 }
 
@@ -63,6 +77,20 @@ mod tests {
 		assert_eq!(&headers.to_string()[..], "Sec-WebSocket-Version: 13\r\n");
 	}
 
+	#[test]
+	fn test_websocket_version_list() {
+		let value = vec![b"13, 8".to_vec(), b"7".to_vec()];
+		let versions = WebSocketVersion::parse_list(&value[..]).unwrap();
+		assert_eq!(
+			versions,
+			vec![
+				WebSocketVersion::WebSocket13,
+				WebSocketVersion::Unknown("8".to_string()),
+				WebSocketVersion::Unknown("7".to_string()),
+			]
+		);
+	}
+
 	#[bench]
 	fn bench_header_version_parse(b: &mut test::Bencher) {
 		let value = vec![b"13".to_vec()];
@@ -29,6 +29,9 @@
 //! WebSocket servers act similarly to the `TcpListener`, and listen for connections.
 //! See the `Server` struct documentation for more information. The `bind()` and
 //! `bind_secure()` functions will bind the server to the given `SocketAddr`.
+//! Servers are only built with the `server` feature (on by default), so e.g. a
+//! command line tool that only needs a synchronous client can depend on this crate
+//! with `default-features = false, features = ["sync"]`.
 //!
 //! # Extending Rust-WebSocket
 //! The `ws` module contains the traits and functions used by Rust-WebSocket at a lower
@@ -40,6 +43,8 @@ pub extern crate futures;
 extern crate hyper;
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 pub extern crate native_tls;
+#[cfg(any(feature = "keepalive", feature = "tcp-buffers", feature = "bind-local"))]
+extern crate socket2;
 #[cfg(test)]
 extern crate tokio;
 #[cfg(feature = "async")]
@@ -50,6 +55,8 @@ extern crate tokio_io;
 extern crate tokio_reactor;
 #[cfg(feature = "async")]
 extern crate tokio_tcp;
+#[cfg(feature = "async")]
+extern crate tokio_timer;
 #[cfg(feature = "async-ssl")]
 extern crate tokio_tls;
 extern crate unicase;
@@ -87,6 +94,8 @@ pub mod receiver;
 pub mod sender;
 
 pub mod client;
+pub mod observer;
+#[cfg(feature = "server")]
 pub mod server;
 pub use websocket_base::stream;
 
@@ -103,12 +112,14 @@ pub mod sync {
 	pub use crate::stream::sync::Stream;
 
 	/// A collection of handy synchronous-only parts of the `server` module.
+	#[cfg(feature = "server")]
 	pub mod server {
 		pub use crate::server::sync::*;
 		pub use crate::server::upgrade::sync as upgrade;
 		pub use crate::server::upgrade::sync::IntoWs;
 		pub use crate::server::upgrade::sync::Upgrade;
 	}
+	#[cfg(feature = "server")]
 	pub use crate::server::sync::Server;
 
 	/// A collection of handy synchronous-only parts of the `client` module.
@@ -132,12 +143,14 @@ pub mod r#async {
 	pub use crate::stream::r#async::Stream;
 
 	/// A collection of handy asynchronous-only parts of the `server` module.
+	#[cfg(feature = "server")]
 	pub mod server {
 		pub use crate::server::r#async::*;
 		pub use crate::server::upgrade::r#async as upgrade;
 		pub use crate::server::upgrade::r#async::IntoWs;
 		pub use crate::server::upgrade::r#async::Upgrade;
 	}
+	#[cfg(feature = "server")]
 	pub use crate::server::r#async::Server;
 
 	/// A collection of handy asynchronous-only parts of the `client` module.
@@ -146,6 +159,7 @@ pub mod r#async {
 		pub use crate::client::r#async::*;
 	}
 	pub use crate::client::r#async::Client;
+	pub use crate::client::r#async::{ClientExt, IdleTimeout};
 
 	pub use crate::result::r#async::WebSocketFuture;
 
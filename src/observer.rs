@@ -0,0 +1,63 @@
+//! Hooks for watching what goes on over a connection, e.g. to export metrics.
+//!
+//! An `Observer` is attached to a synchronous `Client` with
+//! `Client::set_observer` or `ClientBuilder::observer`, or to every client a
+//! synchronous server accepts with `Server::with_observer`. A client without
+//! an observer doesn't pay for any of this.
+//!
+//! Only the synchronous `Client` itself calls the observer. It is dropped
+//! when the client is split into a `Reader` and a `Writer`, and when a
+//! server is turned into an asynchronous one with `Server::into_async`.
+//! Asynchronous clients and servers have no hooks.
+//!
+//! ```rust
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//! use websocket::message::Type;
+//! use websocket::observer::Observer;
+//!
+//! #[derive(Default)]
+//! struct Traffic {
+//!     received: AtomicUsize,
+//! }
+//!
+//! impl Observer for Traffic {
+//!     fn message_received(&self, _kind: Type, size: usize) {
+//!         self.received.fetch_add(size, Ordering::Relaxed);
+//!     }
+//! }
+//! ```
+
+use crate::message::Type;
+use crate::result::WebSocketError;
+
+/// Gets told about the events of the connections it is attached to.
+///
+/// Every method does nothing by default, so implementations only need to
+/// override the ones they care about. The methods are called on the thread
+/// using the client, while it waits, so they should return quickly.
+pub trait Observer: Send + Sync {
+	/// The opening handshake completed and the connection is open.
+	fn handshake_complete(&self) {}
+
+	/// A message was sent. `size` is how many bytes it takes up as a single
+	/// unmasked data frame. `kind` is `None` for messages of a custom type
+	/// which don't tell theirs, see `ws::Message::message_type`.
+	fn message_sent(&self, _kind: Option<Type>, _size: usize) {}
+
+	/// A message was received, including pings and pongs the client handled
+	/// itself. `size` is measured like for `message_sent`.
+	fn message_received(&self, _kind: Type, _size: usize) {}
+
+	/// A close message was sent with status `code`, 1005 (No Status Received)
+	/// standing in for a close message without one.
+	fn close_sent(&self, _code: u16) {}
+
+	/// A close message with status `code` was received, see `close_sent`.
+	fn close_received(&self, _code: u16) {}
+
+	/// Sending or receiving failed with `error`.
+	///
+	/// This includes reads that time out or would block, so the error kind
+	/// should be looked at before counting a connection as broken.
+	fn error(&self, _error: &WebSocketError) {}
+}
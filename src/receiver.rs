@@ -1,16 +1,19 @@
 //! The default implementation of a WebSocket Receiver.
 
+use std::any::Any;
 use std::io::Read;
 use std::io::Result as IoResult;
+use std::sync::Arc;
 
 use hyper::buffer::BufReader;
 
 use crate::dataframe::{DataFrame, Opcode};
-use crate::message::OwnedMessage;
-use crate::result::{WebSocketError, WebSocketResult};
+use crate::message::{reassemble_dataframes, OwnedMessage, Type};
+use crate::result::{ProtocolViolation, WebSocketResult};
 pub use crate::stream::sync::Shutdown;
 use crate::stream::sync::{AsTcpStream, Stream};
 use crate::ws;
+use crate::ws::dataframe::DataFrame as DataFrameTrait;
 use crate::ws::receiver::Receiver as ReceiverTrait;
 use crate::ws::receiver::{DataFrameIterator, MessageIterator};
 
@@ -18,6 +21,7 @@ const DEFAULT_MAX_DATAFRAME_SIZE : usize = 1024*1024*100;
 const DEFAULT_MAX_MESSAGE_SIZE : usize = 1024*1024*200;
 const MAX_DATAFRAMES_IN_ONE_MESSAGE: usize = 1024*1024;
 const PER_DATAFRAME_OVERHEAD : usize = 64; // not actually measured, just to prevent filling memory with empty buffers
+const DEFAULT_MAX_EMPTY_FRAMES: usize = 1024;
 
 /// This reader bundles an existing stream with a parsing algorithm.
 /// It is used by the client in its `.split()` function as the reading component.
@@ -29,12 +33,30 @@ where
 	pub stream: BufReader<R>,
 	/// the parser to parse bytes into messages
 	pub receiver: Receiver,
+	/// The accepting server's connection slot, shared with the `Writer`
+	/// split off the same client. Only held to be dropped along with it.
+	#[allow(dead_code)]
+	pub(crate) connection_guard: Option<Arc<dyn Any + Send + Sync>>,
+	/// Messages the client had put back or peeked at before it was split,
+	/// the next one last.
+	pub(crate) unread: Vec<OwnedMessage>,
 }
 
 impl<R> Reader<R>
 where
 	R: Read,
 {
+	/// Bundles `stream` with `receiver`, e.g. to use a websocket connection
+	/// without a handshake.
+	pub fn new(stream: BufReader<R>, receiver: Receiver) -> Self {
+		Reader {
+			stream,
+			receiver,
+			connection_guard: None,
+			unread: Vec::new(),
+		}
+	}
+
 	/// Reads a single data frame from the remote endpoint.
 	pub fn recv_dataframe(&mut self) -> WebSocketResult<DataFrame> {
 		self.receiver.recv_dataframe(&mut self.stream)
@@ -46,14 +68,41 @@ where
 	}
 
 	/// Reads a single message from this receiver.
+	///
+	/// Messages the client had put back with `unread_message` or peeked at
+	/// before it was split come first.
 	pub fn recv_message(&mut self) -> WebSocketResult<OwnedMessage> {
-		self.receiver.recv_message(&mut self.stream)
+		match self.unread.pop() {
+			Some(message) => Ok(message),
+			None => self.receiver.recv_message(&mut self.stream),
+		}
+	}
+
+	/// Reads a single message into `buf`, see `Receiver::recv_message_into`.
+	///
+	/// Messages left over from the client come first, like with `recv_message`.
+	pub fn recv_message_into(&mut self, buf: &mut Vec<u8>) -> WebSocketResult<Type> {
+		if let Some(message) = self.unread.pop() {
+			buf.clear();
+			message.write_payload(buf)?;
+			return Ok(ws::Message::message_type(&message).unwrap_or(Type::Binary));
+		}
+		self.receiver.recv_message_into(&mut self.stream, buf)
 	}
 
 	/// An iterator over incoming messsages.
 	/// This iterator will block until new messages arrive and will never halt.
+	///
+	/// Messages left over from the client come first, like with `recv_message`.
 	pub fn incoming_messages<'a>(&'a mut self) -> MessageIterator<'a, Receiver, BufReader<R>> {
-		self.receiver.incoming_messages(&mut self.stream)
+		self.receiver
+			.incoming_messages(&mut self.stream)
+			.with_unread(&mut self.unread)
+	}
+
+	/// The number of bytes read from the stream so far, including frame headers.
+	pub fn bytes_received(&self) -> u64 {
+		self.receiver.bytes_received()
 	}
 }
 
@@ -82,6 +131,11 @@ pub struct Receiver {
 	// u32s instead uf usizes to economize used memory by this struct
 	max_dataframe_size: u32,
 	max_message_size: u32,
+	bytes_received: u64,
+	frames_received: u64,
+	max_total_frames: Option<u64>,
+	empty_frames: usize,
+	max_empty_frames: Option<usize>,
 }
 
 impl Receiver {
@@ -106,10 +160,89 @@ impl Receiver {
 			mask,
 			max_dataframe_size,
 			max_message_size,
+			bytes_received: 0,
+			frames_received: 0,
+			max_total_frames: None,
+			empty_frames: 0,
+			max_empty_frames: Some(DEFAULT_MAX_EMPTY_FRAMES),
+		}
+	}
+
+	/// The number of bytes of complete data frames read by this receiver so far,
+	/// including frame headers.
+	pub fn bytes_received(&self) -> u64 {
+		self.bytes_received
+	}
+
+	/// The number of data frames read by this receiver so far.
+	pub fn frames_received(&self) -> u64 {
+		self.frames_received
+	}
+
+	/// Fail with `ProtocolViolation::TooManyFrames` once more than `max`
+	/// frames have been received over the connection's lifetime, or lift the
+	/// limit with `None`.
+	pub fn set_max_total_frames(&mut self, max: Option<u64>) {
+		self.max_total_frames = max;
+	}
+
+	/// Fail with `ProtocolViolation::TooManyEmptyFrames` once a message has
+	/// more than `max` empty, non-final frames in a row, or lift the limit
+	/// with `None`. The default is 1024.
+	pub fn set_max_empty_frames(&mut self, max: Option<usize>) {
+		self.max_empty_frames = max;
+	}
+
+	/// Reads a single message, putting its payload into `buf` (after clearing
+	/// it) instead of a new `OwnedMessage`, and returns its type.
+	///
+	/// Reusing `buf` from one message to the next saves allocating each
+	/// message's payload, only the frames are still read into buffers of
+	/// their own. Control messages come out the same way, a close message's
+	/// payload being its status code followed by the reason.
+	pub fn recv_message_into<R>(
+		&mut self,
+		reader: &mut R,
+		buf: &mut Vec<u8>,
+	) -> WebSocketResult<Type>
+	where
+		R: Read,
+	{
+		let mut frames = self.recv_message_dataframes(reader)?;
+		reassemble_dataframes(&mut frames, buf)
+	}
+
+	/// The opcode of the first frame of a message whose later fragments are
+	/// still to be read, e.g. because a control frame came in between.
+	pub(crate) fn buffered_opcode(&self) -> Option<Opcode> {
+		self.buffer.first().map(|frame| frame.opcode)
+	}
+
+	/// Keeps track of the empty frames in a row of the message being received.
+	fn count_empty_frame(&mut self, frame: &DataFrame) -> WebSocketResult<()> {
+		if !frame.data.is_empty() || frame.finished {
+			self.empty_frames = 0;
+			return Ok(());
+		}
+		self.empty_frames += 1;
+		match self.max_empty_frames {
+			Some(max) if self.empty_frames > max => {
+				Err(ProtocolViolation::TooManyEmptyFrames.into())
+			}
+			_ => Ok(()),
 		}
 	}
 }
 
+/// No extension gives the reserved bits a meaning, so every frame that has
+/// one set fails the connection, control frames included.
+fn check_reserved(frame: &DataFrame) -> WebSocketResult<()> {
+	if frame.reserved != [false; 3] {
+		return Err(ProtocolViolation::ReservedBits.into());
+	}
+	Ok(())
+}
+
 impl ws::Receiver for Receiver {
 	type F = DataFrame;
 
@@ -120,7 +253,19 @@ impl ws::Receiver for Receiver {
 	where
 		R: Read,
 	{
-		DataFrame::read_dataframe_with_limit(reader, self.mask, self.max_dataframe_size as usize)
+		let dataframe = DataFrame::read_dataframe_with_limit(
+			reader,
+			self.mask,
+			self.max_dataframe_size as usize,
+		)?;
+		self.bytes_received += dataframe.frame_size(self.mask) as u64;
+		self.frames_received += 1;
+		if let Some(max) = self.max_total_frames {
+			if self.frames_received > max {
+				return Err(ProtocolViolation::TooManyFrames.into());
+			}
+		}
+		Ok(dataframe)
 	}
 
 	/// Returns the data frames that constitute one message.
@@ -131,13 +276,13 @@ impl ws::Receiver for Receiver {
 		let mut current_message_length : usize = self.buffer.iter().map(|x|x.data.len()).sum();
 		let mut finished = if self.buffer.is_empty() {
 			let first = self.recv_dataframe(reader)?;
+			check_reserved(&first)?;
 
 			if first.opcode == Opcode::Continuation {
-				return Err(WebSocketError::ProtocolError(
-					"Unexpected continuation data frame opcode",
-				));
+				return Err(ProtocolViolation::UnexpectedContinuation.into());
 			}
 
+			self.count_empty_frame(&first)?;
 			let finished = first.finished;
 			current_message_length += first.data.len() + PER_DATAFRAME_OVERHEAD;
 			self.buffer.push(first);
@@ -148,11 +293,13 @@ impl ws::Receiver for Receiver {
 
 		while !finished {
 			let next = self.recv_dataframe(reader)?;
+			check_reserved(&next)?;
 			finished = next.finished;
 
 			match next.opcode as u8 {
 				// Continuation opcode
 				0 => {
+					self.count_empty_frame(&next)?;
 					current_message_length += next.data.len() + PER_DATAFRAME_OVERHEAD;
 					self.buffer.push(next)
 				}
@@ -162,22 +309,16 @@ impl ws::Receiver for Receiver {
 				}
 				// Others
 				_ => {
-					return Err(WebSocketError::ProtocolError(
-						"Unexpected data frame opcode",
-					));
+					return Err(ProtocolViolation::UnfinishedMessage.into());
 				}
 			}
 
 			if !finished {
 				if self.buffer.len() >= MAX_DATAFRAMES_IN_ONE_MESSAGE {
-					return Err(WebSocketError::ProtocolError(
-						"Exceeded count of data frames in one WebSocket message",
-					));
+					return Err(ProtocolViolation::MessageTooBig.into());
 				}
 				if current_message_length >= self.max_message_size as usize {
-					return Err(WebSocketError::ProtocolError(
-						"Exceeded maximum WebSocket message size",
-					));
+					return Err(ProtocolViolation::MessageTooBig.into());
 				}
 			}
 		}
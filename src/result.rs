@@ -1,5 +1,7 @@
 //! The result type used within Rust-WebSocket
 
+use crate::header::WebSocketVersion;
+#[cfg(feature = "server")]
 use crate::server::upgrade::HyperIntoWsError;
 pub use hyper::status::StatusCode;
 use hyper::Error as HttpError;
@@ -30,7 +32,7 @@ pub mod r#async {
 	pub type WebSocketFuture<I> = Box<dyn Future<Item = I, Error = WebSocketError> + Send>;
 }
 
-pub use websocket_base::result::WebSocketError;
+pub use websocket_base::result::{ProtocolErrorPolicy, ProtocolViolation, WebSocketError};
 
 /// Represents a WebSocket error while connecting
 #[derive(Debug)]
@@ -41,12 +43,26 @@ pub enum WebSocketOtherError {
 	RequestError(&'static str),
 	/// Invalid WebSocket response error
 	ResponseError(&'static str),
+	/// The server's handshake response was not a valid upgrade to WebSocket
+	HandshakeResponse(HandshakeResponseError),
 	/// Received unexpected status code
 	StatusCodeError(StatusCode),
+	/// The server answered with a successful HTTP response (like a web page)
+	/// instead of upgrading the connection to WebSocket
+	NotUpgraded(StatusCode),
 	/// Received 3XX status code with a Location header
 	RedirectError(StatusCode, String),
+	/// The server refused the requested WebSocket version with a 426 and sent
+	/// back the versions it does support in its `Sec-WebSocket-Version` header
+	UnsupportedVersionError(StatusCode, Vec<WebSocketVersion>),
 	/// An HTTP parsing error
 	HttpError(HttpError),
+	/// The handshake response could not be parsed as HTTP, along with the
+	/// start of what was received instead (lossily decoded)
+	MalformedHandshake(HttpError, String),
+	/// The server did not complete the handshake within the time set with
+	/// `ClientBuilder::handshake_timeout`
+	HandshakeTimeout,
 	/// A URL parsing error
 	UrlError(ParseError),
 	/// An input/output error
@@ -64,6 +80,36 @@ pub enum WebSocketOtherError {
 	TlsHandshakeInterruption,
 }
 
+/// Compares errors in tests, e.g.
+/// `assert_eq!(err, WebSocketOtherError::StatusCodeError(StatusCode::NotFound))`.
+///
+/// I/O errors are equal when their kinds are. HTTP parsing and TLS errors
+/// can't be compared and are never equal, not even to themselves.
+impl PartialEq for WebSocketOtherError {
+	fn eq(&self, other: &WebSocketOtherError) -> bool {
+		use self::WebSocketOtherError::*;
+		match (self, other) {
+			(ProtocolError(a), ProtocolError(b)) => a == b,
+			(RequestError(a), RequestError(b)) => a == b,
+			(ResponseError(a), ResponseError(b)) => a == b,
+			(HandshakeResponse(a), HandshakeResponse(b)) => a == b,
+			(StatusCodeError(a), StatusCodeError(b)) => a == b,
+			(NotUpgraded(a), NotUpgraded(b)) => a == b,
+			(RedirectError(a, x), RedirectError(b, y)) => a == b && x == y,
+			(UnsupportedVersionError(a, x), UnsupportedVersionError(b, y)) => a == b && x == y,
+			(HandshakeTimeout, HandshakeTimeout) => true,
+			(UrlError(a), UrlError(b)) => a == b,
+			(IoError(a), IoError(b)) => a.kind() == b.kind(),
+			(WebSocketUrlError(a), WebSocketUrlError(b)) => a == b,
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+			(TlsHandshakeFailure, TlsHandshakeFailure) => true,
+			#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+			(TlsHandshakeInterruption, TlsHandshakeInterruption) => true,
+			_ => false,
+		}
+	}
+}
+
 impl fmt::Display for WebSocketOtherError {
 	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
 		match self {
@@ -71,18 +117,43 @@ impl fmt::Display for WebSocketOtherError {
 			WebSocketOtherError::ResponseError(e) => {
 				write!(fmt, "WebSocket response error: {}", e)
 			}
+			WebSocketOtherError::HandshakeResponse(e) => {
+				write!(fmt, "WebSocket response error: {}", e)
+			}
 			WebSocketOtherError::StatusCodeError(e) => write!(
 				fmt,
 				"WebSocketError: Received unexpected status code ({})",
 				e
 			),
+			WebSocketOtherError::NotUpgraded(st) => write!(
+				fmt,
+				"WebSocketError: Server responded with HTTP {} instead of upgrading to WebSocket",
+				st
+			),
 			WebSocketOtherError::RedirectError(st, loc) => write!(
 				fmt,
 				"WebSocketError: Redirected ({}) to {}",
 				st,
 				loc,
 			),
+			WebSocketOtherError::UnsupportedVersionError(st, versions) => {
+				write!(
+					fmt,
+					"WebSocketError: Unsupported WebSocket version ({}), server supports:",
+					st
+				)?;
+				for version in versions {
+					write!(fmt, " {}", version)?;
+				}
+				Ok(())
+			}
 			WebSocketOtherError::HttpError(e) => write!(fmt, "WebSocket HTTP error: {}", e),
+			WebSocketOtherError::MalformedHandshake(e, received) => {
+				write!(fmt, "WebSocket HTTP error: {}, received {:?}", e, received)
+			}
+			WebSocketOtherError::HandshakeTimeout => {
+				write!(fmt, "WebSocketError: Handshake timed out")
+			}
 			WebSocketOtherError::UrlError(e) => write!(fmt, "WebSocket URL parse error: {}", e),
 			WebSocketOtherError::IoError(e) => write!(fmt, "WebSocket I/O error: {}", e),
 			WebSocketOtherError::WebSocketUrlError(e) => e.fmt(fmt),
@@ -105,11 +176,13 @@ impl Error for WebSocketOtherError {
 	fn source(&self) -> Option<&(dyn Error + 'static)> {
 		match *self {
 			WebSocketOtherError::HttpError(ref error) => Some(error),
+			WebSocketOtherError::MalformedHandshake(ref error, _) => Some(error),
 			WebSocketOtherError::UrlError(ref error) => Some(error),
 			#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 			WebSocketOtherError::TlsError(ref error) => Some(error),
 			WebSocketOtherError::WebSocketUrlError(ref error) => Some(error),
 			WebSocketOtherError::IoError(ref e) => Some(e),
+			WebSocketOtherError::HandshakeResponse(ref e) => Some(e),
 			_ => None,
 		}
 	}
@@ -121,6 +194,18 @@ impl From<HttpError> for WebSocketOtherError {
 	}
 }
 
+/// How many of the received bytes a `MalformedHandshake` error shows.
+#[cfg(any(feature = "sync", feature = "async"))]
+const HANDSHAKE_SNIPPET_LEN: usize = 64;
+
+/// The start of a handshake that could not be parsed, in a form fit for
+/// an error message.
+#[cfg(any(feature = "sync", feature = "async"))]
+pub(crate) fn handshake_snippet(received: &[u8]) -> String {
+	let end = received.len().min(HANDSHAKE_SNIPPET_LEN);
+	String::from_utf8_lossy(&received[..end]).into_owned()
+}
+
 impl From<ParseError> for WebSocketOtherError {
 	fn from(err: ParseError) -> WebSocketOtherError {
 		WebSocketOtherError::UrlError(err)
@@ -150,6 +235,9 @@ impl From<crate::codec::http::HttpCodecError> for WebSocketOtherError {
 		match src {
 			crate::codec::http::HttpCodecError::Io(e) => WebSocketOtherError::IoError(e),
 			crate::codec::http::HttpCodecError::Http(e) => WebSocketOtherError::HttpError(e),
+			crate::codec::http::HttpCodecError::Malformed(e, received) => {
+				WebSocketOtherError::MalformedHandshake(e, received)
+			}
 		}
 	}
 }
@@ -160,6 +248,7 @@ impl From<WSUrlErrorKind> for WebSocketOtherError {
 	}
 }
 
+#[cfg(feature = "server")]
 impl From<HyperIntoWsError> for WebSocketOtherError {
 	fn from(err: HyperIntoWsError) -> WebSocketOtherError {
 		use self::HyperIntoWsError::*;
@@ -175,12 +264,52 @@ impl From<HyperIntoWsError> for WebSocketOtherError {
 			NoUpgradeHeader => ProtocolError("Missing Upgrade WebSocket header"),
 			NoWsConnectionHeader => ProtocolError("Invalid Connection WebSocket header"),
 			NoConnectionHeader => ProtocolError("Missing Connection WebSocket header"),
+			TooManyConnections => ProtocolError("Too many concurrent connections"),
+			RequestHasBody => ProtocolError("Upgrade request has a body"),
 		}
 	}
 }
 
+/// Why a server's response to the client handshake was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeResponseError {
+	/// The response has no `Sec-WebSocket-Accept` header
+	MissingAccept,
+	/// The `Sec-WebSocket-Accept` header does not match the key that was sent
+	BadAccept,
+	/// The `Upgrade` header is missing or is not `websocket`
+	WrongUpgrade,
+	/// The `Connection` header is missing or does not contain `Upgrade`
+	WrongConnection,
+	/// The status code is not `101 Switching Protocols`, and the response
+	/// isn't a redirect, a version mismatch or a plain HTTP response either
+	NotSwitchingProtocols(StatusCode),
+}
+
+impl fmt::Display for HandshakeResponseError {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			HandshakeResponseError::MissingAccept => {
+				fmt.write_str("Sec-WebSocket-Accept is missing")
+			}
+			HandshakeResponseError::BadAccept => fmt.write_str("Sec-WebSocket-Accept is invalid"),
+			HandshakeResponseError::WrongUpgrade => {
+				fmt.write_str("Upgrade field must be WebSocket")
+			}
+			HandshakeResponseError::WrongConnection => {
+				fmt.write_str("Connection field must be 'Upgrade'")
+			}
+			HandshakeResponseError::NotSwitchingProtocols(status) => {
+				write!(fmt, "Received unexpected status code ({})", status)
+			}
+		}
+	}
+}
+
+impl Error for HandshakeResponseError {}
+
 /// Represents a WebSocket URL error
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum WSUrlErrorKind {
 	/// Fragments are not valid in a WebSocket URL
 	CannotSetFragment,
@@ -209,6 +338,7 @@ impl From<WebSocketOtherError> for WebSocketError {
 	}
 }
 
+#[cfg(any(feature = "sync", feature = "async"))]
 pub(crate) fn towse<E>(e: E) -> WebSocketError
 where
 	E: Into<WebSocketOtherError>,
@@ -216,3 +346,41 @@ where
 	let e: WebSocketOtherError = e.into();
 	e.into()
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn errors_compare_equal() {
+		assert_eq!(
+			WebSocketError::ProtocolError("bad"),
+			WebSocketError::ProtocolError("bad")
+		);
+		assert_ne!(
+			WebSocketError::ProtocolError("bad"),
+			WebSocketError::DataFrameError("bad")
+		);
+		assert_eq!(
+			WebSocketError::from(io::Error::new(io::ErrorKind::TimedOut, "a")),
+			WebSocketError::from(io::Error::new(io::ErrorKind::TimedOut, "b"))
+		);
+		let other = || WebSocketError::Other(Box::new(WebSocketOtherError::HandshakeTimeout));
+		assert_ne!(other(), other());
+
+		assert_eq!(
+			WebSocketOtherError::StatusCodeError(StatusCode::NotFound),
+			WebSocketOtherError::StatusCodeError(StatusCode::NotFound)
+		);
+		assert_eq!(
+			WebSocketOtherError::WebSocketUrlError(WSUrlErrorKind::NoHostName),
+			WebSocketOtherError::WebSocketUrlError(WSUrlErrorKind::NoHostName)
+		);
+		assert_ne!(
+			WebSocketOtherError::WebSocketUrlError(WSUrlErrorKind::NoHostName),
+			WebSocketOtherError::WebSocketUrlError(WSUrlErrorKind::InvalidScheme)
+		);
+		let http = || WebSocketOtherError::HttpError(HttpError::Status);
+		assert_ne!(http(), http());
+	}
+}
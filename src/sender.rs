@@ -1,13 +1,20 @@
 //! The default implementation of a WebSocket Sender.
 
-use crate::result::WebSocketResult;
+use crate::message::Type;
+use crate::result::{ProtocolViolation, WebSocketError, WebSocketResult};
 use crate::stream::sync::AsTcpStream;
 pub use crate::stream::sync::Shutdown;
 use crate::ws;
 use crate::ws::dataframe::DataFrame;
 use crate::ws::sender::Sender as SenderTrait;
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
 use std::io::Result as IoResult;
 use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 
 /// A writer that bundles a stream with a serializer to send the messages.
 /// This is used in the client's `.split()` function as the writing component.
@@ -18,12 +25,26 @@ pub struct Writer<W> {
 	pub stream: W,
 	/// The serializer that will be used to serialize the messages
 	pub sender: Sender,
+	/// The accepting server's connection slot, shared with the `Reader`
+	/// split off the same client. Only held to be dropped along with it.
+	#[allow(dead_code)]
+	pub(crate) connection_guard: Option<Arc<dyn Any + Send + Sync>>,
 }
 
 impl<W> Writer<W>
 where
 	W: Write,
 {
+	/// Bundles `stream` with `sender`, e.g. to use a websocket connection
+	/// without a handshake.
+	pub fn new(stream: W, sender: Sender) -> Self {
+		Writer {
+			stream,
+			sender,
+			connection_guard: None,
+		}
+	}
+
 	/// Sends a single data frame to the remote endpoint.
 	pub fn send_dataframe<D>(&mut self, dataframe: &D) -> WebSocketResult<()>
 	where
@@ -40,6 +61,27 @@ where
 	{
 		self.sender.send_message(&mut self.stream, message)
 	}
+
+	/// Sends several messages back to back with a single write to the stream,
+	/// see `Sender::send_messages`.
+	pub fn send_messages<I, M>(&mut self, messages: I) -> WebSocketResult<()>
+	where
+		I: IntoIterator<Item = M>,
+		M: ws::Message,
+	{
+		self.sender.send_messages(&mut self.stream, messages)
+	}
+
+	/// Starts streaming a message of type `opcode` to the remote endpoint.
+	/// See `MessageWriter` for details.
+	pub fn message_writer(&mut self, opcode: Type) -> MessageWriter<'_> {
+		MessageWriter::new(&mut self.stream, &mut self.sender, opcode)
+	}
+
+	/// The number of bytes written to the stream so far, including frame headers.
+	pub fn bytes_sent(&self) -> u64 {
+		self.sender.bytes_sent()
+	}
 }
 
 impl<S> Writer<S>
@@ -59,16 +101,444 @@ where
 	}
 }
 
+/// A `Writer` that can be shared between threads to send messages concurrently.
+///
+/// Each message or data frame is written while holding an internal lock, so
+/// messages sent from different threads never get interleaved on the wire.
+/// Cloning a `SharedWriter` gives another handle to the same connection.
+///
+/// ```rust,no_run
+/// use std::thread;
+/// use websocket::ClientBuilder;
+/// use websocket::sync::sender::SharedWriter;
+/// use websocket::Message;
+///
+/// let client = ClientBuilder::new("ws://localhost:3000")
+///     .unwrap()
+///     .connect_insecure()
+///     .unwrap();
+/// let (_receiver, writer) = client.split().unwrap();
+///
+/// let writer = SharedWriter::new(writer);
+/// let other = writer.clone();
+/// thread::spawn(move || other.send_message(&Message::text("from a thread")));
+/// writer.send_message(&Message::text("from main")).unwrap();
+/// ```
+///
+/// By default a producer waits for its turn however long the others take.
+/// Use `with_queue_limit` to have producers shed load instead.
+pub struct SharedWriter<W> {
+	inner: Arc<Mutex<Writer<W>>>,
+	pending: Arc<AtomicUsize>,
+	queue_limit: Option<usize>,
+}
+
+impl<W> SharedWriter<W>
+where
+	W: Write,
+{
+	/// Wraps `writer` so it can be used from several threads.
+	pub fn new(writer: Writer<W>) -> Self {
+		SharedWriter {
+			inner: Arc::new(Mutex::new(writer)),
+			pending: Arc::new(AtomicUsize::new(0)),
+			queue_limit: None,
+		}
+	}
+
+	/// Let at most `max` sends wait for or hold this writer at a time.
+	///
+	/// Further sends through this handle fail with `WebSocketError::WouldBlock`
+	/// instead of waiting. The stream's own `io::ErrorKind::WouldBlock` is
+	/// reported the same way as long as nothing was written, so over a
+	/// nonblocking socket a producer is not held up by a slow consumer either.
+	/// A send that blocks after writing part of a frame fails with an
+	/// `io::ErrorKind::Other` I/O error instead, since the connection is
+	/// unusable from then on.
+	///
+	/// The limit is per handle and copied to clones made afterwards, while the
+	/// count of pending sends is shared by all handles.
+	pub fn with_queue_limit(mut self, max: usize) -> Self {
+		self.queue_limit = Some(max);
+		self
+	}
+
+	/// Sends a single data frame to the remote endpoint.
+	pub fn send_dataframe<D>(&self, dataframe: &D) -> WebSocketResult<()>
+	where
+		D: DataFrame,
+	{
+		self.queued(|writer| writer.send_dataframe(dataframe))
+	}
+
+	/// Sends a single message to the remote endpoint, atomically with
+	/// respect to the other handles of this writer.
+	pub fn send_message<M>(&self, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+	{
+		self.queued(|writer| writer.send_message(message))
+	}
+
+	/// Sends several messages back to back, so that no message sent through
+	/// another handle of this writer ends up between them.
+	pub fn send_messages<I, M>(&self, messages: I) -> WebSocketResult<()>
+	where
+		I: IntoIterator<Item = M>,
+		M: ws::Message,
+	{
+		self.queued(|writer| writer.send_messages(messages))
+	}
+
+	/// The number of bytes written to the stream so far, including frame headers.
+	pub fn bytes_sent(&self) -> u64 {
+		self.lock().bytes_sent()
+	}
+
+	/// Gets the `Writer` back if this is the last handle to it,
+	/// otherwise returns `self` unchanged.
+	pub fn try_unwrap(self) -> Result<Writer<W>, Self> {
+		let SharedWriter {
+			inner,
+			pending,
+			queue_limit,
+		} = self;
+		match Arc::try_unwrap(inner) {
+			Ok(mutex) => Ok(mutex.into_inner().unwrap_or_else(|e| e.into_inner())),
+			Err(inner) => Err(SharedWriter {
+				inner,
+				pending,
+				queue_limit,
+			}),
+		}
+	}
+
+	/// Runs `send` with the lock held, unless the queue limit is reached.
+	fn queued<F>(&self, send: F) -> WebSocketResult<()>
+	where
+		F: FnOnce(&mut Writer<W>) -> WebSocketResult<()>,
+	{
+		let max = match self.queue_limit {
+			Some(max) => max,
+			None => return send(&mut self.lock()),
+		};
+		let result = if self.pending.fetch_add(1, Ordering::SeqCst) < max {
+			let mut writer = self.lock();
+			let sent = writer.bytes_sent();
+			match send(&mut writer) {
+				Err(WebSocketError::IoError(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {
+					if writer.bytes_sent() == sent {
+						Err(WebSocketError::WouldBlock)
+					} else {
+						Err(partial_write_error().into())
+					}
+				}
+				result => result,
+			}
+		} else {
+			Err(WebSocketError::WouldBlock)
+		};
+		self.pending.fetch_sub(1, Ordering::SeqCst);
+		result
+	}
+
+	fn lock(&self) -> MutexGuard<'_, Writer<W>> {
+		// a panic while sending leaves nothing half-updated besides the stream itself
+		self.inner.lock().unwrap_or_else(|e| e.into_inner())
+	}
+}
+
+impl<W> Clone for SharedWriter<W> {
+	fn clone(&self) -> Self {
+		SharedWriter {
+			inner: Arc::clone(&self.inner),
+			pending: Arc::clone(&self.pending),
+			queue_limit: self.queue_limit,
+		}
+	}
+}
+
+/// Sends the same messages to many connections, e.g. everyone in a chat room.
+///
+/// Connections are registered as `SharedWriter`s under an id of your choice.
+/// `broadcast` serializes a message once and writes the same bytes to every
+/// connection, dropping the ones that fail from the registry. Connections
+/// whose sender masks or fragments messages are sent their own copy instead,
+/// so the savings only apply to the unmasked writers of a server.
+///
+/// ```rust,no_run
+/// use std::thread;
+/// use websocket::sync::sender::{Broadcaster, SharedWriter};
+/// use websocket::sync::Server;
+/// use websocket::Message;
+///
+/// let server = Server::bind("127.0.0.1:3000").unwrap();
+/// let room = Broadcaster::new();
+/// for (id, upgrade) in server.filter_map(Result::ok).enumerate() {
+///     let (_receiver, writer) = upgrade.accept().unwrap().split().unwrap();
+///     room.insert(id, SharedWriter::new(writer));
+///     room.broadcast(&Message::text(format!("{} joined", id))).unwrap();
+/// }
+/// ```
+pub struct Broadcaster<K, W> {
+	writers: Mutex<HashMap<K, SharedWriter<W>>>,
+}
+
+impl<K, W> Broadcaster<K, W>
+where
+	K: Eq + Hash + Clone,
+	W: Write,
+{
+	/// A broadcaster without any connections.
+	pub fn new() -> Self {
+		Broadcaster {
+			writers: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Registers `writer` under `id`, returning the writer it replaces.
+	pub fn insert(&self, id: K, writer: SharedWriter<W>) -> Option<SharedWriter<W>> {
+		self.lock().insert(id, writer)
+	}
+
+	/// Unregisters the writer with the given `id`.
+	pub fn remove(&self, id: &K) -> Option<SharedWriter<W>> {
+		self.lock().remove(id)
+	}
+
+	/// The number of registered writers.
+	pub fn len(&self) -> usize {
+		self.lock().len()
+	}
+
+	/// Whether there are no registered writers.
+	pub fn is_empty(&self) -> bool {
+		self.lock().is_empty()
+	}
+
+	/// Sends `message` to every registered writer, returning the ids of the
+	/// ones that failed, which are unregistered.
+	///
+	/// A writer at its queue limit (see `SharedWriter::with_queue_limit`)
+	/// misses the message but stays registered, since its connection is
+	/// still fine, just busy.
+	///
+	/// The registry isn't locked while sending, so writers can be added and
+	/// removed meanwhile. Fails without sending anything if `message` can't
+	/// be serialized.
+	pub fn broadcast<M>(&self, message: &M) -> WebSocketResult<Vec<K>>
+	where
+		M: ws::Message,
+	{
+		let mut buf = Vec::new();
+		message.serialize(&mut buf, false)?;
+
+		let writers: Vec<_> = self
+			.lock()
+			.iter()
+			.map(|(id, writer)| (id.clone(), writer.clone()))
+			.collect();
+		let failed: Vec<_> = writers
+			.into_iter()
+			.filter(|(_, writer)| {
+				let sent = writer.queued(|writer| {
+					if writer.sender.sends_as_is() {
+						writer.sender.send_raw(&mut writer.stream, &buf)
+					} else {
+						writer.send_message(message)
+					}
+				});
+				!matches!(sent, Ok(()) | Err(WebSocketError::WouldBlock))
+			})
+			.collect();
+
+		let mut writers = self.lock();
+		let removed = failed
+			.into_iter()
+			.filter(|(id, writer)| match writers.get(id) {
+				// unless it was replaced meanwhile
+				Some(current) if Arc::ptr_eq(&current.inner, &writer.inner) => {
+					writers.remove(id);
+					true
+				}
+				_ => false,
+			})
+			.map(|(id, _)| id)
+			.collect();
+		Ok(removed)
+	}
+
+	fn lock(&self) -> MutexGuard<'_, HashMap<K, SharedWriter<W>>> {
+		self.writers.lock().unwrap_or_else(|e| e.into_inner())
+	}
+}
+
+impl<K, W> Default for Broadcaster<K, W>
+where
+	K: Eq + Hash + Clone,
+	W: Write,
+{
+	fn default() -> Self {
+		Broadcaster::new()
+	}
+}
+
 /// A Sender that wraps a Writer and provides a default implementation using
 /// DataFrames and Messages.
 pub struct Sender {
 	mask: bool,
+	max_frame_size: Option<usize>,
+	bytes_sent: u64,
+	validate_outgoing: bool,
+	fragment_open: bool,
 }
 
 impl Sender {
 	/// Create a new WebSocketSender using the specified Writer.
 	pub fn new(mask: bool) -> Sender {
-		Sender { mask }
+		Sender {
+			mask,
+			max_frame_size: None,
+			bytes_sent: 0,
+			validate_outgoing: false,
+			fragment_open: false,
+		}
+	}
+
+	/// Automatically split messages whose payload is larger than `max_frame_size`
+	/// into several fragments when sending them, or stop doing so with `None`.
+	/// Control messages are never fragmented.
+	pub fn set_auto_fragment(&mut self, max_frame_size: Option<usize>) {
+		self.max_frame_size = max_frame_size;
+	}
+
+	/// The number of bytes written by this sender so far, including frame headers.
+	pub fn bytes_sent(&self) -> u64 {
+		self.bytes_sent
+	}
+
+	/// Check that the data frames sent by hand make up valid messages.
+	///
+	/// While a fragmented message is open, sending anything but its
+	/// continuation frames or control messages fails with
+	/// `ProtocolViolation::UnfinishedMessage`, and a continuation frame
+	/// without an open message fails with `ProtocolViolation::UnexpectedContinuation`.
+	/// Nothing is written in either case.
+	///
+	/// Fragments are tracked from the moment this is turned on.
+	pub fn set_validate_outgoing(&mut self, validate: bool) {
+		self.validate_outgoing = validate;
+		self.fragment_open = false;
+	}
+
+	/// Keeps track of the fragmented message being sent, if validating.
+	fn check_dataframe<D: DataFrame>(&mut self, dataframe: &D) -> WebSocketResult<()> {
+		let opcode = dataframe.opcode();
+		if !self.validate_outgoing || opcode >= 8 {
+			return Ok(());
+		}
+		match (opcode == 0, self.fragment_open) {
+			(true, false) => Err(ProtocolViolation::UnexpectedContinuation.into()),
+			(false, true) => Err(ProtocolViolation::UnfinishedMessage.into()),
+			_ => {
+				self.fragment_open = !dataframe.is_last();
+				Ok(())
+			}
+		}
+	}
+
+	/// Only control messages may be sent between the fragments of another message.
+	fn check_message<M: ws::Message>(&self, message: &M) -> WebSocketResult<()> {
+		if !self.validate_outgoing || !self.fragment_open {
+			return Ok(());
+		}
+		let is_data = match message.message_type() {
+			Some(kind) => (kind as u8) < 8,
+			// only custom messages don't know, their first frame tells
+			None => {
+				let mut buf = Vec::new();
+				message.serialize(&mut buf, false)?;
+				buf.first().map(|first| first & 0x0F < 8) == Some(true)
+			}
+		};
+		if is_data {
+			Err(ProtocolViolation::UnfinishedMessage.into())
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Serializes all `messages` into one buffer and writes it to `writer`
+	/// with a single `write_all`, saving a write per message.
+	pub fn send_messages<I, M, W>(&mut self, writer: &mut W, messages: I) -> WebSocketResult<()>
+	where
+		I: IntoIterator<Item = M>,
+		M: ws::Message,
+		W: Write,
+	{
+		let mut buf = Vec::new();
+		let sent = self.bytes_sent;
+		let serialized = messages
+			.into_iter()
+			.try_for_each(|message| self.send_message(&mut buf, &message));
+		// only count what reaches `writer`, even if serializing failed
+		self.bytes_sent = sent;
+		serialized?;
+		self.send_raw(writer, &buf)
+	}
+
+	/// Writes `dataframe` masked with `mask` instead of a random key, no matter
+	/// whether this sender masks its frames, see
+	/// `DataFrame::write_to_with_mask` for when that's appropriate.
+	pub fn send_dataframe_with_mask<D, W>(
+		&mut self,
+		writer: &mut W,
+		dataframe: &D,
+		mask: [u8; 4],
+	) -> WebSocketResult<()>
+	where
+		D: DataFrame,
+		W: Write,
+	{
+		self.check_dataframe(dataframe)?;
+		let mut writer = CountingWriter {
+			inner: writer,
+			count: &mut self.bytes_sent,
+		};
+		dataframe.write_to_with_mask(&mut writer, Some(mask))
+	}
+
+	/// Whether an unmasked, unfragmented serialization of a message can be
+	/// written as is, instead of going through `send_message`.
+	fn sends_as_is(&self) -> bool {
+		!self.mask && self.max_frame_size.is_none() && !self.fragment_open
+	}
+
+	fn send_raw<W: Write>(&mut self, writer: &mut W, data: &[u8]) -> WebSocketResult<()> {
+		let mut writer = CountingWriter {
+			inner: writer,
+			count: &mut self.bytes_sent,
+		};
+		writer.write_all(data)?;
+		Ok(())
+	}
+}
+
+/// Keeps track of how many bytes went through to the underlying writer.
+struct CountingWriter<'a, W: 'a> {
+	inner: &'a mut W,
+	count: &'a mut u64,
+}
+
+impl<'a, W: Write> Write for CountingWriter<'a, W> {
+	fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+		let written = self.inner.write(buf)?;
+		*self.count += written as u64;
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> IoResult<()> {
+		self.inner.flush()
 	}
 }
 
@@ -76,4 +546,470 @@ impl ws::Sender for Sender {
 	fn is_masked(&self) -> bool {
 		self.mask
 	}
+
+	fn send_dataframe<D, W>(&mut self, writer: &mut W, dataframe: &D) -> WebSocketResult<()>
+	where
+		D: DataFrame,
+		W: Write,
+	{
+		self.check_dataframe(dataframe)?;
+		let mut writer = CountingWriter {
+			inner: writer,
+			count: &mut self.bytes_sent,
+		};
+		dataframe.write_to(&mut writer, self.mask)
+	}
+
+	fn send_message<M, W>(&mut self, writer: &mut W, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+		W: Write,
+	{
+		self.check_message(message)?;
+		let mut writer = CountingWriter {
+			inner: writer,
+			count: &mut self.bytes_sent,
+		};
+		match self.max_frame_size {
+			Some(max) => message.serialize_fragmented(&mut writer, self.mask, max),
+			None => message.serialize(&mut writer, self.mask),
+		}
+	}
+}
+
+/// Streams a single message to the remote endpoint without buffering its payload.
+///
+/// Every call to `write` with N bytes is sent right away as one data frame
+/// carrying those N bytes (masked with its own key on the client side), so
+/// wrap this in a `BufWriter` to avoid sending many tiny frames.
+/// The message is completed with an empty final frame by `finish`,
+/// or when the writer is dropped.
+///
+/// Control messages (ping, pong and close) cannot be fragmented, writing to
+/// or finishing a `MessageWriter` of those types fails with
+/// `ErrorKind::InvalidInput` and nothing is ever sent, not even on drop.
+pub struct MessageWriter<'a> {
+	writer: &'a mut dyn Write,
+	sender: &'a mut Sender,
+	opcode: Type,
+	started: bool,
+	finished: bool,
+}
+
+impl<'a> MessageWriter<'a> {
+	/// Starts a message of type `opcode` that will be sent by `sender` to `writer`.
+	pub fn new(writer: &'a mut dyn Write, sender: &'a mut Sender, opcode: Type) -> Self {
+		MessageWriter {
+			writer,
+			sender,
+			opcode,
+			started: false,
+			finished: false,
+		}
+	}
+
+	/// Sends the final frame of the message.
+	pub fn finish(mut self) -> WebSocketResult<()> {
+		self.finished = true;
+		if self.is_control() {
+			return Err(control_error().into());
+		}
+		self.send_frame(&[], true)
+	}
+
+	fn is_control(&self) -> bool {
+		(self.opcode as u8) >= 8
+	}
+
+	fn send_frame(&mut self, data: &[u8], last: bool) -> WebSocketResult<()> {
+		let opcode = if self.started { 0 } else { self.opcode as u8 };
+		self.started = true;
+		let frame = MessageFragment { opcode, last, data };
+		self.sender.send_dataframe(&mut self.writer, &frame)
+	}
+}
+
+impl<'a> Write for MessageWriter<'a> {
+	fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+		if self.is_control() {
+			return Err(control_error());
+		}
+		if buf.is_empty() {
+			return Ok(0);
+		}
+		match self.send_frame(buf, false) {
+			Ok(()) => Ok(buf.len()),
+			Err(WebSocketError::IoError(e)) => Err(e),
+			Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
+		}
+	}
+
+	fn flush(&mut self) -> IoResult<()> {
+		self.writer.flush()
+	}
+}
+
+impl<'a> Drop for MessageWriter<'a> {
+	fn drop(&mut self) {
+		if !self.finished && !self.is_control() {
+			let _ = self.send_frame(&[], true);
+		}
+	}
+}
+
+fn control_error() -> io::Error {
+	io::Error::new(
+		io::ErrorKind::InvalidInput,
+		"control messages cannot be streamed",
+	)
+}
+
+/// The error of a send that blocked after writing part of a frame.
+fn partial_write_error() -> io::Error {
+	io::Error::new(
+		io::ErrorKind::Other,
+		"frame only partly written, the connection is unusable",
+	)
+}
+
+/// A borrowed piece of a message being streamed by a `MessageWriter`.
+struct MessageFragment<'a> {
+	opcode: u8,
+	last: bool,
+	data: &'a [u8],
+}
+
+impl<'a> DataFrame for MessageFragment<'a> {
+	fn is_last(&self) -> bool {
+		self.last
+	}
+
+	fn opcode(&self) -> u8 {
+		self.opcode
+	}
+
+	fn reserved(&self) -> &[bool; 3] {
+		&[false; 3]
+	}
+
+	fn size(&self) -> usize {
+		self.data.len()
+	}
+
+	fn write_payload(&self, socket: &mut dyn Write) -> WebSocketResult<()> {
+		socket.write_all(self.data)?;
+		Ok(())
+	}
+
+	fn take_payload(self) -> Vec<u8> {
+		self.data.to_vec()
+	}
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+	use super::*;
+	use crate::codec::ws::{Context, MessageCodec};
+	use crate::message::OwnedMessage;
+	use bytes::BytesMut;
+	use tokio_codec::Decoder;
+
+	#[test]
+	fn auto_fragment_large_message() {
+		let payload: Vec<u8> = (0..1024 * 1024).map(|i| i as u8).collect();
+		let mut writer = Writer::new(Vec::new(), Sender::new(true));
+		writer.sender.set_auto_fragment(Some(64 * 1024));
+		writer
+			.send_message(&OwnedMessage::Binary(payload.clone()))
+			.unwrap();
+		writer
+			.send_message(&OwnedMessage::Ping(vec![1, 2, 3]))
+			.unwrap();
+
+		let mut buf = BytesMut::from(writer.stream);
+		let mut codec = MessageCodec::default(Context::Server);
+		let mut frames = 0;
+		{
+			let mut dataframes = crate::codec::ws::DataFrameCodec::default(Context::Server);
+			let mut copy = buf.clone();
+			while let Some(frame) = dataframes.decode(&mut copy).unwrap() {
+				assert!(frame.data.len() <= 64 * 1024);
+				frames += 1;
+			}
+		}
+		assert_eq!(frames, 16 + 1);
+
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Binary(payload))
+		);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Ping(vec![1, 2, 3]))
+		);
+	}
+
+	#[test]
+	fn shared_writer_keeps_messages_whole() {
+		let writer = SharedWriter::new(Writer::new(Vec::new(), Sender::new(false)));
+		let threads: Vec<_> = (0..4u8)
+			.map(|i| {
+				let writer = writer.clone();
+				::std::thread::spawn(move || {
+					for _ in 0..50 {
+						writer
+							.send_message(&OwnedMessage::Binary(vec![i; 1000]))
+							.unwrap();
+					}
+				})
+			})
+			.collect();
+		for thread in threads {
+			thread.join().unwrap();
+		}
+
+		let stream = match writer.try_unwrap() {
+			Ok(w) => w.stream,
+			Err(_) => panic!("writer is still shared"),
+		};
+		let mut buf = BytesMut::from(stream);
+		let mut codec = MessageCodec::default(Context::Client);
+		let mut count = 0;
+		while let Some(message) = codec.decode(&mut buf).unwrap() {
+			match message {
+				OwnedMessage::Binary(data) => assert!(data.iter().all(|b| *b == data[0])),
+				other => panic!("unexpected message {:?}", other),
+			}
+			count += 1;
+		}
+		assert_eq!(count, 200);
+	}
+
+	#[test]
+	fn message_writer_streams_fragments() {
+		let mut writer = Writer::new(Vec::new(), Sender::new(true));
+		{
+			let mut message = writer.message_writer(Type::Text);
+			message.write_all(b"Hello, ").unwrap();
+			message.write_all(b"streaming ").unwrap();
+			message.write_all(b"world").unwrap();
+			message.finish().unwrap();
+		}
+		{
+			let mut message = writer.message_writer(Type::Binary);
+			message.write_all(&[1, 2, 3]).unwrap();
+		}
+		assert!(writer.message_writer(Type::Ping).write(b"beep").is_err());
+		assert!(writer.message_writer(Type::Close).finish().is_err());
+		assert_eq!(writer.bytes_sent(), writer.stream.len() as u64);
+
+		let mut buf = BytesMut::from(writer.stream);
+		let mut frames = 0;
+		{
+			let mut dataframes = crate::codec::ws::DataFrameCodec::default(Context::Server);
+			let mut copy = buf.clone();
+			while let Some(frame) = dataframes.decode(&mut copy).unwrap() {
+				assert_eq!(frame.finished, frames == 3 || frames == 5);
+				frames += 1;
+			}
+		}
+		assert_eq!(frames, 6);
+
+		let mut codec = MessageCodec::default(Context::Server);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Text("Hello, streaming world".to_string()))
+		);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Binary(vec![1, 2, 3]))
+		);
+		assert!(buf.is_empty());
+	}
+
+	#[test]
+	fn shared_writer_sheds_load() {
+		// takes this many more bytes before blocking
+		struct Full(usize);
+		impl Write for Full {
+			fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+				match self.0.min(buf.len()) {
+					0 => Err(io::Error::new(io::ErrorKind::WouldBlock, "full")),
+					n => {
+						self.0 -= n;
+						Ok(n)
+					}
+				}
+			}
+			fn flush(&mut self) -> IoResult<()> {
+				Ok(())
+			}
+		}
+
+		let message = OwnedMessage::Text("hi".to_string());
+		let writer = SharedWriter::new(Writer::new(Vec::new(), Sender::new(false)));
+		match writer.clone().with_queue_limit(0).send_message(&message) {
+			Err(WebSocketError::WouldBlock) => {}
+			other => panic!("unexpected result {:?}", other),
+		}
+		writer.send_message(&message).unwrap();
+		assert_eq!(writer.bytes_sent(), 4);
+
+		let full = SharedWriter::new(Writer::new(Full(0), Sender::new(false)));
+		match full.clone().send_message(&message) {
+			Err(WebSocketError::IoError(_)) => {}
+			other => panic!("unexpected result {:?}", other),
+		}
+		match full.with_queue_limit(1).send_message(&message) {
+			Err(WebSocketError::WouldBlock) => {}
+			other => panic!("unexpected result {:?}", other),
+		}
+
+		// blocking halfway through a frame is not the same
+		let half = SharedWriter::new(Writer::new(Full(1), Sender::new(false))).with_queue_limit(1);
+		match half.send_message(&message) {
+			Err(WebSocketError::IoError(ref e)) if e.kind() == io::ErrorKind::Other => {}
+			other => panic!("unexpected result {:?}", other),
+		}
+	}
+
+	#[test]
+	fn send_messages_writes_once() {
+		struct Recording(Vec<Vec<u8>>);
+		impl Write for Recording {
+			fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+				self.0.push(buf.to_vec());
+				Ok(buf.len())
+			}
+			fn flush(&mut self) -> IoResult<()> {
+				Ok(())
+			}
+		}
+
+		let mut writer = Writer::new(Recording(Vec::new()), Sender::new(false));
+		writer
+			.send_messages(vec![
+				OwnedMessage::Text("one".to_string()),
+				OwnedMessage::Binary(vec![2; 300]),
+				OwnedMessage::Ping(vec![3]),
+			])
+			.unwrap();
+
+		assert_eq!(writer.stream.0.len(), 1);
+		assert_eq!(writer.bytes_sent(), writer.stream.0[0].len() as u64);
+		let mut buf = BytesMut::from(writer.stream.0.remove(0));
+		let mut codec = MessageCodec::default(Context::Client);
+		assert_eq!(
+			codec.decode_all(&mut buf).unwrap(),
+			vec![
+				OwnedMessage::Text("one".to_string()),
+				OwnedMessage::Binary(vec![2; 300]),
+				OwnedMessage::Ping(vec![3]),
+			]
+		);
+
+		// a message failing halfway through sends and counts nothing
+		let sent = writer.bytes_sent();
+		assert!(writer
+			.send_messages(vec![
+				OwnedMessage::Text("one".to_string()),
+				OwnedMessage::Ping(vec![0; 126]),
+			])
+			.is_err());
+		assert!(writer.stream.0.is_empty());
+		assert_eq!(writer.bytes_sent(), sent);
+	}
+
+	#[test]
+	fn broadcaster_drops_failed_writers() {
+		enum Stream {
+			Buffer(Vec<u8>),
+			Broken,
+		}
+		impl Write for Stream {
+			fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+				match self {
+					Stream::Buffer(v) => v.write(buf),
+					Stream::Broken => Err(io::Error::new(io::ErrorKind::BrokenPipe, "gone")),
+				}
+			}
+			fn flush(&mut self) -> IoResult<()> {
+				Ok(())
+			}
+		}
+
+		let writer = |stream, mask| SharedWriter::new(Writer::new(stream, Sender::new(mask)));
+		let room = Broadcaster::new();
+		room.insert("server", writer(Stream::Buffer(Vec::new()), false));
+		room.insert("client", writer(Stream::Buffer(Vec::new()), true));
+		room.insert("broken", writer(Stream::Broken, false));
+		room.insert(
+			"busy",
+			writer(Stream::Buffer(Vec::new()), false).with_queue_limit(0),
+		);
+
+		let message = OwnedMessage::Text("hello".to_string());
+		assert_eq!(room.broadcast(&message).unwrap(), vec!["broken"]);
+		assert_eq!(room.len(), 3);
+
+		let received = |id, context| {
+			let stream = match room.remove(&id).unwrap().try_unwrap() {
+				Ok(Writer {
+					stream: Stream::Buffer(v),
+					..
+				}) => v,
+				_ => panic!("unexpected writer"),
+			};
+			MessageCodec::default(context)
+				.decode_all(&mut BytesMut::from(stream))
+				.unwrap()
+		};
+		assert_eq!(received("server", Context::Client), vec![message.clone()]);
+		assert_eq!(received("client", Context::Server), vec![message]);
+		assert_eq!(received("busy", Context::Client), vec![]);
+		assert!(room.is_empty());
+	}
+
+	#[test]
+	fn validate_outgoing_fragments() {
+		use crate::dataframe::{DataFrame as Frame, Opcode};
+		let frame = |finished, opcode| Frame::new(finished, opcode, vec![1]);
+
+		let mut writer = Writer::new(Vec::new(), Sender::new(false));
+		writer.sender.set_validate_outgoing(true);
+
+		writer.send_dataframe(&frame(false, Opcode::Text)).unwrap();
+		assert_eq!(
+			writer
+				.send_dataframe(&frame(true, Opcode::Text))
+				.unwrap_err(),
+			ProtocolViolation::UnfinishedMessage.into()
+		);
+		assert_eq!(
+			writer
+				.send_message(&OwnedMessage::Binary(vec![2]))
+				.unwrap_err(),
+			ProtocolViolation::UnfinishedMessage.into()
+		);
+		writer.send_message(&OwnedMessage::Ping(vec![3])).unwrap();
+		writer
+			.send_dataframe(&frame(true, Opcode::Continuation))
+			.unwrap();
+		assert_eq!(
+			writer
+				.send_dataframe(&frame(true, Opcode::Continuation))
+				.unwrap_err(),
+			ProtocolViolation::UnexpectedContinuation.into()
+		);
+
+		let mut buf = BytesMut::from(writer.stream);
+		let mut codec = MessageCodec::default(Context::Client);
+		assert_eq!(
+			codec.decode_all(&mut buf).unwrap(),
+			vec![
+				OwnedMessage::Ping(vec![3]),
+				OwnedMessage::Text("\u{1}\u{1}".to_string()),
+			]
+		);
+	}
 }
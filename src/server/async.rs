@@ -1,15 +1,20 @@
 //! The asynchronous implementation of a websocket server.
+use crate::codec::http::HttpServerCodec;
 use crate::server::upgrade::r#async::{IntoWs, Upgrade};
 use crate::server::InvalidConnection;
 use crate::server::{NoTlsAcceptor, OptionalTlsAcceptor, WsServer};
+use crate::stream::r#async::Stream as AsyncStream;
 use bytes::BytesMut;
 use futures;
-use futures::{Future, Stream};
+use futures::{Future, Sink, Stream};
+use hyper::http::h1::Incoming as HttpIncoming;
+use hyper::version::HttpVersion;
 use std;
 use std::io;
 use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
 pub use tokio_reactor::Handle;
+use tokio_codec::{Framed, FramedParts};
 use tokio_tcp::{TcpListener, TcpStream};
 
 #[cfg(any(feature = "async-ssl"))]
@@ -39,6 +44,53 @@ where
 	}
 }
 
+impl<S> InvalidConnection<S, BytesMut>
+where
+	S: AsyncStream + Send + 'static,
+{
+	/// Asynchronously respond to the failed handshake with an HTTP error and give
+	/// back the stream, framed with the `HttpServerCodec` that was used to send
+	/// the response.
+	///
+	/// The response depends on the cause of the failure, e.g. a client asking for
+	/// an unsupported websocket version is told which versions are supported.
+	/// See `HyperIntoWsError::rejection` for details.
+	///
+	/// If the connection failed before a stream was established, nothing is sent.
+	pub fn reject(
+		self,
+	) -> Box<dyn Future<Item = Option<Framed<S, HttpServerCodec>>, Error = io::Error> + Send> {
+		let stream = match self.stream {
+			Some(s) => s,
+			None => return Box::new(futures::future::ok(None)),
+		};
+		let version = self.parsed.map_or(HttpVersion::Http11, |r| r.version);
+		let (status, headers) = self.error.rejection();
+
+		let mut parts = FramedParts::new(stream, HttpServerCodec);
+		if let Some(buffer) = self.buffer {
+			parts.read_buf = buffer;
+		}
+		let future = Framed::from_parts(parts)
+			.send(HttpIncoming {
+				version,
+				subject: status,
+				headers,
+			})
+			.map(Some);
+		Box::new(future)
+	}
+
+	/// Take the bytes that were read from the stream past the parsed request,
+	/// e.g. the start of a request body, leaving `None` in their place.
+	///
+	/// To handle the connection as plain HTTP, these bytes have to be
+	/// processed before anything else read from `stream`.
+	pub fn take_buffered(&mut self) -> Option<Vec<u8>> {
+		self.buffer.take().map(|buffer| buffer.to_vec())
+	}
+}
+
 /// Asynchronous methods for creating an async server and accepting incoming connections.
 impl WsServer<NoTlsAcceptor, TcpListener> {
 	/// Bind a websocket server to an address.
@@ -49,6 +101,8 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::from_std(tcp, handle)?,
 			ssl_acceptor: NoTlsAcceptor,
+			connection_limit: None,
+			observer: None,
 		})
 	}
 
@@ -107,9 +161,25 @@ impl WsServer<TlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::from_std(tcp, handle)?,
 			ssl_acceptor: acceptor,
+			connection_limit: None,
+			observer: None,
 		})
 	}
 
+	/// Create an SSL websocket server from an already bound async `TcpListener`.
+	///
+	/// Each connection yielded by `incoming` goes through the async TLS handshake
+	/// with `acceptor` before the websocket handshake is read, just like a server
+	/// created with `bind_secure`.
+	pub fn from_listener_secure(listener: TcpListener, acceptor: TlsAcceptor) -> Self {
+		Server {
+			listener,
+			ssl_acceptor: acceptor,
+			connection_limit: None,
+			observer: None,
+		}
+	}
+
 	/// Turns the server into a stream of connection objects.
 	///
 	/// Each item of the stream is the address of the incoming connection and an `Upgrade`
@@ -3,8 +3,15 @@
 use native_tls::TlsAcceptor;
 
 use self::upgrade::{HyperIntoWsError, Request};
+#[cfg(any(feature = "sync", feature = "async"))]
+use crate::observer::Observer;
 use crate::stream::Stream;
+use hyper::method::Method;
+use hyper::uri::RequestUri;
+use hyper::version::HttpVersion;
 use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 pub mod upgrade;
 
@@ -63,6 +70,95 @@ where
 	}
 }
 
+impl<S, B> InvalidConnection<S, B>
+where
+	S: Stream,
+{
+	/// The method of the request, if it could be parsed.
+	pub fn method(&self) -> Option<&Method> {
+		self.parsed.as_ref().map(|r| &r.subject.0)
+	}
+
+	/// The path the request was made for, without the query string,
+	/// if it could be parsed.
+	///
+	/// Requests for an authority (`CONNECT`) or for `*` have no path.
+	pub fn path(&self) -> Option<&str> {
+		match self.parsed.as_ref()?.subject.1 {
+			RequestUri::AbsolutePath(ref path) => path.split('?').next(),
+			RequestUri::AbsoluteUri(ref url) => Some(url.path()),
+			RequestUri::Authority(_) | RequestUri::Star => None,
+		}
+	}
+
+	/// The HTTP version of the request, if it could be parsed.
+	pub fn http_version(&self) -> Option<HttpVersion> {
+		self.parsed.as_ref().map(|r| r.version)
+	}
+}
+
+/// Keeps one slot of a server's connection limit taken while it is alive.
+///
+/// Servers created with `with_connection_limit` hand one of these to every
+/// connection they accept; the slot is given back when the guard is dropped,
+/// i.e. when the upgrade is rejected or the accepted client goes away.
+pub struct ConnectionGuard {
+	live: Arc<AtomicUsize>,
+}
+
+#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+impl ConnectionGuard {
+	/// Take a slot, returning the guard and the number of connections that
+	/// were live before this one.
+	fn acquire(live: &Arc<AtomicUsize>) -> (Self, usize) {
+		let before = live.fetch_add(1, Ordering::SeqCst);
+		(ConnectionGuard { live: live.clone() }, before)
+	}
+}
+
+impl Drop for ConnectionGuard {
+	fn drop(&mut self) {
+		self.live.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+impl Debug for ConnectionGuard {
+	fn fmt(&self, fmt: &mut Formatter<'_>) -> FmtResult {
+		fmt.debug_struct("ConnectionGuard")
+			.field("live", &self.live.load(Ordering::SeqCst))
+			.finish()
+	}
+}
+
+/// The maximum number of live connections a server accepts, along with
+/// the counter shared by the guards of its current connections.
+#[derive(Clone)]
+#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+struct ConnectionLimit {
+	max: usize,
+	live: Arc<AtomicUsize>,
+}
+
+#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+impl ConnectionLimit {
+	fn new(max: usize) -> Self {
+		ConnectionLimit {
+			max,
+			live: Arc::new(AtomicUsize::new(0)),
+		}
+	}
+
+	/// Take a slot, or `None` if the limit is already reached.
+	fn acquire(&self) -> Option<ConnectionGuard> {
+		let (guard, before) = ConnectionGuard::acquire(&self.live);
+		if before < self.max {
+			Some(guard)
+		} else {
+			None
+		}
+	}
+}
+
 /// Represents a WebSocket server which can work with either normal
 /// (non-secure) connections, or secure WebSocket connections.
 ///
@@ -108,4 +204,8 @@ where
 	listener: L,
 	/// The SSL acceptor given to the server
 	pub ssl_acceptor: S,
+	#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+	connection_limit: Option<ConnectionLimit>,
+	#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+	observer: Option<Arc<dyn Observer>>,
 }
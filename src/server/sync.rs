@@ -1,12 +1,21 @@
 //! Provides an implementation of a WebSocket server
+use crate::client::sync::Client;
+use crate::observer::Observer;
 use crate::server::upgrade::sync::{Buffer, IntoWs, Upgrade};
 pub use crate::server::upgrade::{HyperIntoWsError, Request};
-use crate::server::{InvalidConnection, NoTlsAcceptor, OptionalTlsAcceptor, WsServer};
+use crate::server::{
+	ConnectionLimit, InvalidConnection, NoTlsAcceptor, OptionalTlsAcceptor, WsServer,
+};
+use crate::stream::sync::Stream;
+use hyper::version::HttpVersion;
 #[cfg(feature = "sync-ssl")]
 use native_tls::{TlsAcceptor, TlsStream};
 use std::convert::Into;
 use std::io;
 use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 #[cfg(feature = "async")]
 use crate::server::r#async;
@@ -20,6 +29,9 @@ use tokio_tcp::TcpListener as AsyncTcpListener;
 /// `Err` variant).
 pub type AcceptResult<S> = Result<Upgrade<S>, InvalidConnection<S, Buffer>>;
 
+/// How long `run` waits before accepting again after accepting failed.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
 /// Represents a WebSocket server which can work with either normal
 /// (non-secure) connections, or secure WebSocket connections.
 ///
@@ -38,6 +50,63 @@ where
 		self.listener.local_addr()
 	}
 
+	/// Accept at most `max` live connections at a time.
+	///
+	/// Every accepted `Upgrade` and the `Client` it turns into holds a
+	/// `ConnectionGuard` which gives its slot back when dropped. Once all slots
+	/// are taken, `accept` answers new handshakes with `503 Service Unavailable`
+	/// and returns an `InvalidConnection` with `HyperIntoWsError::TooManyConnections`.
+	///
+	/// A split client keeps its slot until both its reader and writer are
+	/// dropped, while turning it back into its stream releases the slot, see
+	/// `Client::take_connection_guard`.
+	pub fn with_connection_limit(mut self, max: usize) -> Self {
+		self.connection_limit = Some(ConnectionLimit::new(max));
+		self
+	}
+
+	/// Attach `observer` to every client this server accepts, see the
+	/// `observer` module. Its `handshake_complete` is called once the
+	/// handshake response has been sent.
+	///
+	/// The observer is not carried over by `into_async`.
+	pub fn with_observer(mut self, observer: Arc<dyn Observer>) -> Self {
+		self.observer = Some(observer);
+		self
+	}
+
+	/// Hand the upgrade a slot of the connection limit, or turn it away if
+	/// there is none left.
+	#[allow(clippy::result_large_err)]
+	fn admit<T: Stream>(&self, mut upgrade: Upgrade<T>) -> AcceptResult<T> {
+		upgrade.observer = self.observer.clone();
+		let limit = match self.connection_limit {
+			Some(ref limit) => limit,
+			None => return Ok(upgrade),
+		};
+		if let Some(guard) = limit.acquire() {
+			upgrade.connection_guard = Some(guard);
+			return Ok(upgrade);
+		}
+
+		let rejected = InvalidConnection {
+			stream: Some(upgrade.stream),
+			parsed: Some(upgrade.request),
+			buffer: upgrade.buffer,
+			error: HyperIntoWsError::TooManyConnections,
+		};
+		let (stream, error) = match rejected.reject() {
+			Ok(stream) => (stream, HyperIntoWsError::TooManyConnections),
+			Err((stream, e)) => (Some(stream), e.into()),
+		};
+		Err(InvalidConnection {
+			stream,
+			parsed: None,
+			buffer: None,
+			error,
+		})
+	}
+
 	/// Changes whether the Server is in nonblocking mode.
 	/// NOTE: It is strongly encouraged to use the `websocket::async` module instead
 	/// of this. It provides high level APIs for creating asynchronous servers.
@@ -84,13 +153,105 @@ where
 	/// This will only work if the stream used for this server `S` already implements
 	/// `AsyncRead + AsyncWrite`. Useful if you would like some blocking things to happen
 	/// at the start of your server.
+	///
+	/// The asynchronous server doesn't enforce a connection limit, so the one
+	/// set with `with_connection_limit` is dropped, as is the observer.
 	#[cfg(feature = "async")]
 	pub fn into_async(self, handle: &Handle) -> io::Result<r#async::Server<S>> {
 		Ok(WsServer {
 			listener: AsyncTcpListener::from_std(self.listener, handle)?,
 			ssl_acceptor: self.ssl_acceptor,
+			connection_limit: None,
+			observer: None,
 		})
 	}
+
+	/// Accept connections forever, doing the handshake of each with
+	/// `handshake` on a thread of its own, see `Server::run`.
+	fn run_with<T, F>(
+		mut self,
+		handshake: fn(&Self, TcpStream) -> AcceptResult<T>,
+		handler: F,
+	) -> !
+	where
+		S: Send + Sync + 'static,
+		T: Stream + 'static,
+		F: Fn(Client<T>) + Send + Sync + 'static,
+	{
+		// slots are taken before a connection gets a thread, so that the
+		// limit bounds the threads as well
+		let limit = self.connection_limit.take();
+		let server = Arc::new(self);
+		let handler = Arc::new(handler);
+		loop {
+			let stream = match server.listener.accept() {
+				Ok((stream, _)) => stream,
+				// e.g. out of file descriptors, give connections time to close
+				Err(_) => {
+					thread::sleep(ACCEPT_BACKOFF);
+					continue;
+				}
+			};
+			let guard = match limit {
+				Some(ref limit) => match limit.acquire() {
+					Some(guard) => Some(guard),
+					None => continue,
+				},
+				None => None,
+			};
+			let server = server.clone();
+			let handler = handler.clone();
+			thread::spawn(move || match handshake(&server, stream) {
+				Ok(mut upgrade) => {
+					upgrade.connection_guard = guard;
+					if let Ok(client) = upgrade.accept() {
+						handler(client);
+					}
+				}
+				Err(invalid) => {
+					let _ = invalid.reject();
+				}
+			});
+		}
+	}
+}
+
+impl<S> InvalidConnection<S, Buffer>
+where
+	S: Stream,
+{
+	/// Respond to the failed handshake with an HTTP error and give back the stream.
+	///
+	/// The response depends on the cause of the failure, e.g. a client asking for
+	/// an unsupported websocket version is told which versions are supported.
+	/// See `HyperIntoWsError::rejection` for details.
+	///
+	/// If the connection failed before a stream was established, nothing is sent.
+	pub fn reject(self) -> Result<Option<S>, (S, io::Error)> {
+		let mut stream = match self.stream {
+			Some(s) => s,
+			None => return Ok(None),
+		};
+		let version = self.parsed.map_or(HttpVersion::Http11, |r| r.version);
+		let (status, headers) = self.error.rejection();
+		let data = format!("{} {}\r\n{}\r\n", version, status, headers);
+		match stream.write_all(data.as_bytes()) {
+			Ok(()) => Ok(Some(stream)),
+			Err(e) => Err((stream, e)),
+		}
+	}
+
+	/// Take the bytes that were read from the stream past the parsed request,
+	/// e.g. the start of a request body, leaving `None` in their place.
+	///
+	/// To handle the connection as plain HTTP, read these bytes before
+	/// anything else from `stream`, e.g. with
+	/// `io::Read::chain(io::Cursor::new(buffered), stream)`.
+	pub fn take_buffered(&mut self) -> Option<Vec<u8>> {
+		self.buffer
+			.take()
+			.map(|Buffer { buf, pos, cap }| buf[pos..cap].to_vec())
+	}
 }
 
 /// Synchronous methods for creating an SSL server and accepting incoming connections.
@@ -142,23 +303,37 @@ impl WsServer<TlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::bind(&addr)?,
 			ssl_acceptor: acceptor,
+			connection_limit: None,
+			observer: None,
 		})
 	}
 
 	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
 	pub fn accept(&mut self) -> AcceptResult<TlsStream<TcpStream>> {
-		let stream = match self.listener.accept() {
-			Ok(s) => s.0,
-			Err(e) => {
-				return Err(InvalidConnection {
-					stream: None,
-					parsed: None,
-					buffer: None,
-					error: HyperIntoWsError::Io(e),
-				});
-			}
-		};
+		match self.listener.accept() {
+			Ok((stream, _)) => self.handshake(stream),
+			Err(e) => Err(InvalidConnection {
+				stream: None,
+				parsed: None,
+				buffer: None,
+				error: HyperIntoWsError::Io(e),
+			}),
+		}
+	}
 
+	/// Accept connections forever, handing each websocket client to `handler`
+	/// on a thread of its own. Like `run` on an insecure server, with the TLS
+	/// handshake done on the connection's thread as well.
+	pub fn run<F>(self, handler: F) -> !
+	where
+		F: Fn(Client<TlsStream<TcpStream>>) + Send + Sync + 'static,
+	{
+		self.run_with(Self::handshake, handler)
+	}
+
+	/// Do the TLS and websocket handshakes of an accepted connection.
+	#[allow(clippy::result_large_err)]
+	fn handshake(&self, stream: TcpStream) -> AcceptResult<TlsStream<TcpStream>> {
 		let stream = match self.ssl_acceptor.accept(stream) {
 			Ok(s) => s,
 			Err(err) => {
@@ -172,7 +347,7 @@ impl WsServer<TlsAcceptor, TcpListener> {
 		};
 
 		match stream.into_ws() {
-			Ok(u) => Ok(u),
+			Ok(u) => self.admit(u),
 			Err((s, r, b, e)) => Err(InvalidConnection {
 				stream: Some(s),
 				parsed: r,
@@ -223,25 +398,59 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: TcpListener::bind(&addr)?,
 			ssl_acceptor: NoTlsAcceptor,
+			connection_limit: None,
+			observer: None,
 		})
 	}
 
 	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
 	pub fn accept(&mut self) -> AcceptResult<TcpStream> {
-		let stream = match self.listener.accept() {
-			Ok(s) => s.0,
-			Err(e) => {
-				return Err(InvalidConnection {
-					stream: None,
-					parsed: None,
-					buffer: None,
-					error: e.into(),
-				});
-			}
-		};
+		match self.listener.accept() {
+			Ok((stream, _)) => self.handshake(stream),
+			Err(e) => Err(InvalidConnection {
+				stream: None,
+				parsed: None,
+				buffer: None,
+				error: e.into(),
+			}),
+		}
+	}
+
+	/// Accept connections forever, handing each websocket client to `handler`
+	/// on a thread of its own.
+	///
+	/// The handshake is read and answered on the connection's thread, so a
+	/// slow client does not hold up the others, and a panicking handler only
+	/// takes down its own connection. Failed handshakes are answered with an
+	/// HTTP error and dropped, combine this with `with_connection_limit` to
+	/// bound the number of clients handled at a time. Connections over the
+	/// limit are closed right away, without a thread to read their handshake
+	/// on, so the limit bounds the number of threads as well.
+	///
+	/// ```no_run
+	/// extern crate websocket;
+	/// # fn main() {
+	/// use websocket::sync::Server;
+	/// use websocket::Message;
+	///
+	/// let server = Server::bind("127.0.0.1:1234").unwrap();
+	/// server.run(|mut client| {
+	///     let _ = client.send_message(&Message::text("Hello, client!"));
+	/// });
+	/// # }
+	/// ```
+	pub fn run<F>(self, handler: F) -> !
+	where
+		F: Fn(Client<TcpStream>) + Send + Sync + 'static,
+	{
+		self.run_with(Self::handshake, handler)
+	}
 
+	/// Do the websocket handshake of an accepted connection.
+	#[allow(clippy::result_large_err)]
+	fn handshake(&self, stream: TcpStream) -> AcceptResult<TcpStream> {
 		match stream.into_ws() {
-			Ok(u) => Ok(u),
+			Ok(u) => self.admit(u),
 			Err((s, r, b, e)) => Err(InvalidConnection {
 				stream: Some(s),
 				parsed: r,
@@ -257,6 +466,8 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 		Ok(Server {
 			listener: inner,
 			ssl_acceptor: self.ssl_acceptor.clone(),
+			connection_limit: self.connection_limit.clone(),
+			observer: self.observer.clone(),
 		})
 	}
 }
@@ -296,4 +507,222 @@ mod tests {
 			},
 		}
 	}
+
+	#[test]
+	fn reject_unsupported_version() {
+		use super::*;
+		use std::io::{Read, Write};
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+		client
+			.write_all(
+				b"GET / HTTP/1.1\r\n\
+				  Host: localhost\r\n\
+				  Upgrade: websocket\r\n\
+				  Connection: Upgrade\r\n\
+				  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+				  Sec-WebSocket-Version: 8\r\n\
+				  \r\n",
+			)
+			.unwrap();
+
+		let invalid = match server.accept() {
+			Ok(_) => panic!("expected error"),
+			Err(e) => e,
+		};
+		match invalid.error {
+			HyperIntoWsError::UnsupportedWebsocketVersion => {}
+			ref e => panic!("unexpected error {}", e),
+		}
+		drop(invalid.reject().unwrap());
+
+		let mut response = String::new();
+		client.read_to_string(&mut response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+		assert!(response.contains("Sec-WebSocket-Version: 13\r\n"));
+	}
+
+	#[test]
+	fn reject_request_with_body() {
+		use super::*;
+		use std::io::{Read, Write};
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+		client
+			.write_all(
+				b"GET / HTTP/1.1\r\n\
+				  Host: localhost\r\n\
+				  Upgrade: websocket\r\n\
+				  Connection: Upgrade\r\n\
+				  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+				  Sec-WebSocket-Version: 13\r\n\
+				  Content-Length: 6\r\n\
+				  \r\n\
+				  \x81\x04ping",
+			)
+			.unwrap();
+
+		let invalid = match server.accept() {
+			Ok(_) => panic!("expected error"),
+			Err(e) => e,
+		};
+		match invalid.error {
+			HyperIntoWsError::RequestHasBody => {}
+			ref e => panic!("unexpected error {}", e),
+		}
+		drop(invalid.reject().unwrap());
+
+		let mut response = String::new();
+		client.read_to_string(&mut response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+	}
+
+	#[test]
+	fn connection_limit_rejects_when_full() {
+		use super::*;
+		use std::io::{Read, Write};
+
+		fn handshake(addr: SocketAddr) -> TcpStream {
+			let mut stream = TcpStream::connect(addr).unwrap();
+			stream
+				.write_all(
+					b"GET / HTTP/1.1\r\n\
+					  Host: localhost\r\n\
+					  Upgrade: websocket\r\n\
+					  Connection: Upgrade\r\n\
+					  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+					  Sec-WebSocket-Version: 13\r\n\
+					  \r\n",
+				)
+				.unwrap();
+			stream
+		}
+
+		let mut server = Server::bind("127.0.0.1:0")
+			.unwrap()
+			.with_connection_limit(1);
+		let addr = server.local_addr().unwrap();
+
+		let _first = handshake(addr);
+		let accepted = server.accept().unwrap().accept().unwrap();
+
+		let mut second = handshake(addr);
+		let invalid = match server.accept() {
+			Ok(_) => panic!("expected error"),
+			Err(e) => e,
+		};
+		match invalid.error {
+			HyperIntoWsError::TooManyConnections => {}
+			ref e => panic!("unexpected error {}", e),
+		}
+		drop(invalid);
+		let mut response = String::new();
+		second.read_to_string(&mut response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+		// the slot stays taken until both halves of a split client are gone
+		let (reader, writer) = accepted.split().unwrap();
+		drop(reader);
+		let _third = handshake(addr);
+		assert!(server.accept().is_err());
+		drop(writer);
+		let _fourth = handshake(addr);
+		assert!(server.accept().is_ok());
+	}
+
+	#[test]
+	fn take_buffered_returns_request_body() {
+		use super::*;
+		use std::io::Write;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+		client
+			.write_all(
+				b"POST /form HTTP/1.1\r\n\
+				  Host: localhost\r\n\
+				  Content-Length: 5\r\n\
+				  \r\n\
+				  a=b&c",
+			)
+			.unwrap();
+
+		let mut invalid = match server.accept() {
+			Ok(_) => panic!("expected error"),
+			Err(e) => e,
+		};
+		assert_eq!(invalid.take_buffered(), Some(b"a=b&c".to_vec()));
+		assert_eq!(invalid.take_buffered(), None);
+	}
+
+	#[test]
+	fn invalid_connection_exposes_request() {
+		use super::*;
+		use hyper::method::Method;
+		use hyper::version::HttpVersion;
+		use std::io::Write;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+		client
+			.write_all(b"GET /static/app.js?v=2 HTTP/1.0\r\nHost: localhost\r\n\r\n")
+			.unwrap();
+
+		let invalid = match server.accept() {
+			Ok(_) => panic!("expected error"),
+			Err(e) => e,
+		};
+		assert_eq!(invalid.method(), Some(&Method::Get));
+		assert_eq!(invalid.path(), Some("/static/app.js"));
+		assert_eq!(invalid.http_version(), Some(HttpVersion::Http10));
+	}
+
+	#[test]
+	fn run_closes_connections_over_the_limit() {
+		use super::*;
+		use std::io::Read;
+
+		let server = Server::bind("127.0.0.1:0")
+			.unwrap()
+			.with_connection_limit(1);
+		let addr = server.local_addr().unwrap();
+		thread::spawn(move || server.run(|_| ()));
+
+		// the silent client holds the only slot
+		let _silent = TcpStream::connect(addr).unwrap();
+		let mut turned_away = TcpStream::connect(addr).unwrap();
+		turned_away
+			.set_read_timeout(Some(Duration::from_secs(5)))
+			.unwrap();
+		let mut response = Vec::new();
+		turned_away.read_to_end(&mut response).unwrap();
+		assert!(response.is_empty());
+	}
+
+	#[test]
+	fn run_hands_clients_to_handler() {
+		use super::*;
+		use crate::{ClientBuilder, Message, OwnedMessage};
+
+		let server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		thread::spawn(move || {
+			server.run(|mut client| {
+				client.send_message(&Message::text("hello")).unwrap();
+			})
+		});
+
+		// a client that never sends its handshake doesn't hold up the others
+		let _silent = TcpStream::connect(addr).unwrap();
+		let mut client = ClientBuilder::new(&format!("ws://{}", addr))
+			.unwrap()
+			.connect_insecure()
+			.unwrap();
+		assert_eq!(
+			client.recv_message().unwrap(),
+			OwnedMessage::Text("hello".to_string())
+		);
+	}
 }
@@ -9,16 +9,17 @@ use super::{validate, HyperIntoWsError, Request, WsUpgrade};
 use crate::client::r#async::ClientNew;
 use crate::codec::http::HttpServerCodec;
 use crate::codec::ws::{Context, MessageCodec};
-use crate::stream::r#async::Stream;
+use crate::stream::r#async::{AsTcpStream, Stream};
 use crate::ws::util::update_framed_codec;
 use bytes::BytesMut;
 use futures::sink::Send as SinkSend;
 use futures::Stream as StreamTrait;
 use futures::{Future, Sink};
-use hyper::header::Headers;
+use hyper::header::{Headers, Location};
 use hyper::http::h1::Incoming;
 use hyper::status::StatusCode;
 use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
 use tokio_codec::{Decoder, Framed, FramedParts};
 
 const DEFAULT_MAX_DATAFRAME_SIZE : usize = 1024*1024*100;
@@ -87,6 +88,19 @@ where
 		self.internal_accept(Some(custom_headers))
 	}
 
+	/// Asynchronously accept the websocket handshake, then create a client.
+	/// The closure `f` is given the response headers prepared so far
+	/// (including e.g. the chosen protocol) and returns extra headers to add
+	/// to the response, useful when these depend on the negotiated state.
+	pub fn accept_with_fn<F>(mut self, f: F) -> ClientNew<S>
+	where
+		F: FnOnce(&Headers) -> Headers,
+	{
+		self.prepare_headers(None);
+		let custom_headers = f(&self.headers);
+		self.internal_accept(Some(&custom_headers))
+	}
+
     /// Like `accept`, but also allows to set memory limits for incoming messages and dataframes
 	pub fn accept_with_limits(self, max_dataframe_size: usize, max_message_size: usize) -> ClientNew<S> {
 		self.internal_accept_with_limits(None, max_dataframe_size, max_message_size)
@@ -109,6 +123,7 @@ where
 			stream,
 			request,
 			buffer,
+			..
 		} = self;
 
 		let mut parts = FramedParts::new(stream, HttpServerCodec);
@@ -145,8 +160,27 @@ where
 		self.internal_reject(Some(headers))
 	}
 
-	fn internal_reject(
+	/// Asynchronously redirect the client's request to make a websocket connection
+	/// to another location, using a `3xx` status code such as
+	/// `StatusCode::TemporaryRedirect`, and deconstruct `self` into it's original
+	/// stream. The stream being returned is framed with the `HttpServerCodec`
+	/// since that was used to send the redirection.
+	pub fn redirect(
 		mut self,
+		status: StatusCode,
+		location: &str,
+	) -> SinkSend<Framed<S, HttpServerCodec>> {
+		self.headers.set(Location(location.to_string()));
+		self.internal_reject_with_status(status, None)
+	}
+
+	fn internal_reject(self, headers: Option<&Headers>) -> SinkSend<Framed<S, HttpServerCodec>> {
+		self.internal_reject_with_status(StatusCode::BadRequest, headers)
+	}
+
+	fn internal_reject_with_status(
+		mut self,
+		status: StatusCode,
 		headers: Option<&Headers>,
 	) -> SinkSend<Framed<S, HttpServerCodec>> {
 		if let Some(custom) = headers {
@@ -157,12 +191,28 @@ where
 		let duplex = Framed::from_parts(parts);
 		duplex.send(Incoming {
 			version: self.request.version,
-			subject: StatusCode::BadRequest,
+			subject: status,
 			headers: self.headers,
 		})
 	}
 }
 
+impl<S> WsUpgrade<S, BytesMut>
+where
+	S: Stream + AsTcpStream,
+{
+	/// The address of the remote end of the connection, e.g. for access logs
+	/// or limits per IP.
+	pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+		self.stream.as_tcp().peer_addr()
+	}
+
+	/// The local address the connection was accepted on.
+	pub fn local_addr(&self) -> io::Result<SocketAddr> {
+		self.stream.as_tcp().local_addr()
+	}
+}
+
 /// Trait to take a stream or similar and attempt to recover the start of a
 /// websocket handshake from it (asynchronously).
 /// Should be used when a stream might contain a request for a websocket session.
@@ -260,7 +310,86 @@ where
 				stream,
 				request: m,
 				buffer,
+				connection_guard: None,
+				observer: None,
 			});
 		Box::new(future)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::header::WebSocketProtocol;
+	use crate::result::towse;
+	use crate::stream::ReadWritePair;
+	use std::io::Cursor;
+
+	#[test]
+	fn accept_with_fn_sees_negotiated_headers() {
+		let request = b"GET / HTTP/1.1\r\n\
+		                Host: localhost\r\n\
+		                Upgrade: websocket\r\n\
+		                Connection: Upgrade\r\n\
+		                Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+		                Sec-WebSocket-Protocol: chat\r\n\
+		                Sec-WebSocket-Version: 13\r\n\
+		                \r\n";
+		let stream = ReadWritePair(Cursor::new(&request[..]), Cursor::new(Vec::new()));
+		let (client, _) = stream
+			.into_ws()
+			.map_err(|e| towse(e.3))
+			.and_then(|upgrade| {
+				upgrade.use_protocol("chat").accept_with_fn(|headers| {
+					let protocol = &headers.get::<WebSocketProtocol>().unwrap().0[0];
+					let mut custom = Headers::new();
+					custom.set_raw("X-Session-Id", vec![format!("{}-1", protocol).into_bytes()]);
+					custom
+				})
+			})
+			.wait()
+			.unwrap();
+
+		let response = String::from_utf8(client.into_parts().io.1.into_inner()).unwrap();
+		assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+		assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+		assert!(
+			response.contains("X-Session-Id: chat-1\r\n"),
+			"{}",
+			response
+		);
+	}
+
+	#[test]
+	fn upgrade_knows_the_connection_addresses() {
+		use std::io::Write;
+		use tokio_tcp::TcpListener;
+
+		let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+		let addr = listener.local_addr().unwrap();
+		let mut peer = std::net::TcpStream::connect(addr).unwrap();
+		peer.write_all(
+			b"GET / HTTP/1.1\r\n\
+			  Host: localhost\r\n\
+			  Upgrade: websocket\r\n\
+			  Connection: Upgrade\r\n\
+			  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+			  Sec-WebSocket-Version: 13\r\n\
+			  \r\n",
+		)
+		.unwrap();
+
+		let future = listener
+			.incoming()
+			.into_future()
+			.map_err(|(e, _)| HyperIntoWsError::from(e))
+			.and_then(|(stream, _)| stream.unwrap().into_ws().map_err(|e| e.3));
+		let upgrade = tokio::runtime::Runtime::new()
+			.unwrap()
+			.block_on(future)
+			.unwrap();
+
+		assert_eq!(upgrade.peer_addr().unwrap(), peer.local_addr().unwrap());
+		assert_eq!(upgrade.local_addr().unwrap(), addr);
+	}
+}
@@ -2,12 +2,16 @@
 //! WebSocket client.
 use crate::header::extensions::Extension;
 use crate::header::{
-	Origin, WebSocketAccept, WebSocketExtensions, WebSocketKey, WebSocketProtocol, WebSocketVersion,
+	origin_from, WebSocketAccept, WebSocketExtensions, WebSocketKey, WebSocketProtocol,
+	WebSocketVersion,
 };
+use crate::observer::Observer;
+use crate::server::ConnectionGuard;
 use crate::stream::Stream;
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
 use std::io;
+use std::sync::Arc;
 
 use hyper::header::{Connection, ConnectionOption, Headers, Protocol, ProtocolName, Upgrade};
 use hyper::http::h1::Incoming;
@@ -16,6 +20,8 @@ use hyper::status::StatusCode;
 use hyper::uri::RequestUri;
 use unicase::UniCase;
 
+#[cfg(any(feature = "sync", feature = "async"))]
+use hyper::header::{ContentLength, TransferEncoding};
 #[cfg(any(feature = "sync", feature = "async"))]
 use hyper::version::HttpVersion;
 
@@ -50,6 +56,13 @@ where
 	pub request: Request,
 	/// Some buffered data from the stream, if it exists.
 	pub buffer: B,
+	/// The slot this connection takes in the server's connection limit, if it has one.
+	/// It is handed on to the client when the connection is accepted.
+	#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+	pub(crate) connection_guard: Option<ConnectionGuard>,
+	/// The observer of the server that accepted this connection, if it has one.
+	/// It is handed on to the client when the connection is accepted.
+	pub observer: Option<Arc<dyn Observer>>,
 }
 
 impl<S, B> WsUpgrade<S, B>
@@ -68,6 +81,25 @@ where
 		self
 	}
 
+	/// Select the first protocol the client offered that is one of `allowed`,
+	/// for a server that only talks to clients speaking one of its protocols.
+	///
+	/// The client's order of preference is kept. If the client offered none
+	/// of `allowed` (or no protocol at all) the upgrade is given back
+	/// untouched as the error, so it can be rejected.
+	#[allow(clippy::result_large_err)]
+	pub fn require_protocol(self, allowed: &[&str]) -> Result<Self, Self> {
+		let selected = self
+			.protocols()
+			.iter()
+			.find(|offered| allowed.contains(&offered.as_str()))
+			.cloned();
+		match selected {
+			Some(protocol) => Ok(self.use_protocol(protocol)),
+			None => Err(self),
+		}
+	}
+
 	/// Select an extension to use in the handshake response.
 	pub fn use_extension(mut self, extension: Extension) -> Self {
 		upsert_header!(self.headers; WebSocketExtensions; {
@@ -130,7 +162,17 @@ where
 
 	/// Origin of the client
 	pub fn origin(&self) -> Option<&str> {
-		self.request.headers.get::<Origin>().map(|o| &o.0 as &str)
+		origin_from(&self.request.headers)
+	}
+
+	/// The value of the request header `name` (case-insensitive) as it was
+	/// sent, e.g. `X-Forwarded-For` when running behind a proxy.
+	///
+	/// If the header was sent more than once only the first value is
+	/// returned. Values that aren't valid UTF-8 are treated as missing.
+	pub fn header_str(&self, name: &str) -> Option<&str> {
+		let raw = self.request.headers.get_raw(name)?.first()?;
+		std::str::from_utf8(raw).ok()
 	}
 
 	#[cfg(feature = "sync")]
@@ -187,6 +229,11 @@ pub enum HyperIntoWsError {
 	Io(io::Error),
 	/// Error while parsing an incoming request
 	Parsing(::hyper::error::Error),
+	/// The server already has as many live connections as its connection limit allows
+	TooManyConnections,
+	/// A websocket upgrade request must not have a body, i.e. neither a non-zero
+	/// `Content-Length` nor a `Transfer-Encoding` header
+	RequestHasBody,
 }
 
 impl Display for HyperIntoWsError {
@@ -212,6 +259,32 @@ impl Display for HyperIntoWsError {
 			}
 			HyperIntoWsError::Io(ref e) => fmt.write_str(e.to_string().as_str()),
 			HyperIntoWsError::Parsing(ref e) => fmt.write_str(e.to_string().as_str()),
+			HyperIntoWsError::TooManyConnections => {
+				fmt.write_str("Too many concurrent connections")
+			}
+			HyperIntoWsError::RequestHasBody => fmt.write_str("Upgrade request has a body"),
+		}
+	}
+}
+
+impl HyperIntoWsError {
+	/// The status code and headers a server should respond with when a handshake
+	/// fails because of this error.
+	///
+	/// An unsupported websocket version gets a `426 Upgrade Required` with a
+	/// `Sec-WebSocket-Version` header listing the versions this crate supports
+	/// (see [RFC6455 section 4.4](https://tools.ietf.org/html/rfc6455#section-4.4)),
+	/// A server at its connection limit answers `503 Service Unavailable`,
+	/// anything else is a plain `400 Bad Request`.
+	pub fn rejection(&self) -> (StatusCode, Headers) {
+		let mut headers = Headers::new();
+		match *self {
+			HyperIntoWsError::UnsupportedWebsocketVersion => {
+				headers.set(WebSocketVersion::WebSocket13);
+				(StatusCode::UpgradeRequired, headers)
+			}
+			HyperIntoWsError::TooManyConnections => (StatusCode::ServiceUnavailable, headers),
+			_ => (StatusCode::BadRequest, headers),
 		}
 	}
 }
@@ -244,6 +317,7 @@ impl From<crate::codec::http::HttpCodecError> for HyperIntoWsError {
 		match src {
 			crate::codec::http::HttpCodecError::Io(e) => HyperIntoWsError::Io(e),
 			crate::codec::http::HttpCodecError::Http(e) => HyperIntoWsError::Parsing(e),
+			crate::codec::http::HttpCodecError::Malformed(e, _) => HyperIntoWsError::Parsing(e),
 		}
 	}
 }
@@ -259,6 +333,15 @@ pub fn validate(
 		return Err(HyperIntoWsError::MethodNotGet);
 	}
 
+	// the body would be taken for websocket frames, so refuse requests
+	// whose end is ambiguous instead of guessing
+	let has_length = headers.get_raw("Content-Length").is_some();
+	if (has_length && headers.get::<ContentLength>() != Some(&ContentLength(0)))
+		|| headers.has::<TransferEncoding>()
+	{
+		return Err(HyperIntoWsError::RequestHasBody);
+	}
+
 	if version == HttpVersion::Http09 || version == HttpVersion::Http10 {
 		return Err(HyperIntoWsError::UnsupportedHttpVersion);
 	}
@@ -7,7 +7,7 @@ use std::io;
 use std::net::TcpStream;
 
 use hyper::buffer::BufReader;
-use hyper::header::Headers;
+use hyper::header::{Headers, Location};
 use hyper::http::h1::parse_request;
 use hyper::http::h1::Incoming;
 use hyper::net::NetworkStream;
@@ -67,21 +67,57 @@ where
 	/// Accept the handshake request and send a response,
 	/// if nothing goes wrong a client will be created.
 	pub fn accept_with_limits(self, max_dataframe_size: usize, max_message_size: usize) -> Result<Client<S>, (S, io::Error)> {
-		self.internal_accept_with_limits(None, max_dataframe_size, max_message_size)
+		self.internal_accept_with_limits(None, max_dataframe_size, max_message_size, false)
 	}
 
 	/// Accept the handshake request and send a response while
 	/// adding on a few headers. These headers are added before the required
 	/// headers are, so some might be overwritten.
-	pub fn accept_with_headers_and_limits(self, custom_headers: &Headers, max_dataframe_size: usize, max_message_size: usize) -> Result<Client<S>, (S, io::Error)> {
-		self.internal_accept_with_limits(Some(custom_headers), max_dataframe_size, max_message_size)
+	pub fn accept_with_headers_and_limits(
+		self,
+		custom_headers: &Headers,
+		max_dataframe_size: usize,
+		max_message_size: usize,
+	) -> Result<Client<S>, (S, io::Error)> {
+		self.internal_accept_with_limits(
+			Some(custom_headers),
+			max_dataframe_size,
+			max_message_size,
+			false,
+		)
+	}
+
+	/// Accept the handshake request like `accept`, but mask the frames sent
+	/// to the client.
+	///
+	/// **This violates RFC6455**, which forbids servers from masking frames,
+	/// and conforming clients will fail the connection. Only use this to
+	/// interoperate with known-broken endpoints that expect masked frames.
+	pub fn accept_masked(self) -> Result<Client<S>, (S, io::Error)> {
+		self.internal_accept_with_limits(
+			None,
+			DEFAULT_MAX_DATAFRAME_SIZE,
+			DEFAULT_MAX_MESSAGE_SIZE,
+			true,
+		)
 	}
 
 	fn internal_accept(self, headers: Option<&Headers>) -> Result<Client<S>, (S, io::Error)> {
-		self.internal_accept_with_limits(headers, DEFAULT_MAX_DATAFRAME_SIZE, DEFAULT_MAX_MESSAGE_SIZE)
+		self.internal_accept_with_limits(
+			headers,
+			DEFAULT_MAX_DATAFRAME_SIZE,
+			DEFAULT_MAX_MESSAGE_SIZE,
+			false,
+		)
 	}
 
-	fn internal_accept_with_limits(mut self, headers: Option<&Headers>, max_dataframe_size: usize, max_message_size: usize) -> Result<Client<S>, (S, io::Error)> {
+	fn internal_accept_with_limits(
+		mut self,
+		headers: Option<&Headers>,
+		max_dataframe_size: usize,
+		max_message_size: usize,
+		out_mask: bool,
+	) -> Result<Client<S>, (S, io::Error)> {
 		let status = self.prepare_headers(headers);
 
 		if let Err(e) = self.send(status) {
@@ -93,7 +129,20 @@ where
 			None => BufReader::new(self.stream),
 		};
 
-		Ok(Client::unchecked_with_limits(stream, self.headers, false, true, max_dataframe_size, max_message_size))
+		let mut client = Client::unchecked_with_limits(
+			stream,
+			self.headers,
+			out_mask,
+			true,
+			max_dataframe_size,
+			max_message_size,
+		);
+		client.set_connection_guard(self.connection_guard);
+		if let Some(observer) = self.observer {
+			observer.handshake_complete();
+			client.set_observer(Some(observer));
+		}
+		Ok(client)
 	}
 
 	/// Reject the client's request to make a websocket connection.
@@ -107,11 +156,26 @@ where
 		self.internal_reject(Some(headers))
 	}
 
-	fn internal_reject(mut self, headers: Option<&Headers>) -> Result<S, (S, io::Error)> {
+	/// Redirect the client's request to make a websocket connection to another
+	/// location, using a `3xx` status code such as `StatusCode::TemporaryRedirect`.
+	pub fn redirect(mut self, status: StatusCode, location: &str) -> Result<S, (S, io::Error)> {
+		self.headers.set(Location(location.to_string()));
+		self.internal_reject_with_status(status, None)
+	}
+
+	fn internal_reject(self, headers: Option<&Headers>) -> Result<S, (S, io::Error)> {
+		self.internal_reject_with_status(StatusCode::BadRequest, headers)
+	}
+
+	fn internal_reject_with_status(
+		mut self,
+		status: StatusCode,
+		headers: Option<&Headers>,
+	) -> Result<S, (S, io::Error)> {
 		if let Some(custom) = headers {
 			self.headers.extend(custom.iter());
 		}
-		match self.send(StatusCode::BadRequest) {
+		match self.send(status) {
 			Ok(()) => Ok(self.stream),
 			Err(e) => Err((self.stream, e)),
 		}
@@ -200,6 +264,8 @@ where
 				stream,
 				request,
 				buffer,
+				connection_guard: None,
+				observer: None,
 			}),
 			Err(e) => Err((stream, Some(request), buffer, e)),
 		}
@@ -220,6 +286,8 @@ where
 				stream: self.0,
 				request: self.1,
 				buffer: None,
+				connection_guard: None,
+				observer: None,
 			}),
 			Err(e) => Err((self.0, self.1, e)),
 		}
@@ -292,6 +360,250 @@ impl<'a, 'b> IntoWs for HyperRequest<'a, 'b> {
 				headers,
 				subject: (method, uri),
 			},
+			connection_guard: None,
+			observer: None,
 		})
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::header::WebSocketProtocol;
+	use crate::stream::ReadWritePair;
+	use std::io::Cursor;
+
+	#[test]
+	fn redirect_upgrade() {
+		let request = b"GET / HTTP/1.1\r\n\
+		                Host: localhost\r\n\
+		                Upgrade: websocket\r\n\
+		                Connection: Upgrade\r\n\
+		                Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+		                Sec-WebSocket-Version: 13\r\n\
+		                \r\n";
+		let stream = ReadWritePair(Cursor::new(&request[..]), Cursor::new(Vec::new()));
+		let upgrade = match stream.into_ws() {
+			Ok(u) => u,
+			Err(e) => panic!("unexpected error {}", e.3),
+		};
+
+		let stream = match upgrade.redirect(StatusCode::TemporaryRedirect, "ws://elsewhere/") {
+			Ok(s) => s,
+			Err(e) => panic!("unexpected error {}", e.1),
+		};
+		let response = String::from_utf8(stream.1.into_inner()).unwrap();
+		assert!(response.starts_with("HTTP/1.1 307 Temporary Redirect\r\n"));
+		assert!(response.contains("Location: ws://elsewhere/\r\n"));
+	}
+
+	#[test]
+	fn require_protocol_selects_an_offered_protocol() {
+		let upgrade = |protocols: &str| {
+			let request = format!(
+				"GET / HTTP/1.1\r\n\
+				 Host: localhost\r\n\
+				 Upgrade: websocket\r\n\
+				 Connection: Upgrade\r\n\
+				 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+				 Sec-WebSocket-Version: 13\r\n\
+				 {}\r\n",
+				protocols
+			);
+			let stream = ReadWritePair(Cursor::new(request.into_bytes()), Cursor::new(Vec::new()));
+			match stream.into_ws() {
+				Ok(u) => u,
+				Err(e) => panic!("unexpected error {}", e.3),
+			}
+		};
+
+		let accepted = upgrade("Sec-WebSocket-Protocol: chat, superchat\r\n")
+			.require_protocol(&["superchat", "chat"])
+			.ok()
+			.unwrap();
+		assert_eq!(
+			accepted.headers.get::<WebSocketProtocol>(),
+			Some(&WebSocketProtocol(vec!["chat".to_string()]))
+		);
+
+		let refused = upgrade("Sec-WebSocket-Protocol: mqtt\r\n")
+			.require_protocol(&["chat"])
+			.err()
+			.unwrap();
+		assert!(refused.headers.get::<WebSocketProtocol>().is_none());
+		assert!(upgrade("").require_protocol(&["chat"]).is_err());
+	}
+
+	#[test]
+	fn observer_follows_the_connection() {
+		use crate::message::{OwnedMessage, Type};
+		use crate::observer::Observer;
+		use crate::result::WebSocketError;
+		use std::sync::{Arc, Mutex};
+
+		#[derive(Default)]
+		struct Events(Mutex<Vec<String>>);
+
+		impl Events {
+			fn push(&self, event: String) {
+				self.0.lock().unwrap().push(event);
+			}
+		}
+
+		impl Observer for Events {
+			fn handshake_complete(&self) {
+				self.push("open".to_string());
+			}
+			fn message_sent(&self, kind: Option<Type>, size: usize) {
+				self.push(format!("sent {:?} {}", kind, size));
+			}
+			fn message_received(&self, kind: Type, size: usize) {
+				self.push(format!("received {:?} {}", kind, size));
+			}
+			fn close_sent(&self, code: u16) {
+				self.push(format!("close sent {}", code));
+			}
+			fn close_received(&self, code: u16) {
+				self.push(format!("close received {}", code));
+			}
+			fn error(&self, _error: &WebSocketError) {
+				self.push("error".to_string());
+			}
+		}
+
+		let mut request = b"GET / HTTP/1.1\r\n\
+		                    Host: localhost\r\n\
+		                    Upgrade: websocket\r\n\
+		                    Connection: Upgrade\r\n\
+		                    Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+		                    Sec-WebSocket-Version: 13\r\n\
+		                    \r\n"
+			.to_vec();
+		request.extend_from_slice(b"\x89\x82\0\0\0\0hi");
+		request.extend_from_slice(b"\x81\x83\0\0\0\0hey");
+		request.extend_from_slice(b"\x88\x82\0\0\0\0\x03\xe8");
+		let stream = ReadWritePair(Cursor::new(request), Cursor::new(Vec::new()));
+		let mut upgrade = match stream.into_ws() {
+			Ok(u) => u,
+			Err(e) => panic!("unexpected error {}", e.3),
+		};
+		let events = Arc::new(Events::default());
+		upgrade.observer = Some(events.clone());
+		let mut client = upgrade.accept().ok().unwrap();
+
+		let ping = client.recv_message().unwrap();
+		client
+			.send_message(&OwnedMessage::Pong(b"hi".to_vec()))
+			.unwrap();
+		assert_eq!(ping, OwnedMessage::Ping(b"hi".to_vec()));
+		client.recv_message().unwrap();
+		client.recv_message().unwrap();
+		client.close_nowait(None).unwrap();
+		assert!(client.recv_message().is_err());
+
+		assert_eq!(
+			*events.0.lock().unwrap(),
+			vec![
+				"open",
+				"received Ping 4",
+				"sent Some(Pong) 4",
+				"received Text 5",
+				"received Close 4",
+				"close received 1000",
+				"sent Some(Close) 2",
+				"close sent 1005",
+				"error",
+			]
+		);
+	}
+
+	#[test]
+	fn header_str_reads_raw_headers() {
+		let request = b"GET / HTTP/1.1\r\n\
+		                Host: localhost\r\n\
+		                Upgrade: websocket\r\n\
+		                Connection: Upgrade\r\n\
+		                Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+		                Sec-WebSocket-Version: 13\r\n\
+		                X-Forwarded-For: 203.0.113.7, 10.0.0.1\r\n\
+		                \r\n";
+		let stream = ReadWritePair(Cursor::new(&request[..]), Cursor::new(Vec::new()));
+		let upgrade = match stream.into_ws() {
+			Ok(u) => u,
+			Err(e) => panic!("unexpected error {}", e.3),
+		};
+
+		assert_eq!(
+			upgrade.header_str("x-forwarded-for"),
+			Some("203.0.113.7, 10.0.0.1")
+		);
+		assert_eq!(upgrade.header_str("X-Real-IP"), None);
+	}
+
+	#[test]
+	fn accept_over_split_stream() {
+		use crate::message::{Message, OwnedMessage};
+		use crate::ws::Message as MessageTrait;
+
+		let mut input = b"GET / HTTP/1.1\r\n\
+		                  Host: localhost\r\n\
+		                  Upgrade: websocket\r\n\
+		                  Connection: Upgrade\r\n\
+		                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+		                  Sec-WebSocket-Version: 13\r\n\
+		                  \r\n"
+			.to_vec();
+		Message::text("over the wire")
+			.serialize(&mut input, true)
+			.unwrap();
+
+		let stream = ReadWritePair(Cursor::new(input), Vec::new());
+		let client = match stream.into_ws() {
+			Ok(u) => u.accept().map_err(|e| e.1).unwrap(),
+			Err(e) => panic!("unexpected error {}", e.3),
+		};
+
+		let (mut reader, mut writer) = client.split().unwrap();
+		assert_eq!(
+			reader.recv_message().unwrap(),
+			OwnedMessage::Text("over the wire".to_string())
+		);
+		writer.send_message(&Message::text("and back")).unwrap();
+
+		let mut expected = Vec::new();
+		Message::text("and back")
+			.serialize(&mut expected, false)
+			.unwrap();
+		let output = writer.stream;
+		assert!(output.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+		assert!(output.ends_with(&expected));
+	}
+
+	#[test]
+	fn accept_masked_masks_outgoing_frames() {
+		use crate::message::{Message, OwnedMessage};
+		use crate::receiver::Receiver;
+		use crate::ws::Receiver as ReceiverTrait;
+
+		let request = b"GET / HTTP/1.1\r\n\
+		                Host: localhost\r\n\
+		                Upgrade: websocket\r\n\
+		                Connection: Upgrade\r\n\
+		                Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+		                Sec-WebSocket-Version: 13\r\n\
+		                \r\n";
+		let stream = ReadWritePair(Cursor::new(&request[..]), Vec::new());
+		let mut client = match stream.into_ws() {
+			Ok(u) => u.accept_masked().map_err(|e| e.1).unwrap(),
+			Err(e) => panic!("unexpected error {}", e.3),
+		};
+		client.send_message(&Message::text("masked")).unwrap();
+
+		let (stream, _) = client.into_stream();
+		let output = stream.1;
+		let start = output.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+		let mut frames = &output[start..];
+		let message = Receiver::new(true).recv_message(&mut frames).unwrap();
+		assert_eq!(message, OwnedMessage::Text("masked".to_string()));
+	}
+}
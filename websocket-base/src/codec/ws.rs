@@ -13,7 +13,6 @@ extern crate tokio_codec;
 use std::borrow::Borrow;
 use std::io::Cursor;
 use std::marker::PhantomData;
-use std::mem;
 
 use self::bytes::BufMut;
 use self::bytes::BytesMut;
@@ -21,16 +20,17 @@ use self::tokio_codec::Decoder;
 use self::tokio_codec::Encoder;
 
 use crate::dataframe::DataFrame;
-use crate::message::OwnedMessage;
-use crate::result::WebSocketError;
+use crate::message::{Fragment, FragmentState, Message, OwnedMessage};
+use crate::result::{ProtocolViolation, WebSocketError};
 use crate::ws::dataframe::DataFrame as DataFrameTrait;
 use crate::ws::message::Message as MessageTrait;
-use crate::ws::util::header::read_header;
+use crate::ws::util::header::read_header_with_mask;
 
 const DEFAULT_MAX_DATAFRAME_SIZE : usize = 1024*1024*100;
 const DEFAULT_MAX_MESSAGE_SIZE : usize = 1024*1024*200;
 const MAX_DATAFRAMES_IN_ONE_MESSAGE: usize = 1024*1024;
 const PER_DATAFRAME_OVERHEAD : usize = 64;
+const DEFAULT_MAX_EMPTY_FRAMES: usize = 1024;
 
 /// Even though a websocket connection may look perfectly symmetrical
 /// in reality there are small differences between clients and servers.
@@ -68,6 +68,10 @@ pub struct DataFrameCodec<D> {
 	is_server: bool,
 	frame_type: PhantomData<D>,
 	max_dataframe_size: u32,
+	bytes_sent: u64,
+	bytes_received: u64,
+	frames_received: u64,
+	max_total_frames: Option<u64>,
 }
 
 impl DataFrameCodec<DataFrame> {
@@ -100,8 +104,35 @@ impl<D> DataFrameCodec<D> {
 			is_server: context == Context::Server,
 			frame_type: PhantomData,
 			max_dataframe_size,
+			bytes_sent: 0,
+			bytes_received: 0,
+			frames_received: 0,
+			max_total_frames: None,
 		}
 	}
+
+	/// The number of bytes encoded by this codec so far, including frame headers.
+	pub fn bytes_sent(&self) -> u64 {
+		self.bytes_sent
+	}
+
+	/// The number of bytes of complete data frames decoded by this codec so far,
+	/// including frame headers.
+	pub fn bytes_received(&self) -> u64 {
+		self.bytes_received
+	}
+
+	/// The number of data frames decoded by this codec so far.
+	pub fn frames_received(&self) -> u64 {
+		self.frames_received
+	}
+
+	/// Fail with `ProtocolViolation::TooManyFrames` once more than `max`
+	/// frames have been received over the codec's lifetime, or lift the
+	/// limit with `None`.
+	pub fn set_max_total_frames(&mut self, max: Option<u64>) {
+		self.max_total_frames = max;
+	}
 }
 
 impl<D> Decoder for DataFrameCodec<D> {
@@ -115,7 +146,7 @@ impl<D> Decoder for DataFrameCodec<D> {
 			let mut reader = Cursor::new(src.as_ref());
 
 			// read header to get the size, bail if not enough
-			let header = match read_header(&mut reader) {
+			let header = match read_header_with_mask(&mut reader, self.is_server) {
 				Ok(head) => head,
 				Err(WebSocketError::NoDataAvailable) => return Ok(None),
 				Err(e) => return Err(e),
@@ -125,9 +156,12 @@ impl<D> Decoder for DataFrameCodec<D> {
 		};
 
 		if header.len > self.max_dataframe_size as u64 {
-			return Err(WebSocketError::ProtocolError(
-				"Exceeded maximum incoming DataFrame size",
-			));
+			return Err(ProtocolViolation::MessageTooBig.into());
+		}
+		if let Some(max) = self.max_total_frames {
+			if self.frames_received >= max {
+				return Err(ProtocolViolation::TooManyFrames.into());
+			}
 		}
 
 		// check if we have enough bytes to continue
@@ -138,6 +172,8 @@ impl<D> Decoder for DataFrameCodec<D> {
 		// TODO: using usize is not the right thing here (can be larger)
 		let _ = src.split_to(bytes_read as usize);
 		let body = src.split_to(header.len as usize).to_vec();
+		self.bytes_received += bytes_read + header.len;
+		self.frames_received += 1;
 
 		// construct a dataframe
 		Ok(Some(DataFrame::read_dataframe_body(
@@ -161,7 +197,10 @@ where
 		if frame_size > dst.remaining_mut() {
 			dst.reserve(frame_size);
 		}
-		item.borrow().write_to(&mut dst.writer(), masked)
+		let before = dst.len();
+		let result = item.borrow().write_to(&mut dst.writer(), masked);
+		self.bytes_sent += (dst.len() - before) as u64;
+		result
 	}
 }
 
@@ -224,6 +263,13 @@ where
 	dataframe_codec: DataFrameCodec<DataFrame>,
 	message_type: PhantomData<fn(M)>,
 	max_message_size: u32,
+	max_frame_size: Option<usize>,
+	strict_close: bool,
+	empty_frames: usize,
+	max_empty_frames: Option<usize>,
+	/// An error `decode_all` ran into after decoding some messages, which
+	/// the next call reports.
+	pending_error: Option<WebSocketError>,
 }
 
 impl MessageCodec<OwnedMessage> {
@@ -264,8 +310,71 @@ where
 			dataframe_codec: DataFrameCodec::new_with_limits(context, max_dataframe_size),
 			message_type: PhantomData,
 			max_message_size,
+			max_frame_size: None,
+			strict_close: false,
+			empty_frames: 0,
+			max_empty_frames: Some(DEFAULT_MAX_EMPTY_FRAMES),
+			pending_error: None,
 		}
 	}
+
+	/// Automatically split outgoing messages whose payload is larger than
+	/// `max_frame_size` into several fragments, or stop doing so with `None`.
+	/// Control messages are never fragmented.
+	pub fn set_auto_fragment(&mut self, max_frame_size: Option<usize>) {
+		self.max_frame_size = max_frame_size;
+	}
+
+	/// Fail with `ProtocolViolation::DataAfterClose` when a close frame is
+	/// followed by more bytes in the same read, instead of ignoring them.
+	///
+	/// Nothing may follow a close frame, so such bytes point at a peer that
+	/// pipelines data after closing or at frames that were misread.
+	pub fn set_strict_close(&mut self, strict: bool) {
+		self.strict_close = strict;
+	}
+
+	/// The number of bytes encoded by this codec so far, including frame headers.
+	///
+	/// For a `Framed` client this is available through `client.codec().bytes_sent()`.
+	pub fn bytes_sent(&self) -> u64 {
+		self.dataframe_codec.bytes_sent
+	}
+
+	/// The number of bytes of complete data frames decoded by this codec so far,
+	/// including frame headers.
+	pub fn bytes_received(&self) -> u64 {
+		self.dataframe_codec.bytes_received
+	}
+
+	/// The number of data frames decoded by this codec so far.
+	pub fn frames_received(&self) -> u64 {
+		self.dataframe_codec.frames_received
+	}
+
+	/// Fail with `ProtocolViolation::TooManyFrames` once more than `max`
+	/// frames have been received over the connection's lifetime, or lift the
+	/// limit with `None`.
+	///
+	/// Unlike the synchronous `Client`, the codec doesn't close the
+	/// connection itself: whoever polls the stream should send a close
+	/// message with the violation's `close_code`, 1008 (Policy Violation).
+	/// The codec of a client from an accepted upgrade can be reached with
+	/// `client.codec_mut()`.
+	pub fn set_max_total_frames(&mut self, max: Option<u64>) {
+		self.dataframe_codec.set_max_total_frames(max);
+	}
+
+	/// Fail with `ProtocolViolation::TooManyEmptyFrames` once a message has
+	/// more than `max` empty, non-final frames in a row, or lift the limit
+	/// with `None`. The default is 1024.
+	///
+	/// Such frames are allowed, but make no progress, so a peer can keep a
+	/// message open with them at little cost. The connection should then be
+	/// closed with the violation's `close_code`, 1009 (Message Too Big).
+	pub fn set_max_empty_frames(&mut self, max: Option<usize>) {
+		self.max_empty_frames = max;
+	}
 }
 
 impl<M> Decoder for MessageCodec<M>
@@ -276,17 +385,27 @@ where
 	type Error = WebSocketError;
 
 	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		if let Some(err) = self.pending_error.take() {
+			return Err(err);
+		}
 		let mut current_message_length : usize = self.buffer.iter().map(|x|x.data.len()).sum();
 		while let Some(frame) = self.dataframe_codec.decode(src)? {
+			// no extension gives them a meaning, so fail on every frame that has
+			// one set, control frames and unfinished messages included
+			if frame.reserved != [false; 3] {
+				return Err(ProtocolViolation::ReservedBits.into());
+			}
 			let is_first = self.buffer.is_empty();
 			let finished = frame.finished;
 
 			match frame.opcode as u8 {
 				// continuation code
 				0 if is_first => {
-					return Err(WebSocketError::ProtocolError(
-						"Unexpected continuation data frame opcode",
-					));
+					return Err(ProtocolViolation::UnexpectedContinuation.into());
+				}
+				// close frame with data following it
+				8 if self.strict_close && !src.is_empty() => {
+					return Err(ProtocolViolation::DataAfterClose.into());
 				}
 				// control frame
 				8..=15 => {
@@ -294,30 +413,32 @@ where
 				}
 				// data frame
 				1..=7 if !is_first => {
-					return Err(WebSocketError::ProtocolError(
-						"Unexpected data frame opcode",
-					));
+					return Err(ProtocolViolation::UnfinishedMessage.into());
 				}
 				// its good
 				_ => {
+					if frame.data.is_empty() && !finished {
+						self.empty_frames += 1;
+						if self.max_empty_frames.map(|max| self.empty_frames > max) == Some(true) {
+							return Err(ProtocolViolation::TooManyEmptyFrames.into());
+						}
+					} else {
+						self.empty_frames = 0;
+					}
 					current_message_length += frame.data.len() + PER_DATAFRAME_OVERHEAD;
 					self.buffer.push(frame);
 				}
 			};
 
 			if finished {
-				let buffer = mem::replace(&mut self.buffer, Vec::new());
-				return Ok(Some(OwnedMessage::from_dataframes(buffer)?));
+				let message = Message::drain_dataframes(&mut self.buffer)?;
+				return Ok(Some(message.into()));
 			} else {
 				if self.buffer.len() >= MAX_DATAFRAMES_IN_ONE_MESSAGE {
-					return Err(WebSocketError::ProtocolError(
-						"Exceeded count of data frames in one WebSocket message",
-					));
+					return Err(ProtocolViolation::MessageTooBig.into());
 				}
 				if current_message_length > self.max_message_size as usize {
-					return Err(WebSocketError::ProtocolError(
-						"Exceeded maximum WebSocket message size",
-					));
+					return Err(ProtocolViolation::MessageTooBig.into());
 				}
 			}
 		}
@@ -326,6 +447,32 @@ where
 	}
 }
 
+impl<M> MessageCodec<M>
+where
+	M: MessageTrait,
+{
+	/// Decodes all the complete messages currently in `src` at once,
+	/// leaving any incomplete message buffered for later.
+	///
+	/// Each message is subject to the same limits as with `decode`. If one
+	/// of them fails after others were decoded, those are returned and the
+	/// error is reported by the next call to `decode` or `decode_all`.
+	pub fn decode_all(&mut self, src: &mut BytesMut) -> Result<Vec<OwnedMessage>, WebSocketError> {
+		let mut messages = Vec::new();
+		loop {
+			match self.decode(src) {
+				Ok(Some(message)) => messages.push(message),
+				Ok(None) => return Ok(messages),
+				Err(err) if !messages.is_empty() => {
+					self.pending_error = Some(err);
+					return Ok(messages);
+				}
+				Err(err) => return Err(err),
+			}
+		}
+	}
+}
+
 impl<M> Encoder for MessageCodec<M>
 where
 	M: MessageTrait,
@@ -339,7 +486,187 @@ where
 		if frame_size > dst.remaining_mut() {
 			dst.reserve(frame_size);
 		}
-		item.serialize(&mut dst.writer(), masked)
+		let before = dst.len();
+		let result = match self.max_frame_size {
+			Some(max) => item.serialize_fragmented(&mut dst.writer(), masked, max),
+			None => item.serialize(&mut dst.writer(), masked),
+		};
+		self.dataframe_codec.bytes_sent += (dst.len() - before) as u64;
+		result
+	}
+}
+
+/// Encodes `message` and decodes it back with `MessageCodec`, as sent by a
+/// client if `masked` and by a server otherwise.
+///
+/// This is meant for property tests and fuzzers, which can check that every
+/// message comes back unchanged. Bytes left over after decoding, or a message
+/// that doesn't decode from what was encoded, are reported as errors.
+///
+/// ```rust
+/// # use websocket_base::codec::ws::roundtrip;
+/// # use websocket_base::OwnedMessage;
+/// let message = OwnedMessage::Binary(vec![7; 70_000]);
+/// assert_eq!(roundtrip(&message, true).unwrap(), message);
+/// ```
+pub fn roundtrip(message: &OwnedMessage, masked: bool) -> Result<OwnedMessage, WebSocketError> {
+	let (sender, receiver) = if masked {
+		(Context::Client, Context::Server)
+	} else {
+		(Context::Server, Context::Client)
+	};
+	let mut buf = BytesMut::new();
+	MessageCodec::default(sender).encode(message.clone(), &mut buf)?;
+	let decoded = MessageCodec::default(receiver).decode(&mut buf)?;
+	match decoded {
+		Some(decoded) if buf.is_empty() => Ok(decoded),
+		Some(_) => Err(WebSocketError::ProtocolError(
+			"Bytes left over after decoding the message",
+		)),
+		None => Err(WebSocketError::ProtocolError(
+			"The encoded message could not be decoded",
+		)),
+	}
+}
+
+/// A codec that decodes websocket messages in batches: every item is
+/// the list of all the complete messages that were buffered at that time.
+///
+/// This lets consumers process bursts of small messages in one go (e.g. with
+/// a single lock acquisition or channel send). A message that fails to
+/// decode ends the batch and its error is reported in place of the next one.
+/// Encoding works just like with `MessageCodec`, one message at a time.
+pub struct BatchMessageCodec<M>
+where
+	M: MessageTrait,
+{
+	codec: MessageCodec<M>,
+}
+
+impl<M> BatchMessageCodec<M>
+where
+	M: MessageTrait,
+{
+	/// Decodes batches of messages using the settings and limits of `codec`.
+	pub fn new(codec: MessageCodec<M>) -> Self {
+		BatchMessageCodec { codec }
+	}
+
+	/// Gets a reference to the underlying message codec.
+	pub fn get_ref(&self) -> &MessageCodec<M> {
+		&self.codec
+	}
+
+	/// Gets a mutable reference to the underlying message codec.
+	pub fn get_mut(&mut self) -> &mut MessageCodec<M> {
+		&mut self.codec
+	}
+
+	/// Gives back the underlying message codec.
+	pub fn into_inner(self) -> MessageCodec<M> {
+		self.codec
+	}
+}
+
+impl<M> Decoder for BatchMessageCodec<M>
+where
+	M: MessageTrait,
+{
+	type Item = Vec<OwnedMessage>;
+	type Error = WebSocketError;
+
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		let messages = self.codec.decode_all(src)?;
+		if messages.is_empty() {
+			Ok(None)
+		} else {
+			Ok(Some(messages))
+		}
+	}
+}
+
+impl<M> Encoder for BatchMessageCodec<M>
+where
+	M: MessageTrait,
+{
+	type Item = M;
+	type Error = WebSocketError;
+
+	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		self.codec.encode(item, dst)
+	}
+}
+
+/// A codec that delivers text and binary messages frame by frame, as
+/// `Fragment::Data` items, instead of reassembling them first.
+///
+/// This keeps latency low when streaming media over long fragmented messages.
+/// Fragments are still checked to arrive in order and text to be valid UTF-8.
+/// Control messages are decoded whole as `Fragment::Control`, answering pings
+/// is up to the user. Since nothing is buffered, the maximum message size of
+/// the underlying codec doesn't apply, only its maximum data frame size.
+///
+/// Encoding works just like with `MessageCodec`.
+pub struct FragmentCodec<M>
+where
+	M: MessageTrait,
+{
+	codec: MessageCodec<M>,
+	state: FragmentState,
+}
+
+impl<M> FragmentCodec<M>
+where
+	M: MessageTrait,
+{
+	/// Decodes fragments using the settings and limits of `codec`.
+	pub fn new(codec: MessageCodec<M>) -> Self {
+		FragmentCodec {
+			codec,
+			state: FragmentState::new(),
+		}
+	}
+
+	/// Gets a reference to the underlying message codec.
+	pub fn get_ref(&self) -> &MessageCodec<M> {
+		&self.codec
+	}
+
+	/// Gets a mutable reference to the underlying message codec.
+	pub fn get_mut(&mut self) -> &mut MessageCodec<M> {
+		&mut self.codec
+	}
+
+	/// Gives back the underlying message codec.
+	pub fn into_inner(self) -> MessageCodec<M> {
+		self.codec
+	}
+}
+
+impl<M> Decoder for FragmentCodec<M>
+where
+	M: MessageTrait,
+{
+	type Item = Fragment;
+	type Error = WebSocketError;
+
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		match self.codec.dataframe_codec.decode(src)? {
+			Some(frame) => self.state.push(frame).map(Some),
+			None => Ok(None),
+		}
+	}
+}
+
+impl<M> Encoder for FragmentCodec<M>
+where
+	M: MessageTrait,
+{
+	type Item = M;
+	type Error = WebSocketError;
+
+	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		self.codec.encode(item, dst)
 	}
 }
 
@@ -349,6 +676,7 @@ mod tests {
 	use super::*;
 	use crate::message::CloseData;
 	use crate::message::Message;
+	use crate::message::{MessageFragment, Type};
 	use crate::stream::ReadWritePair;
 	use futures::{Future, Sink, Stream};
 	use std::io::Cursor;
@@ -383,6 +711,30 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn messages_survive_roundtrip() {
+		let mut messages = vec![
+			OwnedMessage::Text(String::new()),
+			OwnedMessage::Text("nilbog".to_string()),
+			OwnedMessage::Ping(Vec::new()),
+			OwnedMessage::Ping(vec![9; 125]),
+			OwnedMessage::Pong("boop".to_string().into_bytes()),
+			OwnedMessage::Close(None),
+			OwnedMessage::Close(Some(CloseData::new(1000, String::new()))),
+			OwnedMessage::Close(Some(CloseData::new(4000, "because".to_string()))),
+		];
+		// around the lengths where the frame header grows
+		for &len in &[0, 125, 126, 127, 65535, 65536, 65556] {
+			messages.push(OwnedMessage::Binary(vec![42; len]));
+		}
+
+		for message in messages {
+			for &masked in &[false, true] {
+				assert_eq!(roundtrip(&message, masked).unwrap(), message);
+			}
+		}
+	}
+
 	#[test]
 	fn cow_message_predicts_size() {
 		let messages = vec![
@@ -473,4 +825,322 @@ mod tests {
 
 		runtime.block_on(f).unwrap();
 	}
+
+	#[test]
+	fn message_codec_counts_bytes() {
+		let message = Message::binary(vec![42; 300]);
+		let mut client = MessageCodec::default(Context::Client);
+		let mut server = MessageCodec::default(Context::Server);
+		let mut buf = BytesMut::new();
+
+		client.encode(message.clone().into(), &mut buf).unwrap();
+		assert_eq!(client.bytes_sent(), message.message_size(true) as u64);
+
+		// half a frame is not counted yet
+		let mut partial = buf.split_to(100);
+		assert_eq!(server.decode(&mut partial).unwrap(), None);
+		assert_eq!(server.bytes_received(), 0);
+
+		partial.unsplit(buf);
+		assert!(server.decode(&mut partial).unwrap().is_some());
+		assert_eq!(server.bytes_received(), client.bytes_sent());
+	}
+
+	#[test]
+	fn message_codec_reports_violations() {
+		let cases: Vec<(&[u8], ProtocolViolation)> = vec![
+			(&[0x83, 0x00], ProtocolViolation::InvalidOpcode),
+			(&[0xC1, 0x00], ProtocolViolation::ReservedBits),
+			(&[0xC9, 0x00], ProtocolViolation::ReservedBits),
+			(&[0x41, 0x00], ProtocolViolation::ReservedBits),
+			(
+				&[0x82, 0x7E, 0x00, 0x05, 1, 2, 3, 4, 5],
+				ProtocolViolation::NonMinimalLength,
+			),
+			(&[0x81, 0x02, 0xC3, 0x28], ProtocolViolation::InvalidUtf8),
+			(
+				&[0x88, 0x02, 0x03, 0xED],
+				ProtocolViolation::InvalidCloseCode,
+			),
+			(&[0x88, 0x01, 0x03], ProtocolViolation::InvalidCloseCode),
+			(
+				&[0x89, 0x7E, 0x00, 0x7E],
+				ProtocolViolation::ControlFrameTooBig,
+			),
+			(&[0x09, 0x00], ProtocolViolation::FragmentedControl),
+			(&[0x80, 0x00], ProtocolViolation::UnexpectedContinuation),
+			(
+				&[0x01, 0x00, 0x81, 0x00],
+				ProtocolViolation::UnfinishedMessage,
+			),
+		];
+
+		for (bytes, violation) in cases {
+			let mut codec = MessageCodec::default(Context::Client);
+			let mut buf = BytesMut::from(bytes);
+			let err = codec.decode(&mut buf).unwrap_err();
+			assert_eq!(err.protocol_violation(), Some(violation), "{:?}", bytes);
+		}
+
+		let mut codec = MessageCodec::<OwnedMessage>::new_with_limits(Context::Client, 4, 8);
+		let mut buf = BytesMut::from(&[0x82, 0x05, 1, 2, 3, 4, 5][..]);
+		let err = codec.decode(&mut buf).unwrap_err();
+		assert_eq!(
+			err.protocol_violation(),
+			Some(ProtocolViolation::MessageTooBig)
+		);
+		assert_eq!(ProtocolViolation::MessageTooBig.close_code(), 1009);
+		assert_eq!(ProtocolViolation::InvalidUtf8.close_code(), 1007);
+		assert_eq!(ProtocolViolation::FragmentedControl.close_code(), 1002);
+	}
+
+	#[test]
+	fn dataframe_codec_rejects_wrong_masking() {
+		// only the headers, the violation is detected before the payload arrives
+		let masked = [0x82, 0xFE, 0x01, 0x00, 1, 2, 3, 4];
+		let unmasked = [0x82, 0x7E, 0x01, 0x00];
+
+		let mut client = DataFrameCodec::default(Context::Client);
+		let err = client.decode(&mut BytesMut::from(&masked[..])).unwrap_err();
+		assert_eq!(
+			err.protocol_violation(),
+			Some(ProtocolViolation::MaskingViolation)
+		);
+		assert_eq!(
+			client.decode(&mut BytesMut::from(&unmasked[..])).unwrap(),
+			None
+		);
+
+		let mut server = DataFrameCodec::default(Context::Server);
+		let err = server
+			.decode(&mut BytesMut::from(&unmasked[..]))
+			.unwrap_err();
+		assert_eq!(
+			err.protocol_violation(),
+			Some(ProtocolViolation::MaskingViolation)
+		);
+		assert!(err.to_string().contains("Context"), "{}", err);
+		assert_eq!(
+			server.decode(&mut BytesMut::from(&masked[..])).unwrap(),
+			None
+		);
+	}
+
+	#[test]
+	fn batch_codec_decodes_bursts() {
+		let mut input = Vec::new();
+		for i in 0..10u8 {
+			Message::binary(vec![i; 10])
+				.serialize(&mut input, true)
+				.unwrap();
+		}
+		// half of another message
+		let mut last = Vec::new();
+		Message::text("tail").serialize(&mut last, true).unwrap();
+		input.extend_from_slice(&last[..3]);
+
+		let mut codec = BatchMessageCodec::new(MessageCodec::default(Context::Server));
+		let mut buf = BytesMut::from(input);
+		let batch = codec.decode(&mut buf).unwrap().unwrap();
+		assert_eq!(batch.len(), 10);
+		assert_eq!(batch[9], OwnedMessage::Binary(vec![9; 10]));
+		assert_eq!(codec.decode(&mut buf).unwrap(), None);
+
+		buf.extend_from_slice(&last[3..]);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(vec![OwnedMessage::Text("tail".to_string())])
+		);
+
+		// limits still apply to every message of the batch
+		let mut codec = BatchMessageCodec::new(MessageCodec::<OwnedMessage>::new_with_limits(
+			Context::Server,
+			4,
+			8,
+		));
+		let mut input = Vec::new();
+		Message::binary(vec![1])
+			.serialize(&mut input, true)
+			.unwrap();
+		Message::binary(vec![1; 5])
+			.serialize(&mut input, true)
+			.unwrap();
+		// the messages before the one over the limit come out first
+		let mut buf = BytesMut::from(input);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(vec![OwnedMessage::Binary(vec![1])])
+		);
+		assert!(codec.decode(&mut buf).is_err());
+	}
+
+	#[test]
+	fn fragment_codec_delivers_each_frame() {
+		let mut server = MessageCodec::default(Context::Server);
+		server.set_auto_fragment(Some(4));
+		let mut buf = BytesMut::new();
+		server
+			.encode(OwnedMessage::Binary(vec![1; 10]), &mut buf)
+			.unwrap();
+		server.encode(OwnedMessage::Ping(vec![]), &mut buf).unwrap();
+
+		let mut codec = FragmentCodec::new(MessageCodec::default(Context::Client));
+		let mut received = Vec::new();
+		while let Some(fragment) = codec.decode(&mut buf).unwrap() {
+			received.push(fragment);
+		}
+		let data = |len, is_final| {
+			Fragment::Data(MessageFragment {
+				opcode: Type::Binary,
+				data: vec![1; len],
+				is_final,
+			})
+		};
+		assert_eq!(
+			received,
+			vec![
+				data(4, false),
+				data(4, false),
+				data(2, true),
+				Fragment::Control(OwnedMessage::Ping(vec![])),
+			]
+		);
+	}
+
+	#[test]
+	fn close_reason_must_be_utf8() {
+		// status 1000 followed by an invalid UTF-8 reason
+		let mut buf = BytesMut::from(&[0x88, 0x04, 0x03, 0xE8, 0xC3, 0x28][..]);
+		let err = MessageCodec::default(Context::Client)
+			.decode(&mut buf)
+			.unwrap_err();
+		let violation = err.protocol_violation().unwrap();
+		assert_eq!(violation, ProtocolViolation::InvalidUtf8);
+		assert_eq!(violation.close_code(), 1007);
+	}
+
+	#[test]
+	fn empty_data_messages() {
+		let mut codec = MessageCodec::default(Context::Client);
+		// empty text, empty binary, then empty text fragmented into three empty frames
+		let mut buf =
+			BytesMut::from(&[0x81, 0x00, 0x82, 0x00, 0x01, 0x00, 0x00, 0x00, 0x80, 0x00][..]);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Text(String::new()))
+		);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Binary(vec![]))
+		);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Text(String::new()))
+		);
+		assert!(buf.is_empty());
+
+		// an empty binary message split into a first frame and a final continuation
+		let mut buf = BytesMut::from(&[0x02, 0x00, 0x80, 0x00][..]);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Binary(vec![]))
+		);
+	}
+
+	#[test]
+	fn strict_close_rejects_trailing_bytes() {
+		let input = [0x88, 0x02, 0x03, 0xe8, 0x81, 0x01, b'x'];
+		let mut codec = MessageCodec::default(Context::Client);
+		let mut buf = BytesMut::from(&input[..]);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Close(Some(CloseData::new(
+				1000,
+				String::new()
+			))))
+		);
+
+		codec.set_strict_close(true);
+		let mut buf = BytesMut::from(&input[..]);
+		match codec.decode(&mut buf) {
+			Err(WebSocketError::ProtocolViolation(ProtocolViolation::DataAfterClose)) => (),
+			other => panic!("expected a violation, got {:?}", other),
+		}
+
+		// a close frame on its own is fine
+		let mut buf = BytesMut::from(&input[..4]);
+		assert!(codec.decode(&mut buf).unwrap().is_some());
+	}
+
+	#[test]
+	fn max_total_frames_counts_every_frame() {
+		let mut codec = MessageCodec::default(Context::Client);
+		codec.set_max_total_frames(Some(3));
+		// a ping in the middle of a fragmented message counts as well
+		let mut buf = BytesMut::from(&b"\x01\x01a\x89\x00\x80\x01b\x81\x01c"[..]);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Ping(vec![]))
+		);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Text("ab".to_string()))
+		);
+		assert_eq!(codec.frames_received(), 3);
+		match codec.decode(&mut buf) {
+			Err(WebSocketError::ProtocolViolation(ProtocolViolation::TooManyFrames)) => (),
+			other => panic!("expected a violation, got {:?}", other),
+		}
+		assert_eq!(ProtocolViolation::TooManyFrames.close_code(), 1008);
+
+		codec.set_max_total_frames(None);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Text("c".to_string()))
+		);
+	}
+
+	#[test]
+	fn max_empty_frames_stops_empty_continuations() {
+		let mut codec = MessageCodec::default(Context::Client);
+		codec.set_max_empty_frames(Some(2));
+		// a non-empty frame starts the count over
+		let mut buf = BytesMut::from(&b"\x01\x00\x00\x00\x00\x01a\x00\x00\x80\x00"[..]);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Text("a".to_string()))
+		);
+
+		let mut buf = BytesMut::from(&b"\x01\x00"[..]);
+		for _ in 0..100 {
+			buf.extend_from_slice(b"\x00\x00");
+		}
+		match codec.decode(&mut buf) {
+			Err(WebSocketError::ProtocolViolation(ProtocolViolation::TooManyEmptyFrames)) => (),
+			other => panic!("expected a violation, got {:?}", other),
+		}
+		assert_eq!(ProtocolViolation::TooManyEmptyFrames.close_code(), 1009);
+		assert_eq!(buf.len(), 98 * 2);
+	}
+
+	#[test]
+	fn message_codec_reuses_frame_buffer() {
+		let mut codec = MessageCodec::default(Context::Client);
+		// "hello" in two fragments, then "hi" in one frame
+		let mut buf = BytesMut::from(&b"\x01\x03hel\x80\x02lo\x81\x02hi"[..]);
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Text("hello".to_string()))
+		);
+		assert!(codec.buffer.is_empty());
+		let capacity = codec.buffer.capacity();
+		assert!(capacity >= 2);
+
+		assert_eq!(
+			codec.decode(&mut buf).unwrap(),
+			Some(OwnedMessage::Text("hi".to_string()))
+		);
+		assert!(codec.buffer.is_empty());
+		assert_eq!(codec.buffer.capacity(), capacity);
+	}
 }
@@ -1,9 +1,16 @@
 //! Module containing the default implementation of data frames.
+use crate::result::ProtocolViolation;
+#[cfg(feature = "std")]
 use crate::result::{WebSocketError, WebSocketResult};
+#[cfg(feature = "std")]
 use crate::ws::dataframe::DataFrame as DataFrameable;
 use crate::ws::util::header as dfh;
 use crate::ws::util::header::DataFrameHeader;
 use crate::ws::util::mask;
+use crate::ws::util::write::{EncodeError, FrameWrite};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
 use std::io::{self, Read, Write};
 
 /// Represents a WebSocket data frame.
@@ -37,6 +44,109 @@ impl DataFrame {
 		}
 	}
 
+	/// How many bytes the header of a data frame with a `payload_len` byte
+	/// payload takes up on the wire, see `ws::util::header::header_len`.
+	///
+	/// ```rust
+	/// # use websocket_base::dataframe::DataFrame;
+	/// assert_eq!(DataFrame::header_len(125, false), 2);
+	/// assert_eq!(DataFrame::header_len(126, true), 8);
+	/// ```
+	pub fn header_len(payload_len: usize, masked: bool) -> usize {
+		dfh::header_len(payload_len, masked)
+	}
+
+	/// Writes the data frame to a `FrameWrite`, masked with `masking_key` or
+	/// unmasked with `None`. This is the `write_to_with_mask` of
+	/// `ws::dataframe::DataFrame` for builds without `std`.
+	///
+	/// ```rust
+	/// # use websocket_base::dataframe::{DataFrame, Opcode};
+	/// let mut bytes = Vec::new();
+	/// DataFrame::new(true, Opcode::Text, b"hi".to_vec()).encode(&mut bytes, None).unwrap();
+	/// assert_eq!(bytes, [0x81, 0x02, b'h', b'i']);
+	/// ```
+	pub fn encode<W>(
+		&self,
+		writer: &mut W,
+		masking_key: Option<[u8; 4]>,
+	) -> Result<(), EncodeError<W::Error>>
+	where
+		W: FrameWrite + ?Sized,
+	{
+		let header = DataFrameHeader {
+			flags: dfh::DataFrameFlags::new(self.finished, &self.reserved),
+			opcode: self.opcode as u8,
+			mask: masking_key,
+			len: self.data.len() as u64,
+		};
+		dfh::encode_header(writer, header)?;
+
+		match masking_key {
+			Some(key) => {
+				// Mask through a small buffer instead of copying the whole payload,
+				// its length being a multiple of 4 keeps the key aligned across chunks
+				let mut buf = [0u8; 256];
+				for chunk in self.data.chunks(buf.len()) {
+					for (i, &byte) in chunk.iter().enumerate() {
+						buf[i] = byte ^ key[i % 4];
+					}
+					writer
+						.write_bytes(&buf[..chunk.len()])
+						.map_err(EncodeError::Write)?;
+				}
+				Ok(())
+			}
+			None => writer.write_bytes(&self.data).map_err(EncodeError::Write),
+		}
+	}
+
+	/// Decodes a data frame from the start of `bytes`, which doesn't need `std`.
+	///
+	/// Returns the frame along with how many bytes it took up, or `None` if
+	/// `bytes` doesn't hold all of it yet. `should_be_masked` is true for frames
+	/// sent by a client, see `ws::util::header::decode_header`.
+	///
+	/// ```rust
+	/// # use websocket_base::dataframe::{DataFrame, Opcode};
+	/// let bytes = [0x81, 0x02, b'h', b'i', 0x89];
+	/// let (frame, len) = DataFrame::decode(&bytes, false).unwrap().unwrap();
+	/// assert_eq!(frame, DataFrame::new(true, Opcode::Text, b"hi".to_vec()));
+	/// assert_eq!(DataFrame::decode(&bytes[len..], false), Ok(None));
+	/// ```
+	pub fn decode(
+		bytes: &[u8],
+		should_be_masked: bool,
+	) -> Result<Option<(Self, usize)>, ProtocolViolation> {
+		let (header, header_len) = match dfh::decode_header(bytes, Some(should_be_masked))? {
+			Some(decoded) => decoded,
+			None => return Ok(None),
+		};
+		if ((bytes.len() - header_len) as u64) < header.len {
+			return Ok(None);
+		}
+		let end = header_len + header.len as usize;
+		let body = &bytes[header_len..end];
+
+		let frame = DataFrame {
+			finished: header.flags.contains(dfh::DataFrameFlags::FIN),
+			reserved: [
+				header.flags.contains(dfh::DataFrameFlags::RSV1),
+				header.flags.contains(dfh::DataFrameFlags::RSV2),
+				header.flags.contains(dfh::DataFrameFlags::RSV3),
+			],
+			opcode: Opcode::new(header.opcode).expect("Invalid header opcode!"),
+			data: match header.mask {
+				Some(mask) => mask::mask_data(mask, body),
+				None => body.to_vec(),
+			},
+		};
+		Ok(Some((frame, end)))
+	}
+}
+
+#[cfg(feature = "std")]
+impl DataFrame {
 	/// Take the body and header of a dataframe and combine it into a single
 	/// Dataframe struct. A websocket message can be made up of many individual
 	/// dataframes, use the methods from the Message or OwnedMessage structs to
@@ -60,14 +170,18 @@ impl DataFrame {
 			Some(mask) => {
 				if !should_be_masked {
 					return Err(WebSocketError::DataFrameError(
-						"Expected unmasked data frame",
+						"Received a masked data frame, but this endpoint is a client \
+						 (expected unmasked server frames), did you swap the Context?",
 					));
 				}
 				mask::mask_data(mask, &body)
 			}
 			None => {
 				if should_be_masked {
-					return Err(WebSocketError::DataFrameError("Expected masked data frame"));
+					return Err(WebSocketError::DataFrameError(
+						"Received an unmasked data frame, but this endpoint is a server \
+						 (expected masked client frames), did you swap the Context?",
+					));
 				}
 				body
 			}
@@ -86,7 +200,7 @@ impl DataFrame {
 	where
 		R: Read,
 	{
-		let header = dfh::read_header(reader)?;
+		let header = dfh::read_header_with_mask(reader, should_be_masked)?;
 
 		let mut data: Vec<u8> = Vec::with_capacity(header.len as usize);
 		let read = reader.take(header.len).read_to_end(&mut data)?;
@@ -97,15 +211,49 @@ impl DataFrame {
 		DataFrame::read_dataframe_body(header, data, should_be_masked)
 	}
 
+	/// Decodes all the data frames in `bytes`, e.g. to analyze captured traffic
+	/// without a stream.
+	///
+	/// `should_be_masked` is true for frames sent by a client. A frame cut
+	/// short by the end of `bytes` is reported as an error, and nothing is
+	/// allocated for a payload before it is known to be there.
+	///
+	/// ```rust
+	/// # use websocket_base::dataframe::{DataFrame, Opcode};
+	/// let frames = DataFrame::decode_all(&[0x01, 0x01, b'h', 0x80, 0x01, b'i'], false).unwrap();
+	/// assert_eq!(frames[0], DataFrame::new(false, Opcode::Text, b"h".to_vec()));
+	/// assert_eq!(frames[1], DataFrame::new(true, Opcode::Continuation, b"i".to_vec()));
+	///
+	/// assert!(DataFrame::decode_all(&[0x81, 0x02, b'h'], false).is_err());
+	/// ```
+	pub fn decode_all(bytes: &[u8], should_be_masked: bool) -> WebSocketResult<Vec<Self>> {
+		let mut rest = bytes;
+		let mut frames = Vec::new();
+		while !rest.is_empty() {
+			match DataFrame::decode(rest, should_be_masked)? {
+				Some((frame, len)) => {
+					frames.push(frame);
+					rest = &rest[len..];
+				}
+				None => {
+					return Err(WebSocketError::DataFrameError(
+						"Truncated data frame at the end of the buffer",
+					));
+				}
+			}
+		}
+		Ok(frames)
+	}
+
 	/// Reads a DataFrame from a Reader, or error out if header declares exceeding limit you specify
 	pub fn read_dataframe_with_limit<R>(reader: &mut R, should_be_masked: bool, limit: usize) -> WebSocketResult<Self>
 	where
 		R: Read,
 	{
-		let header = dfh::read_header(reader)?;
+		let header = dfh::read_header_with_mask(reader, should_be_masked)?;
 
 		if header.len > limit as u64 {
-			return Err(io::Error::new(io::ErrorKind::InvalidData, "exceeded DataFrame length limit").into());
+			return Err(ProtocolViolation::MessageTooBig.into());
 		}
 		let mut data: Vec<u8> = Vec::with_capacity(header.len as usize);
 		let read = reader.take(header.len).read_to_end(&mut data)?;
@@ -117,6 +265,7 @@ impl DataFrame {
 	}
 }
 
+#[cfg(feature = "std")]
 impl DataFrameable for DataFrame {
 	#[inline(always)]
 	fn is_last(&self) -> bool {
@@ -8,14 +8,25 @@
 //!
 //! Note that there is no normal example of usage so far. See usual `websocket` crate or
 //! alternative like `tungstenite`.
+//!
+//! Without the default `std` feature the crate is `no_std` and only needs `alloc`.
+//! What is left then is the `DataFrame` type, the header and masking utilities and
+//! `result::ProtocolViolation`. Frames serialize into a `ws::util::write::FrameWrite`
+//! instead of a `std::io::Write` and are decoded from byte slices. The `message`
+//! module, with `Message` and `OwnedMessage`, and the `ws::Message` and
+//! `ws::dataframe::DataFrame` traits still need `std`.
 
+#![cfg_attr(not(feature = "std"), no_std)]
 #![allow(clippy::match_ref_pats, clippy::needless_doctest_main)]
+#[cfg(not(feature = "std"))]
+extern crate alloc;
 extern crate byteorder;
 
 #[cfg(feature = "async")]
 extern crate bytes;
 #[cfg(feature = "async")]
 extern crate futures;
+#[cfg(feature = "std")]
 extern crate rand;
 #[macro_use]
 extern crate bitflags;
@@ -35,11 +46,16 @@ extern crate tokio_tls;
 #[cfg(feature = "async")]
 pub mod codec;
 pub mod dataframe;
+#[cfg(feature = "std")]
 pub mod header;
+#[cfg(feature = "std")]
 pub mod message;
 pub mod result;
+#[cfg(feature = "std")]
 pub mod stream;
 pub mod ws;
 
+#[cfg(feature = "std")]
 pub use crate::message::Message;
+#[cfg(feature = "std")]
 pub use crate::message::OwnedMessage;
@@ -1,11 +1,12 @@
 //! Module containing the default implementation for messages.
-use crate::dataframe::Opcode;
-use crate::result::{WebSocketError, WebSocketResult};
+use crate::dataframe::{DataFrame, Opcode};
+use crate::result::{ProtocolViolation, WebSocketError, WebSocketResult};
 use crate::ws;
 use crate::ws::dataframe::DataFrame as DataFrameTrait;
 use crate::ws::util::bytes_to_string;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::io;
 use std::io::Write;
 use std::str::from_utf8;
@@ -47,6 +48,33 @@ pub struct Message<'a> {
 }
 
 impl<'a> Message<'a> {
+	/// Form a message from the data frames in `frames`, leaving it empty
+	/// (but with its capacity) so that it can be reused for the next message.
+	pub(crate) fn drain_dataframes<D>(frames: &mut Vec<D>) -> WebSocketResult<Self>
+	where
+		D: DataFrameTrait,
+	{
+		let payload_size = frames.iter().map(ws::dataframe::DataFrame::size).sum();
+
+		let mut data = Vec::with_capacity(payload_size);
+
+		let msg = match reassemble_dataframes(frames, &mut data)? {
+			Type::Text => Message {
+				opcode: Type::Text,
+				cd_status_code: None,
+				payload: Cow::Owned(data),
+			},
+			Type::Binary => Message::binary(data),
+			Type::Close => match parse_close_payload(&data)? {
+				Some(data) => Message::close_because(data.status_code, data.reason),
+				None => Message::close(),
+			},
+			Type::Ping => Message::ping(data),
+			Type::Pong => Message::pong(data),
+		};
+		Ok(msg)
+	}
+
 	fn new(code: Type, status: Option<u16>, payload: Cow<'a, [u8]>) -> Self {
 		Message {
 			opcode: code,
@@ -185,63 +213,94 @@ impl<'a> ws::Message for Message<'a> {
 		self.frame_size(masked)
 	}
 
+	fn close_code(&self) -> Option<u16> {
+		match self.opcode {
+			Type::Close => Some(self.cd_status_code.unwrap_or(1005)),
+			_ => None,
+		}
+	}
+
+	fn message_type(&self) -> Option<Type> {
+		Some(self.opcode)
+	}
+
 	/// Attempt to form a message from a series of data frames
-	fn from_dataframes<D>(frames: Vec<D>) -> WebSocketResult<Self>
+	fn from_dataframes<D>(mut frames: Vec<D>) -> WebSocketResult<Self>
 	where
 		D: DataFrameTrait,
 	{
-		let opcode = frames
-			.first()
-			.ok_or(WebSocketError::ProtocolError("No dataframes provided"))
-			.map(ws::dataframe::DataFrame::opcode)?;
-		let opcode = Opcode::new(opcode);
-
-		let payload_size = frames.iter().map(ws::dataframe::DataFrame::size).sum();
-
-		let mut data = Vec::with_capacity(payload_size);
+		Message::drain_dataframes(&mut frames)
+	}
+}
 
-		for (i, dataframe) in frames.into_iter().enumerate() {
-			if i > 0 && dataframe.opcode() != Opcode::Continuation as u8 {
-				return Err(WebSocketError::ProtocolError(
-					"Unexpected non-continuation data frame",
-				));
-			}
-			if *dataframe.reserved() != [false; 3] {
-				return Err(WebSocketError::ProtocolError(
-					"Unsupported reserved bits received",
-				));
-			}
-			data.append(&mut dataframe.take_payload());
+/// Puts the payload of the message made up of the data frames in `frames`
+/// into `buf`, after clearing it, and returns the type of the message.
+///
+/// The message is checked like it is by `ws::Message::from_dataframes`, but
+/// `buf` and its capacity can be reused for the next message. A close
+/// message's payload is left as it was sent, status code first. `frames` is
+/// left empty.
+pub fn reassemble_dataframes<D>(frames: &mut Vec<D>, buf: &mut Vec<u8>) -> WebSocketResult<Type>
+where
+	D: DataFrameTrait,
+{
+	let opcode = frames
+		.first()
+		.ok_or(WebSocketError::ProtocolError("No dataframes provided"))
+		.map(ws::dataframe::DataFrame::opcode)?;
+	let kind = match Opcode::new(opcode) {
+		Some(Opcode::Text) => Type::Text,
+		Some(Opcode::Binary) => Type::Binary,
+		Some(Opcode::Close) => Type::Close,
+		Some(Opcode::Ping) => Type::Ping,
+		Some(Opcode::Pong) => Type::Pong,
+		_ => return Err(ProtocolViolation::InvalidOpcode.into()),
+	};
+
+	buf.clear();
+	for (i, dataframe) in frames.drain(..).enumerate() {
+		if i > 0 && dataframe.opcode() != Opcode::Continuation as u8 {
+			return Err(ProtocolViolation::UnfinishedMessage.into());
 		}
+		if *dataframe.reserved() != [false; 3] {
+			return Err(ProtocolViolation::ReservedBits.into());
+		}
+		buf.append(&mut dataframe.take_payload());
+	}
 
-		if opcode == Some(Opcode::Text) {
-			if let Err(e) = from_utf8(data.as_slice()) {
-				return Err(e.into());
-			}
+	match kind {
+		Type::Text if from_utf8(buf.as_slice()).is_err() => {
+			return Err(ProtocolViolation::InvalidUtf8.into());
+		}
+		Type::Close => {
+			parse_close_payload(buf)?;
 		}
+		_ => (),
+	}
+	Ok(kind)
+}
 
-		let msg = match opcode {
-			Some(Opcode::Text) => Message {
-				opcode: Type::Text,
-				cd_status_code: None,
-				payload: Cow::Owned(data),
-			},
-			Some(Opcode::Binary) => Message::binary(data),
-			Some(Opcode::Close) => {
-				if !data.is_empty() {
-					let status_code = (&data[..]).read_u16::<BigEndian>()?;
-					let reason = bytes_to_string(&data[2..])?;
-					Message::close_because(status_code, reason)
-				} else {
-					Message::close()
-				}
-			}
-			Some(Opcode::Ping) => Message::ping(data),
-			Some(Opcode::Pong) => Message::pong(data),
-			_ => return Err(WebSocketError::ProtocolError("Unsupported opcode received")),
-		};
-		Ok(msg)
+/// Read the status code and reason of a received close frame's payload.
+fn parse_close_payload(data: &[u8]) -> WebSocketResult<Option<CloseData>> {
+	if data.is_empty() {
+		return Ok(None);
+	}
+	if data.len() < 2 {
+		return Err(ProtocolViolation::InvalidCloseCode.into());
 	}
+	let status_code = (&data[..]).read_u16::<BigEndian>()?;
+	if !is_valid_close_code(status_code) {
+		return Err(ProtocolViolation::InvalidCloseCode.into());
+	}
+	let reason = bytes_to_string(&data[2..])
+		.map_err(|_| WebSocketError::from(ProtocolViolation::InvalidUtf8))?;
+	Ok(Some(CloseData::new(status_code, reason)))
+}
+
+/// Whether `code` may be received in a close frame, see the
+/// [RFC](https://tools.ietf.org/html/rfc6455#section-7.4.1).
+fn is_valid_close_code(code: u16) -> bool {
+	matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
 }
 
 /// Represents an owned WebSocket message.
@@ -342,6 +401,49 @@ impl OwnedMessage {
 			_ => false,
 		}
 	}
+
+	/// Gives the message to send back in reply to a received `Close` message,
+	/// completing the closing handshake: a close with the same status code
+	/// (and no reason), or `None` if this is not a close message.
+	///
+	///```rust
+	///# use websocket_base::OwnedMessage;
+	///# use websocket_base::message::CloseData;
+	///let received = OwnedMessage::Close(Some(CloseData::new(1001, "bye".to_string())));
+	///assert_eq!(
+	///    received.close_echo(),
+	///    Some(OwnedMessage::Close(Some(CloseData::new(1001, String::new()))))
+	///);
+	///assert_eq!(OwnedMessage::Close(None).close_echo(), Some(OwnedMessage::Close(None)));
+	///assert_eq!(OwnedMessage::Ping(vec![]).close_echo(), None);
+	///```
+	pub fn close_echo(&self) -> Option<OwnedMessage> {
+		match *self {
+			OwnedMessage::Close(ref data) => Some(OwnedMessage::Close(
+				data.as_ref()
+					.map(|d| CloseData::new(d.status_code, String::new())),
+			)),
+			_ => None,
+		}
+	}
+
+	/// Append the serialized message to `buf`, reserving the space it needs
+	/// up front.
+	///
+	/// Nothing already in `buf` is touched, so encoding loops can keep reusing
+	/// one buffer and only `clear` it once its contents have been sent.
+	///
+	///```rust
+	///# use websocket_base::OwnedMessage;
+	///let mut buf = Vec::new();
+	///OwnedMessage::Text("hi".to_string()).serialize_into(&mut buf, false).unwrap();
+	///OwnedMessage::Ping(vec![]).serialize_into(&mut buf, false).unwrap();
+	///assert_eq!(buf, vec![0x81, 0x02, b'h', b'i', 0x89, 0x00]);
+	///```
+	pub fn serialize_into(&self, buf: &mut Vec<u8>, masked: bool) -> WebSocketResult<()> {
+		buf.reserve(ws::Message::message_size(self, masked));
+		ws::Message::serialize(self, buf, masked)
+	}
 }
 
 impl ws::Message for OwnedMessage {
@@ -355,6 +457,23 @@ impl ws::Message for OwnedMessage {
 		self.frame_size(masked)
 	}
 
+	fn close_code(&self) -> Option<u16> {
+		match *self {
+			OwnedMessage::Close(ref data) => Some(data.as_ref().map_or(1005, |d| d.status_code)),
+			_ => None,
+		}
+	}
+
+	fn message_type(&self) -> Option<Type> {
+		Some(match *self {
+			OwnedMessage::Text(_) => Type::Text,
+			OwnedMessage::Binary(_) => Type::Binary,
+			OwnedMessage::Close(_) => Type::Close,
+			OwnedMessage::Ping(_) => Type::Ping,
+			OwnedMessage::Pong(_) => Type::Pong,
+		})
+	}
+
 	/// Attempt to form a message from a series of data frames
 	fn from_dataframes<D>(frames: Vec<D>) -> WebSocketResult<Self>
 	where
@@ -448,6 +567,18 @@ impl From<Vec<u8>> for OwnedMessage {
 	}
 }
 
+impl<'a> From<&'a str> for OwnedMessage {
+	fn from(text: &'a str) -> Self {
+		OwnedMessage::Text(text.to_string())
+	}
+}
+
+impl<'a> From<&'a [u8]> for OwnedMessage {
+	fn from(buf: &'a [u8]) -> Self {
+		OwnedMessage::Binary(buf.to_vec())
+	}
+}
+
 impl<'m> From<Message<'m>> for OwnedMessage {
 	fn from(message: Message<'m>) -> Self {
 		match message.opcode {
@@ -469,6 +600,60 @@ impl<'m> From<Message<'m>> for OwnedMessage {
 	}
 }
 
+/// Turn a single data frame into a message without going through a `Vec`
+/// of frames, e.g. for frames read with `recv_dataframe`.
+///
+/// The frame has to hold a whole message: data frames must have the FIN
+/// bit set, and continuation frames are rejected. Control frames are
+/// checked for their size and fragmentation like when reading messages.
+///
+///```rust
+///# use std::convert::TryFrom;
+///# use websocket_base::dataframe::{DataFrame, Opcode};
+///# use websocket_base::OwnedMessage;
+///let frame = DataFrame::new(true, Opcode::Text, b"hi".to_vec());
+///assert_eq!(OwnedMessage::try_from(frame).unwrap(), OwnedMessage::Text("hi".to_string()));
+///
+///let frame = DataFrame::new(false, Opcode::Text, b"h".to_vec());
+///assert!(OwnedMessage::try_from(frame).is_err());
+///```
+impl TryFrom<DataFrame> for OwnedMessage {
+	type Error = WebSocketError;
+
+	fn try_from(frame: DataFrame) -> WebSocketResult<Self> {
+		if frame.reserved != [false; 3] {
+			return Err(ProtocolViolation::ReservedBits.into());
+		}
+		let is_control = frame.opcode as u8 & 0x8 != 0;
+		if is_control {
+			if !frame.finished {
+				return Err(ProtocolViolation::FragmentedControl.into());
+			}
+			if frame.data.len() > 125 {
+				return Err(ProtocolViolation::ControlFrameTooBig.into());
+			}
+		} else if frame.opcode == Opcode::Continuation {
+			return Err(ProtocolViolation::UnexpectedContinuation.into());
+		} else if !frame.finished {
+			return Err(WebSocketError::ProtocolError(
+				"Data frame is only the first fragment of a message",
+			));
+		}
+
+		Ok(match frame.opcode {
+			Opcode::Text => match String::from_utf8(frame.data) {
+				Ok(text) => OwnedMessage::Text(text),
+				Err(_) => return Err(ProtocolViolation::InvalidUtf8.into()),
+			},
+			Opcode::Binary => OwnedMessage::Binary(frame.data),
+			Opcode::Close => OwnedMessage::Close(parse_close_payload(&frame.data)?),
+			Opcode::Ping => OwnedMessage::Ping(frame.data),
+			Opcode::Pong => OwnedMessage::Pong(frame.data),
+			_ => return Err(ProtocolViolation::InvalidOpcode.into()),
+		})
+	}
+}
+
 impl<'m> From<OwnedMessage> for Message<'m> {
 	fn from(message: OwnedMessage) -> Self {
 		match message {
@@ -536,3 +721,263 @@ impl<'a> IntoCowBytes<'a> for Cow<'a, [u8]> {
 		self
 	}
 }
+
+/// A piece of a text or binary message, delivered as soon as its frame
+/// arrives instead of once the whole message has been reassembled.
+///
+/// The `data` of a text fragment is valid UTF-8 on its own: a character that
+/// is split across frames is held back until the rest of it has arrived.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct MessageFragment {
+	/// The type of the whole message, `Type::Text` or `Type::Binary`
+	pub opcode: Type,
+	/// The payload of this fragment
+	pub data: Vec<u8>,
+	/// Whether this is the last fragment of the message
+	pub is_final: bool,
+}
+
+/// A received frame as made sense of by `FragmentState::push`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Fragment {
+	/// A piece of a data message
+	Data(MessageFragment),
+	/// A complete ping, pong or close message
+	Control(OwnedMessage),
+}
+
+/// Keeps track of the message in progress when data messages are delivered
+/// fragment by fragment, rejecting frames that arrive out of order and text
+/// that isn't valid UTF-8, just like reassembling the messages would.
+#[derive(Debug, Default, Clone)]
+pub struct FragmentState {
+	current: Option<Type>,
+	partial_char: Vec<u8>,
+}
+
+impl FragmentState {
+	/// A state expecting the start of a new message.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Turn the next received frame into a fragment, or into a control
+	/// message if that is what it holds.
+	pub fn push<D>(&mut self, frame: D) -> WebSocketResult<Fragment>
+	where
+		D: DataFrameTrait,
+	{
+		if *frame.reserved() != [false; 3] {
+			return Err(ProtocolViolation::ReservedBits.into());
+		}
+		let opcode = match (Opcode::new(frame.opcode()), self.current) {
+			(Some(Opcode::Continuation), Some(current)) => current,
+			(Some(Opcode::Continuation), None) => {
+				return Err(ProtocolViolation::UnexpectedContinuation.into());
+			}
+			(Some(Opcode::Text), None) => Type::Text,
+			(Some(Opcode::Binary), None) => Type::Binary,
+			(Some(Opcode::Text), Some(_)) | (Some(Opcode::Binary), Some(_)) => {
+				return Err(ProtocolViolation::UnfinishedMessage.into());
+			}
+			(Some(Opcode::Close), _) | (Some(Opcode::Ping), _) | (Some(Opcode::Pong), _) => {
+				let message = ws::Message::from_dataframes(vec![frame])?;
+				return Ok(Fragment::Control(message));
+			}
+			_ => return Err(ProtocolViolation::InvalidOpcode.into()),
+		};
+		let is_final = frame.is_last();
+
+		let mut data = frame.take_payload();
+		if opcode == Type::Text {
+			if !self.partial_char.is_empty() {
+				let mut joined = std::mem::take(&mut self.partial_char);
+				joined.append(&mut data);
+				data = joined;
+			}
+			match from_utf8(&data) {
+				Ok(_) => (),
+				// the character might be completed by the next fragment
+				Err(e) if e.error_len().is_none() && !is_final => {
+					self.partial_char = data.split_off(e.valid_up_to());
+				}
+				Err(_) => return Err(ProtocolViolation::InvalidUtf8.into()),
+			}
+		}
+
+		self.current = if is_final { None } else { Some(opcode) };
+		Ok(Fragment::Data(MessageFragment {
+			opcode,
+			data,
+			is_final,
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::ws::Message as MessageTrait;
+
+	fn serialized<M: MessageTrait>(message: M) -> Vec<u8> {
+		let mut buf = Vec::new();
+		message.serialize(&mut buf, false).unwrap();
+		buf
+	}
+
+	#[test]
+	fn close_payload_sizes() {
+		// no status code means no payload at all
+		assert_eq!(serialized(Message::close()), [0x88, 0x00]);
+		assert_eq!(serialized(OwnedMessage::Close(None)), [0x88, 0x00]);
+		// a status code is always sent in full, even without a reason
+		assert_eq!(
+			serialized(Message::close_because(1000, "")),
+			[0x88, 0x02, 0x03, 0xe8]
+		);
+		assert_eq!(
+			serialized(OwnedMessage::Close(Some(CloseData::new(
+				1000,
+				String::new()
+			)))),
+			[0x88, 0x02, 0x03, 0xe8]
+		);
+	}
+
+	#[test]
+	fn one_byte_close_payload_is_rejected() {
+		let frame = DataFrame::new(true, Opcode::Close, vec![0x03]);
+		match OwnedMessage::from_dataframes(vec![frame]) {
+			Err(WebSocketError::ProtocolViolation(ProtocolViolation::InvalidCloseCode)) => (),
+			other => panic!("expected an invalid close code, got {:?}", other),
+		}
+
+		let frame = DataFrame::new(true, Opcode::Close, vec![]);
+		assert_eq!(
+			OwnedMessage::from_dataframes(vec![frame]).unwrap(),
+			OwnedMessage::Close(None)
+		);
+	}
+
+	#[test]
+	fn serialize_fragmented_masks_every_fragment() {
+		let mut bytes = Vec::new();
+		Message::text("fragments")
+			.serialize_fragmented(&mut bytes, true, 4)
+			.unwrap();
+		let frames = DataFrame::decode_all(&bytes, true).unwrap();
+		assert_eq!(
+			frames,
+			vec![
+				DataFrame::new(false, Opcode::Text, b"frag".to_vec()),
+				DataFrame::new(false, Opcode::Continuation, b"ment".to_vec()),
+				DataFrame::new(true, Opcode::Continuation, b"s".to_vec()),
+			]
+		);
+
+		// control messages stay in one piece
+		let mut bytes = Vec::new();
+		Message::ping(b"too long".to_vec())
+			.serialize_fragmented(&mut bytes, false, 4)
+			.unwrap();
+		let frames = DataFrame::decode_all(&bytes, false).unwrap();
+		assert_eq!(frames, vec![DataFrame::new(true, Opcode::Ping, b"too long".to_vec())]);
+	}
+
+	#[test]
+	fn fragments_are_delivered_as_they_arrive() {
+		let mut state = FragmentState::new();
+		let fragment = |opcode, data: &[u8], is_final| {
+			Fragment::Data(MessageFragment {
+				opcode,
+				data: data.to_vec(),
+				is_final,
+			})
+		};
+
+		// "né" with the "é" split across frames, and a ping in between
+		let frames = vec![
+			DataFrame::new(false, Opcode::Text, vec![b'n', 0xc3]),
+			DataFrame::new(true, Opcode::Ping, vec![1]),
+			DataFrame::new(true, Opcode::Continuation, vec![0xa9]),
+			DataFrame::new(true, Opcode::Binary, vec![7, 8]),
+		];
+		let received: Vec<_> = frames.into_iter().map(|f| state.push(f).unwrap()).collect();
+		assert_eq!(
+			received,
+			vec![
+				fragment(Type::Text, b"n", false),
+				Fragment::Control(OwnedMessage::Ping(vec![1])),
+				fragment(Type::Text, "é".as_bytes(), true),
+				fragment(Type::Binary, &[7, 8], true),
+			]
+		);
+
+		let unexpected = state.push(DataFrame::new(true, Opcode::Continuation, vec![]));
+		assert!(unexpected.is_err());
+		let truncated = state.push(DataFrame::new(true, Opcode::Text, vec![0xc3]));
+		assert!(truncated.is_err());
+	}
+
+	#[test]
+	fn single_frame_try_into_message() {
+		let message = |opcode, data: &[u8]| {
+			OwnedMessage::try_from(DataFrame::new(true, opcode, data.to_vec()))
+		};
+		assert_eq!(
+			message(Opcode::Binary, &[1, 2]).unwrap(),
+			OwnedMessage::Binary(vec![1, 2])
+		);
+		assert_eq!(
+			message(Opcode::Close, &[0x03, 0xe8, b'o', b'k']).unwrap(),
+			OwnedMessage::Close(Some(CloseData::new(1000, "ok".to_string())))
+		);
+		assert_eq!(
+			message(Opcode::Pong, &[]).unwrap(),
+			OwnedMessage::Pong(vec![])
+		);
+
+		let violation = |result: WebSocketResult<OwnedMessage>| match result {
+			Err(WebSocketError::ProtocolViolation(v)) => v,
+			other => panic!("expected a violation, got {:?}", other),
+		};
+		assert_eq!(
+			violation(message(Opcode::Text, &[0xc3])),
+			ProtocolViolation::InvalidUtf8
+		);
+		assert_eq!(
+			violation(message(Opcode::Close, &[0x03])),
+			ProtocolViolation::InvalidCloseCode
+		);
+		assert_eq!(
+			violation(message(Opcode::Ping, &[0; 126])),
+			ProtocolViolation::ControlFrameTooBig
+		);
+		assert_eq!(
+			violation(message(Opcode::Continuation, &[])),
+			ProtocolViolation::UnexpectedContinuation
+		);
+		assert_eq!(
+			violation(OwnedMessage::try_from(DataFrame::new(
+				false,
+				Opcode::Ping,
+				vec![]
+			))),
+			ProtocolViolation::FragmentedControl
+		);
+		assert!(OwnedMessage::try_from(DataFrame::new(false, Opcode::Text, vec![])).is_err());
+	}
+
+	#[test]
+	fn message_size_matches_serialized_length() {
+		for &len in &[0, 125, 126, 65535, 65536] {
+			let message = OwnedMessage::Binary(vec![0; len]);
+			for &masked in &[false, true] {
+				let mut buf = Vec::new();
+				message.serialize(&mut buf, masked).unwrap();
+				assert_eq!(message.message_size(masked), buf.len());
+				assert_eq!(DataFrame::header_len(len, masked), buf.len() - len);
+			}
+		}
+	}
+}
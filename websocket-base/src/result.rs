@@ -1,13 +1,21 @@
 //! The result type used within Rust-WebSocket
+//!
+//! Without the `std` feature only `ProtocolViolation` and `ProtocolErrorPolicy` are left.
 
+use core::fmt;
+#[cfg(feature = "std")]
 use std;
+#[cfg(feature = "std")]
 use std::convert::From;
+#[cfg(feature = "std")]
 use std::error::Error;
-use std::fmt;
+#[cfg(feature = "std")]
 use std::io;
+#[cfg(feature = "std")]
 use std::str::Utf8Error;
 
 /// The type used for WebSocket results
+#[cfg(feature = "std")]
 pub type WebSocketResult<T> = Result<T, WebSocketError>;
 
 /// This module contains convenience types to make working with Futures and
@@ -23,14 +31,20 @@ pub mod r#async {
 }
 
 /// Represents a WebSocket error
+#[cfg(feature = "std")]
 #[derive(Debug)]
 pub enum WebSocketError {
 	/// A WebSocket protocol error
 	ProtocolError(&'static str),
+	/// The remote endpoint violated the protocol in a way that calls for
+	/// a specific close code, see `ProtocolViolation`
+	ProtocolViolation(ProtocolViolation),
 	/// Invalid WebSocket data frame error
 	DataFrameError(&'static str),
 	/// No data available
 	NoDataAvailable,
+	/// The operation could not be completed without blocking
+	WouldBlock,
 	/// An input/output error
 	IoError(io::Error),
 	/// A UTF-8 error
@@ -39,13 +53,18 @@ pub enum WebSocketError {
 	Other(Box<dyn std::error::Error + Send + Sync + 'static>),
 }
 
+#[cfg(feature = "std")]
 impl fmt::Display for WebSocketError {
 	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
 		fmt.write_str("WebSocketError: ")?;
 		match self {
 			WebSocketError::ProtocolError(_) => fmt.write_str("WebSocket protocol error"),
-			WebSocketError::DataFrameError(_) => fmt.write_str("WebSocket data frame error"),
+			WebSocketError::ProtocolViolation(v) => {
+				write!(fmt, "WebSocket protocol violation: {}", v)
+			}
+			WebSocketError::DataFrameError(e) => write!(fmt, "WebSocket data frame error: {}", e),
 			WebSocketError::NoDataAvailable => fmt.write_str("No data available"),
+			WebSocketError::WouldBlock => fmt.write_str("Operation would block"),
 			WebSocketError::IoError(_) => fmt.write_str("I/O failure"),
 			WebSocketError::Utf8Error(_) => fmt.write_str("UTF-8 failure"),
 			WebSocketError::Other(x) => x.fmt(fmt),
@@ -53,6 +72,40 @@ impl fmt::Display for WebSocketError {
 	}
 }
 
+#[cfg(feature = "std")]
+impl WebSocketError {
+	/// The protocol violation behind this error, if the remote endpoint
+	/// sent something invalid.
+	pub fn protocol_violation(&self) -> Option<ProtocolViolation> {
+		match *self {
+			WebSocketError::ProtocolViolation(v) => Some(v),
+			_ => None,
+		}
+	}
+}
+
+/// Compares errors in tests, e.g. `assert_eq!(err, WebSocketError::NoDataAvailable)`.
+///
+/// I/O errors are equal when their kinds are. Errors wrapped in `Other` can't
+/// be compared and are never equal, downcast them to compare them.
+#[cfg(feature = "std")]
+impl PartialEq for WebSocketError {
+	fn eq(&self, other: &WebSocketError) -> bool {
+		use self::WebSocketError::*;
+		match (self, other) {
+			(ProtocolError(a), ProtocolError(b)) => a == b,
+			(ProtocolViolation(a), ProtocolViolation(b)) => a == b,
+			(DataFrameError(a), DataFrameError(b)) => a == b,
+			(NoDataAvailable, NoDataAvailable) => true,
+			(WouldBlock, WouldBlock) => true,
+			(IoError(a), IoError(b)) => a.kind() == b.kind(),
+			(Utf8Error(a), Utf8Error(b)) => a == b,
+			_ => false,
+		}
+	}
+}
+
+#[cfg(feature = "std")]
 impl Error for WebSocketError {
 	fn source(&self) -> Option<&(dyn Error + 'static)> {
 		match *self {
@@ -64,6 +117,14 @@ impl Error for WebSocketError {
 	}
 }
 
+#[cfg(feature = "std")]
+impl From<ProtocolViolation> for WebSocketError {
+	fn from(violation: ProtocolViolation) -> WebSocketError {
+		WebSocketError::ProtocolViolation(violation)
+	}
+}
+
+#[cfg(feature = "std")]
 impl From<io::Error> for WebSocketError {
 	fn from(err: io::Error) -> WebSocketError {
 		if err.kind() == io::ErrorKind::UnexpectedEof {
@@ -73,8 +134,126 @@ impl From<io::Error> for WebSocketError {
 	}
 }
 
+#[cfg(feature = "std")]
 impl From<Utf8Error> for WebSocketError {
 	fn from(err: Utf8Error) -> WebSocketError {
 		WebSocketError::Utf8Error(err)
 	}
 }
+
+/// The reasons for which frames or messages received from the remote endpoint
+/// can be rejected, so that e.g. a conformance test runner can check what went wrong.
+///
+/// Use `close_code` to get the status code the connection should be closed with,
+/// see the [RFC](https://tools.ietf.org/html/rfc6455#section-7.4.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProtocolViolation {
+	/// A frame with a reserved or unknown opcode
+	InvalidOpcode,
+	/// A frame with reserved bits set while no extension defines them
+	ReservedBits,
+	/// A payload length that is not encoded in the minimal number of bytes
+	NonMinimalLength,
+	/// A masked frame where an unmasked one was expected, or vice versa
+	MaskingViolation,
+	/// A text message or close reason that is not valid UTF-8
+	InvalidUtf8,
+	/// A close frame with an invalid status code, or a truncated one
+	InvalidCloseCode,
+	/// A control frame with a payload larger than 125 bytes
+	ControlFrameTooBig,
+	/// A control frame without the FIN bit set
+	FragmentedControl,
+	/// A continuation frame while no fragmented message is in progress
+	UnexpectedContinuation,
+	/// A new data frame while a fragmented message is still in progress
+	UnfinishedMessage,
+	/// A frame or message exceeding the configured size limits
+	MessageTooBig,
+	/// Data following a close frame in the same read, see `set_strict_close`
+	DataAfterClose,
+	/// More frames over the connection's lifetime than allowed by
+	/// `set_max_total_frames`
+	TooManyFrames,
+	/// More empty, non-final frames in a row than allowed by `set_max_empty_frames`
+	TooManyEmptyFrames,
+}
+
+impl ProtocolViolation {
+	/// The close code the connection should be failed with.
+	pub fn close_code(self) -> u16 {
+		match self {
+			ProtocolViolation::InvalidUtf8 => 1007,
+			ProtocolViolation::MessageTooBig => 1009,
+			ProtocolViolation::TooManyEmptyFrames => 1009,
+			ProtocolViolation::TooManyFrames => 1008,
+			_ => 1002,
+		}
+	}
+
+	fn description(self) -> &'static str {
+		match self {
+			ProtocolViolation::InvalidOpcode => "Invalid data frame opcode",
+			ProtocolViolation::ReservedBits => "Unsupported reserved bits received",
+			ProtocolViolation::NonMinimalLength => "Invalid data frame length",
+			ProtocolViolation::MaskingViolation => {
+				"Unexpected data frame masking, clients must mask their frames and servers \
+				 must not (is the Context swapped?)"
+			}
+			ProtocolViolation::InvalidUtf8 => "Invalid UTF-8 payload",
+			ProtocolViolation::InvalidCloseCode => "Invalid close status code",
+			ProtocolViolation::ControlFrameTooBig => "Control frame length too long",
+			ProtocolViolation::FragmentedControl => "Illegal fragmented control frame",
+			ProtocolViolation::UnexpectedContinuation => {
+				"Unexpected continuation data frame opcode"
+			}
+			ProtocolViolation::UnfinishedMessage => "Unexpected data frame opcode",
+			ProtocolViolation::MessageTooBig => "Exceeded maximum WebSocket message size",
+			ProtocolViolation::DataAfterClose => "Unexpected data after a close frame",
+			ProtocolViolation::TooManyFrames => "Exceeded maximum number of data frames",
+			ProtocolViolation::TooManyEmptyFrames => "Too many consecutive empty data frames",
+		}
+	}
+}
+
+impl fmt::Display for ProtocolViolation {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		fmt.write_str(self.description())
+	}
+}
+
+/// Which close message, if any, is sent when the remote endpoint commits
+/// a `ProtocolViolation`, before the error is reported.
+///
+/// The default follows the RFC, see `ProtocolViolation::close_code`.
+#[derive(Debug, Clone, Copy)]
+pub enum ProtocolErrorPolicy {
+	/// Close with the code the RFC asks for.
+	Rfc,
+	/// Close with the same code whatever the violation, e.g. 1011.
+	Always(u16),
+	/// Don't send a close message, only report the error.
+	Silent,
+	/// Pick the close code for each violation, `None` to send no close message.
+	Custom(fn(ProtocolViolation) -> Option<u16>),
+}
+
+// deriving it needs `#[default]`, which older compilers don't know
+#[allow(clippy::derivable_impls)]
+impl Default for ProtocolErrorPolicy {
+	fn default() -> Self {
+		ProtocolErrorPolicy::Rfc
+	}
+}
+
+impl ProtocolErrorPolicy {
+	/// The code of the close message to send because of `violation`, if any.
+	pub fn close_code(self, violation: ProtocolViolation) -> Option<u16> {
+		match self {
+			ProtocolErrorPolicy::Rfc => Some(violation.close_code()),
+			ProtocolErrorPolicy::Always(code) => Some(code),
+			ProtocolErrorPolicy::Silent => None,
+			ProtocolErrorPolicy::Custom(pick) => pick(violation),
+		}
+	}
+}
@@ -6,13 +6,21 @@ use std::io::{self, Read, Write};
 /// Represents a stream that can be read from, and written to.
 /// This is an abstraction around readable and writable things to be able
 /// to speak websockets over ssl, tcp, unix sockets, etc.
+///
+/// Every type that is both `Read` and `Write` is a `Stream`, so it can be
+/// passed to `ClientBuilder::connect_on` as it is. `ReadWritePair` is only
+/// needed to combine a separate reader and writer.
 pub trait Stream: Read + Write {}
 impl<S> Stream for S where S: Read + Write {}
 
 /// If you would like to combine an input stream and an output stream into a single
 /// stream to talk websockets over then this is the struct for you!
 ///
-/// This is useful if you want to use different mediums for different directions.
+/// This is useful if you want to use different mediums for different directions,
+/// or to speak websockets over transports that already hand out separate send and
+/// receive halves (like the bidirectional streams of QUIC). Both the handshake
+/// (`IntoWs`, `ClientBuilder::connect_on`) and the framing can be driven over
+/// such a pair, and splitting the resulting client gives the two halves back.
 pub struct ReadWritePair<R, W>(pub R, pub W)
 where
 	R: Read,
@@ -70,6 +78,7 @@ pub mod r#async {
 	pub use super::ReadWritePair;
 	use futures::Poll;
 	use std::io::{self, Read, Write};
+	use std::ops::Deref;
 	pub use tokio_io::io::{ReadHalf, WriteHalf};
 	pub use tokio_io::{AsyncRead, AsyncWrite};
 	pub use tokio_tcp::TcpStream;
@@ -96,6 +105,38 @@ pub mod r#async {
 			self.1.shutdown()
 		}
 	}
+
+	/// The ability to access a borrow of an underlying async TcpStream,
+	/// e.g. to find out the address of the peer.
+	///
+	/// Async clients are framed streams, so the stream of an accepted
+	/// client is reached with `client.get_ref().as_tcp()`.
+	pub trait AsTcpStream {
+		/// Get a borrow of the TcpStream
+		fn as_tcp(&self) -> &TcpStream;
+	}
+
+	impl AsTcpStream for TcpStream {
+		fn as_tcp(&self) -> &TcpStream {
+			self
+		}
+	}
+
+	#[cfg(feature = "async-ssl")]
+	impl AsTcpStream for tokio_tls::TlsStream<TcpStream> {
+		fn as_tcp(&self) -> &TcpStream {
+			self.get_ref().get_ref()
+		}
+	}
+
+	impl<T> AsTcpStream for Box<T>
+	where
+		T: AsTcpStream + ?Sized,
+	{
+		fn as_tcp(&self) -> &TcpStream {
+			self.deref().as_tcp()
+		}
+	}
 }
 
 /// A collection of traits and implementations for synchronous streams.
@@ -122,7 +163,10 @@ pub mod sync {
 	/// `TcpStream` is an example. This trait marks this ability so one can split
 	/// up the client into two parts.
 	///
-	/// Notice however that this is not possible to do with SSL.
+	/// Notice however that this is not possible to do with SSL: a `TlsStream`
+	/// keeps a single session state for both directions. Use `TrySplit` in
+	/// generic code, and shut down one direction of an SSL connection through
+	/// the `TcpStream` underneath (see `AsTcpStream`).
 	pub trait Splittable {
 		/// The reading component of this type
 		type Reader: Read;
@@ -155,6 +199,71 @@ pub mod sync {
 		}
 	}
 
+	/// Attempt to split up a stream into separate reading and writing components,
+	/// for generic code that wants to split when possible and fall back to
+	/// using the whole stream otherwise.
+	///
+	/// Splittable streams give their components, while the others (like SSL streams)
+	/// give themselves back. Implement this for your own streams to use them
+	/// with `Client::try_split`.
+	pub trait TrySplit: Sized {
+		/// The reading component of this type
+		type Reader: Read;
+		/// The writing component of this type
+		type Writer: Write;
+
+		/// Split apart this type into a reading and writing component,
+		/// or give it back if that is not possible.
+		fn try_split(self) -> Result<(Self::Reader, Self::Writer), Self>;
+	}
+
+	impl<R, W> TrySplit for ReadWritePair<R, W>
+	where
+		R: Read,
+		W: Write,
+	{
+		type Reader = R;
+		type Writer = W;
+
+		fn try_split(self) -> Result<(R, W), Self> {
+			Ok((self.0, self.1))
+		}
+	}
+
+	impl TrySplit for TcpStream {
+		type Reader = TcpStream;
+		type Writer = TcpStream;
+
+		fn try_split(self) -> Result<(TcpStream, TcpStream), Self> {
+			match self.try_clone() {
+				Ok(s) => Ok((s, self)),
+				Err(_) => Err(self),
+			}
+		}
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	impl<S> TrySplit for TlsStream<S>
+	where
+		S: Read + Write,
+	{
+		type Reader = io::Empty;
+		type Writer = io::Sink;
+
+		fn try_split(self) -> Result<(io::Empty, io::Sink), Self> {
+			Err(self)
+		}
+	}
+
+	impl TrySplit for Box<dyn NetworkStream + Send> {
+		type Reader = io::Empty;
+		type Writer = io::Sink;
+
+		fn try_split(self) -> Result<(io::Empty, io::Sink), Self> {
+			Err(self)
+		}
+	}
+
 	/// The ability access a borrow to an underlying TcpStream,
 	/// so one can set options on the stream such as `nonblocking`.
 	pub trait AsTcpStream {
@@ -168,6 +277,12 @@ pub mod sync {
 		}
 	}
 
+	/// TLS streams set up by other code, e.g. after routing connections on
+	/// SNI, can be used as they are: `ClientBuilder::connect_on` and `IntoWs`
+	/// run the websocket handshake over them, and socket options, timeouts
+	/// and `shutdown` of the resulting client go to the `TcpStream` underneath.
+	///
+	/// Such clients can't be split, `try_split` gives them back.
 	#[cfg(feature = "sync-ssl")]
 	impl AsTcpStream for TlsStream<TcpStream> {
 		fn as_tcp(&self) -> &TcpStream {
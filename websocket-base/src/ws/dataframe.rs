@@ -25,22 +25,7 @@ pub trait DataFrame {
 	/// Get's the size of the entire dataframe in bytes,
 	/// i.e. header and payload.
 	fn frame_size(&self, masked: bool) -> usize {
-		// one byte for the opcode & reserved & fin
-		1
-        // depending on the size of the payload, add the right payload len bytes
-        + match self.size() {
-            s if s <= 125 => 1,
-            s if s <= 65535 => 3,
-            _ => 9,
-        }
-        // add the mask size if there is one
-        + if masked {
-            4
-        } else {
-            0
-        }
-        // finally add the payload len
-        + self.size()
+		dfh::header_len(self.size(), masked) + self.size()
 	}
 
 	/// Write the payload to a writer
@@ -51,24 +36,24 @@ pub trait DataFrame {
 
 	/// Writes a DataFrame to a Writer.
 	fn write_to(&self, writer: &mut dyn Write, mask: bool) -> WebSocketResult<()> {
-		let mut flags = dfh::DataFrameFlags::empty();
-		if self.is_last() {
-			flags.insert(dfh::DataFrameFlags::FIN);
-		}
-		{
-			let reserved = self.reserved();
-			if reserved[0] {
-				flags.insert(dfh::DataFrameFlags::RSV1);
-			}
-			if reserved[1] {
-				flags.insert(dfh::DataFrameFlags::RSV2);
-			}
-			if reserved[2] {
-				flags.insert(dfh::DataFrameFlags::RSV3);
-			}
-		}
-
 		let masking_key = if mask { Some(mask::gen_mask()) } else { None };
+		self.write_to_with_mask(writer, masking_key)
+	}
+
+	/// Writes a DataFrame to a Writer, masked with `masking_key` instead of
+	/// a random key, or unmasked with `None`.
+	///
+	/// Only meant for conformance tests and for reproducing the exact bytes
+	/// of a packet capture. Masking is there to keep intermediaries from
+	/// being confused by attacker chosen bytes, which a predictable key
+	/// defeats, so real clients have to use an unpredictable key for every
+	/// frame.
+	fn write_to_with_mask(
+		&self,
+		writer: &mut dyn Write,
+		masking_key: Option<[u8; 4]>,
+	) -> WebSocketResult<()> {
+		let flags = dfh::DataFrameFlags::new(self.is_last(), self.reserved());
 
 		let header = dfh::DataFrameHeader {
 			flags,
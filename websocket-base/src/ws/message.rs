@@ -2,8 +2,12 @@
 //!
 //! See the `ws` module documentation for more information.
 
+use crate::dataframe::Opcode;
+use crate::message::Type;
 use crate::result::WebSocketResult;
 use crate::ws::dataframe::DataFrame as DataFrameable;
+use crate::ws::util::header::{self as dfh, DataFrameFlags, DataFrameHeader};
+use crate::ws::util::mask;
 use std::io::Write;
 
 /// A trait for WebSocket messages
@@ -11,9 +15,84 @@ pub trait Message: Sized {
 	/// Writes this message to the writer
 	fn serialize(&self, _: &mut dyn Write, masked: bool) -> WebSocketResult<()>;
 
+	/// Writes this message to the writer, split up into fragments whose payload is
+	/// at most `max_frame_size` bytes long. Control messages are never fragmented,
+	/// and a `max_frame_size` of zero disables fragmentation.
+	///
+	/// The default implementation serializes the message once and then writes
+	/// the fragments from slices of the resulting payload. Messages which
+	/// serialize into more than one data frame are written as they are.
+	fn serialize_fragmented(
+		&self,
+		writer: &mut dyn Write,
+		masked: bool,
+		max_frame_size: usize,
+	) -> WebSocketResult<()> {
+		if max_frame_size == 0 || self.message_size(false) <= max_frame_size {
+			return self.serialize(writer, masked);
+		}
+
+		let mut buf = Vec::with_capacity(self.message_size(false));
+		self.serialize(&mut buf, false)?;
+		let mut payload = &buf[..];
+		let header = dfh::read_header(&mut payload)?;
+		if payload.len() as u64 != header.len
+			|| header.opcode >= 8
+			|| payload.len() <= max_frame_size
+		{
+			return self.serialize(writer, masked);
+		}
+
+		// fragments are put together in one buffer so that each of them
+		// goes out in a single write
+		let mut frame = Vec::with_capacity(dfh::header_len(max_frame_size, masked) + max_frame_size);
+		let mut opcode = header.opcode;
+		let mut reserved = header.flags - DataFrameFlags::FIN;
+		let mut chunks = payload.chunks(max_frame_size).peekable();
+		while let Some(chunk) = chunks.next() {
+			let mut flags = reserved;
+			flags.set(
+				DataFrameFlags::FIN,
+				header.flags.contains(DataFrameFlags::FIN) && chunks.peek().is_none(),
+			);
+			let masking_key = if masked { Some(mask::gen_mask()) } else { None };
+			frame.clear();
+			dfh::write_header(
+				&mut frame,
+				DataFrameHeader {
+					flags,
+					opcode,
+					mask: masking_key,
+					len: chunk.len() as u64,
+				},
+			)?;
+			match masking_key {
+				Some(key) => frame.extend(chunk.iter().zip(key.iter().cycle()).map(|(b, k)| b ^ k)),
+				None => frame.extend_from_slice(chunk),
+			}
+			writer.write_all(&frame)?;
+			opcode = Opcode::Continuation as u8;
+			reserved = DataFrameFlags::empty();
+		}
+		Ok(())
+	}
+
 	/// Returns how many bytes this message will take up
 	fn message_size(&self, masked: bool) -> usize;
 
+	/// The status code of a close message, with 1005 (No Status Received)
+	/// standing in for a close message without one. `None` for any other
+	/// message, which is all the default implementation knows about.
+	fn close_code(&self) -> Option<u16> {
+		None
+	}
+
+	/// The type of this message. `None` if it isn't known, which is all the
+	/// default implementation can say.
+	fn message_type(&self) -> Option<Type> {
+		None
+	}
+
 	/// Attempt to form a message from a series of data frames
 	fn from_dataframes<D: DataFrameable>(frames: Vec<D>) -> WebSocketResult<Self>;
 }
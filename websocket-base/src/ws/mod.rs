@@ -37,6 +37,9 @@
 //!
 //! To make life easier for a `Receiver`, several utility functions are provided which read
 //! various pieces of data from a Reader. These are found within the `util` module.
+//!
+//! Without the `std` feature only the `util` module is available.
+#[cfg(feature = "std")]
 pub use self::message::Message;
 
 #[cfg(feature = "sync")]
@@ -46,7 +49,9 @@ pub use self::receiver::{DataFrameIterator, MessageIterator};
 #[cfg(feature = "sync")]
 pub use self::sender::Sender;
 
+#[cfg(feature = "std")]
 pub mod dataframe;
+#[cfg(feature = "std")]
 pub mod message;
 pub mod util;
 
@@ -54,6 +54,7 @@ pub trait Receiver: Sized {
 		MessageIterator {
 			reader,
 			inner: self,
+			unread: None,
 		}
 	}
 }
@@ -89,6 +90,20 @@ where
 {
 	reader: &'a mut R,
 	inner: &'a mut Recv,
+	unread: Option<&'a mut Vec<Recv::M>>,
+}
+
+impl<'a, Recv, R> MessageIterator<'a, Recv, R>
+where
+	Recv: 'a + Receiver,
+	R: 'a + Read,
+{
+	/// Yields the messages in `unread` before reading any, taking them off
+	/// the end, so the next one goes last.
+	pub fn with_unread(mut self, unread: &'a mut Vec<Recv::M>) -> Self {
+		self.unread = Some(unread);
+		self
+	}
 }
 
 impl<'a, Recv, R> Iterator for MessageIterator<'a, Recv, R>
@@ -100,6 +115,9 @@ where
 
 	/// Get the next message from the receiver. Always returns `Some`.
 	fn next(&mut self) -> Option<WebSocketResult<Recv::M>> {
+		if let Some(message) = self.unread.as_mut().and_then(|unread| unread.pop()) {
+			return Some(Ok(message));
+		}
 		Some(self.inner.recv_message(self.reader))
 	}
 }
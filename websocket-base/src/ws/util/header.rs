@@ -1,7 +1,13 @@
 //! Utility functions for reading and writing data frame headers.
 
-use crate::result::{WebSocketError, WebSocketResult};
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use crate::result::ProtocolViolation;
+#[cfg(feature = "std")]
+use crate::result::WebSocketResult;
+#[cfg(feature = "std")]
+use crate::ws::util::write::IoWrite;
+use crate::ws::util::write::{EncodeError, FrameWrite};
+use byteorder::{BigEndian, ByteOrder};
+#[cfg(feature = "std")]
 use std::io::{Read, Write};
 
 bitflags! {
@@ -18,6 +24,18 @@ bitflags! {
 	}
 }
 
+impl DataFrameFlags {
+	/// The flags of a data frame with the given FIN and reserved bits.
+	pub fn new(finished: bool, reserved: &[bool; 3]) -> DataFrameFlags {
+		let mut flags = DataFrameFlags::empty();
+		flags.set(DataFrameFlags::FIN, finished);
+		flags.set(DataFrameFlags::RSV1, reserved[0]);
+		flags.set(DataFrameFlags::RSV2, reserved[1]);
+		flags.set(DataFrameFlags::RSV3, reserved[2]);
+		flags
+	}
+}
+
 /// Represents a data frame header.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct DataFrameHeader {
@@ -31,104 +49,215 @@ pub struct DataFrameHeader {
 	pub len: u64,
 }
 
+/// How many bytes the header of a data frame with a `payload_len` byte
+/// payload takes up: 2, 4 or 10 bytes depending on the length, plus 4 for the
+/// masking key if `masked`.
+pub fn header_len(payload_len: usize, masked: bool) -> usize {
+	let len = match payload_len {
+		0..=125 => 2,
+		126..=65535 => 4,
+		_ => 10,
+	};
+	if masked {
+		len + 4
+	} else {
+		len
+	}
+}
+
 /// Writes a data frame header.
+#[cfg(feature = "std")]
 pub fn write_header(writer: &mut dyn Write, header: DataFrameHeader) -> WebSocketResult<()> {
+	encode_header(&mut IoWrite(writer), header)?;
+	Ok(())
+}
+
+/// Writes a data frame header to a `FrameWrite`, which doesn't need `std`.
+///
+/// Nothing is written if the header is invalid.
+pub fn encode_header<W>(
+	writer: &mut W,
+	header: DataFrameHeader,
+) -> Result<(), EncodeError<W::Error>>
+where
+	W: FrameWrite + ?Sized,
+{
 	if header.opcode > 0xF {
-		return Err(WebSocketError::DataFrameError("Invalid data frame opcode"));
+		return Err(EncodeError::InvalidFrame("Invalid data frame opcode"));
 	}
 	if header.opcode >= 8 && header.len >= 126 {
-		return Err(WebSocketError::DataFrameError(
-			"Control frame length too long",
-		));
+		return Err(EncodeError::InvalidFrame("Control frame length too long"));
 	}
 
+	// At most 2 bytes, 8 bytes of 'Extended payload length' and 4 bytes of 'Masking-key'
+	let mut buf = [0u8; 14];
+
 	// Write 'FIN', 'RSV1', 'RSV2', 'RSV3' and 'opcode'
-	writer.write_u8((header.flags.bits) | header.opcode)?;
+	buf[0] = (header.flags.bits) | header.opcode;
 
-	writer.write_u8(
+	buf[1] =
 		// Write the 'MASK'
 		if header.mask.is_some() { 0x80 } else { 0x00 } |
 		// Write the 'Payload len'
 		if header.len <= 125 { header.len as u8 }
 		else if header.len <= 65535 { 126 }
-		else { 127 },
-	)?;
+		else { 127 };
+	let mut pos = 2;
 
 	// Write 'Extended payload length'
 	if header.len >= 126 && header.len <= 65535 {
-		writer.write_u16::<BigEndian>(header.len as u16)?;
+		buf[pos..pos + 2].copy_from_slice(&(header.len as u16).to_be_bytes());
+		pos += 2;
 	} else if header.len > 65535 {
-		writer.write_u64::<BigEndian>(header.len)?;
+		buf[pos..pos + 8].copy_from_slice(&header.len.to_be_bytes());
+		pos += 8;
 	}
 
 	// Write 'Masking-key'
 	if let Some(mask) = header.mask {
-		writer.write_all(&mask)?
+		buf[pos..pos + 4].copy_from_slice(&mask);
+		pos += 4;
 	}
 
-	Ok(())
+	writer.write_bytes(&buf[..pos]).map_err(EncodeError::Write)
 }
 
 /// Reads a data frame header.
+#[cfg(feature = "std")]
 pub fn read_header<R>(reader: &mut R) -> WebSocketResult<DataFrameHeader>
 where
 	R: Read,
 {
-	let byte0 = reader.read_u8()?;
-	let byte1 = reader.read_u8()?;
+	read_header_inner(reader, None)
+}
+
+/// Reads a data frame header, failing with `ProtocolViolation::MaskingViolation`
+/// as soon as the mask bit does not match `should_be_masked`: frames sent by
+/// clients must be masked while frames sent by servers must not.
+#[cfg(feature = "std")]
+pub fn read_header_with_mask<R>(
+	reader: &mut R,
+	should_be_masked: bool,
+) -> WebSocketResult<DataFrameHeader>
+where
+	R: Read,
+{
+	read_header_inner(reader, Some(should_be_masked))
+}
+
+#[cfg(feature = "std")]
+fn read_header_inner<R>(
+	reader: &mut R,
+	should_be_masked: Option<bool>,
+) -> WebSocketResult<DataFrameHeader>
+where
+	R: Read,
+{
+	let mut buf = [0u8; 14];
+	reader.read_exact(&mut buf[..2])?;
+	let len = match buf[1] & 0x7F {
+		126 => 4,
+		127 => 10,
+		_ => 2,
+	};
+	reader.read_exact(&mut buf[2..len])?;
+	if let Some((header, _)) = decode_header(&buf[..len], should_be_masked)? {
+		return Ok(header);
+	}
 
-	let flags = DataFrameFlags::from_bits_truncate(byte0);
-	let opcode = byte0 & 0x0F;
+	// Only the masking key is missing, the mask bit has been checked by now
+	reader.read_exact(&mut buf[len..len + 4])?;
+	match decode_header(&buf[..len + 4], should_be_masked)? {
+		Some((header, _)) => Ok(header),
+		None => unreachable!(),
+	}
+}
+
+/// Decodes a data frame header from the start of `bytes`, which doesn't need `std`.
+///
+/// Returns the header along with how many bytes it took up, or `None` if
+/// `bytes` doesn't hold all of it yet. `should_be_masked` is checked as in
+/// `read_header_with_mask`, pass `None` to accept both masked and unmasked frames.
+///
+/// ```rust
+/// # use websocket_base::ws::util::header::decode_header;
+/// let (header, len) = decode_header(&[0x81, 0x02, b'h', b'i'], Some(false)).unwrap().unwrap();
+/// assert_eq!((header.opcode, header.len, len), (1, 2, 2));
+///
+/// assert_eq!(decode_header(&[0x82, 0x7E, 0x01], None), Ok(None));
+/// ```
+pub fn decode_header(
+	bytes: &[u8],
+	should_be_masked: Option<bool>,
+) -> Result<Option<(DataFrameHeader, usize)>, ProtocolViolation> {
+	if bytes.len() < 2 {
+		return Ok(None);
+	}
+
+	let flags = DataFrameFlags::from_bits_truncate(bytes[0]);
+	let opcode = bytes[0] & 0x0F;
 
-	let len = match byte1 & 0x7F {
-		0..=125 => u64::from(byte1 & 0x7F),
+	let (len, mut pos) = match bytes[1] & 0x7F {
 		126 => {
-			let len = u64::from(reader.read_u16::<BigEndian>()?);
+			let ext = match bytes.get(2..4) {
+				Some(ext) => ext,
+				None => return Ok(None),
+			};
+			let len = u64::from(BigEndian::read_u16(ext));
 			if len <= 125 {
-				return Err(WebSocketError::DataFrameError("Invalid data frame length"));
+				return Err(ProtocolViolation::NonMinimalLength);
 			}
-			len
+			(len, 4)
 		}
 		127 => {
-			let len = reader.read_u64::<BigEndian>()?;
+			let ext = match bytes.get(2..10) {
+				Some(ext) => ext,
+				None => return Ok(None),
+			};
+			let len = BigEndian::read_u64(ext);
 			if len <= 65535 {
-				return Err(WebSocketError::DataFrameError("Invalid data frame length"));
+				return Err(ProtocolViolation::NonMinimalLength);
 			}
-			len
+			(len, 10)
 		}
-		_ => unreachable!(),
+		len => (u64::from(len), 2),
 	};
 
 	if opcode >= 8 {
 		if len >= 126 {
-			return Err(WebSocketError::DataFrameError(
-				"Control frame length too long",
-			));
+			return Err(ProtocolViolation::ControlFrameTooBig);
 		}
 		if !flags.contains(DataFrameFlags::FIN) {
-			return Err(WebSocketError::ProtocolError(
-				"Illegal fragmented control frame",
-			));
+			return Err(ProtocolViolation::FragmentedControl);
 		}
 	}
 
-	let mask = if byte1 & 0x80 == 0x80 {
-		Some([
-			reader.read_u8()?,
-			reader.read_u8()?,
-			reader.read_u8()?,
-			reader.read_u8()?,
-		])
+	let is_masked = bytes[1] & 0x80 == 0x80;
+	if should_be_masked == Some(!is_masked) {
+		return Err(ProtocolViolation::MaskingViolation);
+	}
+
+	let mask = if is_masked {
+		match bytes.get(pos..pos + 4) {
+			Some(key) => {
+				pos += 4;
+				Some([key[0], key[1], key[2], key[3]])
+			}
+			None => return Ok(None),
+		}
 	} else {
 		None
 	};
 
-	Ok(DataFrameHeader {
-		flags,
-		opcode,
-		mask,
-		len,
-	})
+	Ok(Some((
+		DataFrameHeader {
+			flags,
+			opcode,
+			mask,
+			len,
+		},
+		pos,
+	)))
 }
 
 #[cfg(all(feature = "nightly", test))]
@@ -1,16 +1,23 @@
 //! Utility functions for masking data frame payload data
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
 use rand;
+#[cfg(feature = "std")]
 use std::io::Result as IoResult;
+#[cfg(feature = "std")]
 use std::io::Write;
 
 /// Struct to pipe data into another writer,
 /// while masking the data being written
+#[cfg(feature = "std")]
 pub struct Masker<'w> {
 	key: [u8; 4],
 	pos: usize,
 	end: &'w mut dyn Write,
 }
 
+#[cfg(feature = "std")]
 impl<'w> Masker<'w> {
 	/// Create a new Masker with the key and the endpoint
 	/// to be writer to.
@@ -23,6 +30,7 @@ impl<'w> Masker<'w> {
 	}
 }
 
+#[cfg(feature = "std")]
 impl<'w> Write for Masker<'w> {
 	fn write(&mut self, data: &[u8]) -> IoResult<usize> {
 		let mut buf = Vec::with_capacity(data.len());
@@ -39,6 +47,7 @@ impl<'w> Write for Masker<'w> {
 }
 
 /// Generates a random masking key
+#[cfg(feature = "std")]
 pub fn gen_mask() -> [u8; 4] {
 	rand::random()
 }
@@ -2,14 +2,18 @@
 
 pub mod header;
 pub mod mask;
+pub mod write;
 
+#[cfg(feature = "std")]
 use std::str::from_utf8;
+#[cfg(feature = "std")]
 use std::str::Utf8Error;
 
 #[cfg(feature = "async")]
 use tokio_codec::{Framed, FramedParts};
 
 /// Transforms a u8 slice into an owned String
+#[cfg(feature = "std")]
 pub fn bytes_to_string(data: &[u8]) -> Result<String, Utf8Error> {
 	let utf8 = from_utf8(data)?;
 	Ok(utf8.to_string())
@@ -0,0 +1,148 @@
+//! A minimal stand-in for `std::io::Write`, so that data frames can be
+//! serialized without `std`.
+#[cfg(feature = "std")]
+use crate::result::WebSocketError;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::convert::Infallible;
+#[cfg(feature = "std")]
+use std::io;
+
+/// Something data frames can be serialized into.
+///
+/// `Vec<u8>` is one, whether or not the `std` feature is on. With it any
+/// `std::io::Write` can be used through `IoWrite`, any other sink (e.g. a UART
+/// driver) has to implement it.
+pub trait FrameWrite {
+	/// The error writing can fail with.
+	type Error;
+
+	/// Writes all of `bytes`, or fails.
+	fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+impl FrameWrite for Vec<u8> {
+	type Error = Infallible;
+
+	fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Infallible> {
+		self.extend_from_slice(bytes);
+		Ok(())
+	}
+}
+
+/// Serializes data frames into a `std::io::Write`.
+///
+/// ```rust
+/// # use websocket_base::dataframe::{DataFrame, Opcode};
+/// # use websocket_base::ws::util::write::IoWrite;
+/// let mut stream = std::io::Cursor::new(Vec::new());
+/// let frame = DataFrame::new(true, Opcode::Text, b"hi".to_vec());
+/// frame.encode(&mut IoWrite(&mut stream), None).unwrap();
+/// assert_eq!(stream.into_inner(), [0x81, 0x02, b'h', b'i']);
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct IoWrite<W>(pub W);
+
+#[cfg(feature = "std")]
+impl<W: io::Write> FrameWrite for IoWrite<W> {
+	type Error = io::Error;
+
+	fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+		self.0.write_all(bytes)
+	}
+}
+
+/// The ways serializing a data frame into a `FrameWrite` can fail.
+#[derive(Debug, PartialEq)]
+pub enum EncodeError<E> {
+	/// The frame can't be sent, e.g. a control frame with a payload over 125 bytes.
+	InvalidFrame(&'static str),
+	/// The `FrameWrite` failed.
+	Write(E),
+}
+
+#[cfg(feature = "std")]
+impl From<EncodeError<io::Error>> for WebSocketError {
+	fn from(err: EncodeError<io::Error>) -> WebSocketError {
+		match err {
+			EncodeError::InvalidFrame(msg) => WebSocketError::DataFrameError(msg),
+			EncodeError::Write(err) => err.into(),
+		}
+	}
+}
+
+// these run without `std` as well, except where noted
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::dataframe::{DataFrame, Opcode};
+	use crate::result::ProtocolViolation;
+	#[cfg(feature = "std")]
+	use crate::ws::dataframe::DataFrame as DataFrameable;
+	#[cfg(not(feature = "std"))]
+	use alloc::vec;
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn encode_matches_write_to() {
+		for &len in &[0, 125, 126, 1000, 70000] {
+			let frame = DataFrame::new(true, Opcode::Binary, vec![0xab; len]);
+			for &key in &[None, Some([1, 2, 3, 4])] {
+				let mut encoded = Vec::new();
+				frame.encode(&mut encoded, key).unwrap();
+				let mut written = Vec::new();
+				frame.write_to_with_mask(&mut written, key).unwrap();
+				assert_eq!(encoded, written);
+			}
+		}
+	}
+
+	#[test]
+	fn encode_rejects_long_control_frames() {
+		let frame = DataFrame::new(true, Opcode::Ping, vec![0; 126]);
+		let mut encoded = Vec::new();
+		match frame.encode(&mut encoded, None) {
+			Err(EncodeError::InvalidFrame(_)) => (),
+			other => panic!("unexpected result: {:?}", other),
+		}
+		assert!(encoded.is_empty());
+	}
+
+	#[test]
+	fn decode_reverses_encode() {
+		let frame = DataFrame::new(false, Opcode::Binary, vec![0xab; 70000]);
+		let mut encoded = Vec::new();
+		frame.encode(&mut encoded, Some([1, 2, 3, 4])).unwrap();
+		assert_eq!(DataFrame::decode(&encoded[..10], true), Ok(None));
+		assert_eq!(DataFrame::decode(&encoded[..70013], true), Ok(None));
+		assert_eq!(
+			DataFrame::decode(&encoded, true),
+			Ok(Some((frame, encoded.len())))
+		);
+		assert_eq!(
+			DataFrame::decode(&encoded, false),
+			Err(ProtocolViolation::MaskingViolation)
+		);
+	}
+
+	#[test]
+	fn encode_writes_header_and_masked_payload() {
+		let frame = DataFrame::new(true, Opcode::Text, b"hi".to_vec());
+		let mut encoded = Vec::new();
+		frame.encode(&mut encoded, Some([1, 2, 3, 4])).unwrap();
+		assert_eq!(encoded, [0x81, 0x82, 1, 2, 3, 4, b'h' ^ 1, b'i' ^ 2]);
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn decode_all_checks_lengths_before_allocating() {
+		// declares a payload of 2^63 - 1 bytes
+		let mut bytes = vec![0x82, 0x7F, 0x7F];
+		bytes.extend_from_slice(&[0xFF; 7]);
+		match DataFrame::decode_all(&bytes, false) {
+			Err(WebSocketError::DataFrameError(_)) => (),
+			other => panic!("unexpected result: {:?}", other),
+		}
+	}
+}